@@ -78,7 +78,9 @@ pub fn wrappers_fdw(attr: TokenStream, item: TokenStream) -> TokenStream {
 
             #[pg_extern]
             fn #fn_validator_ident(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) {
-                #ident::validator(options, catalog)
+                if let Err(err) = #ident::validator(options, catalog) {
+                    err.report();
+                }
             }
 
             #[pg_extern]
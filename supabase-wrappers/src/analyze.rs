@@ -0,0 +1,81 @@
+use pgx::{debug2, prelude::*, PgTupleDesc};
+use std::os::raw::c_int;
+
+use crate::error::ReportResult;
+use crate::instance;
+use crate::prelude::ForeignDataWrapper;
+use crate::utils;
+
+#[pg_guard]
+pub(super) extern "C" fn analyze_foreign_table<W: ForeignDataWrapper>(
+    _relation: pg_sys::Relation,
+    func: *mut pg_sys::AcquireSampleRowsFunc,
+    totalpages: *mut pg_sys::BlockNumber,
+) -> bool {
+    debug2!("---> analyze_foreign_table");
+    unsafe {
+        *func = Some(acquire_sample_rows::<W>);
+
+        // the foreign table has no local pages, just make it non-empty
+        *totalpages = 1;
+    }
+    true
+}
+
+#[pg_guard]
+extern "C" fn acquire_sample_rows<W: ForeignDataWrapper>(
+    relation: pg_sys::Relation,
+    elevel: c_int,
+    rows: *mut pg_sys::HeapTuple,
+    targrows: c_int,
+    totalrows: *mut f64,
+    totaldeadrows: *mut f64,
+) -> c_int {
+    debug2!("---> acquire_sample_rows");
+    unsafe {
+        let ftable_id = (*relation).rd_id;
+        let ftable = pg_sys::GetForeignTable(ftable_id);
+        let opts = utils::options_to_hashmap((*ftable).options);
+
+        let tup_desc = (*relation).rd_att;
+        let natts = (*tup_desc).natts as usize;
+        let columns: Vec<String> = PgTupleDesc::from_pg_copy(tup_desc)
+            .iter()
+            .filter(|a| !a.attisdropped)
+            .map(|a| pgx::name_data_to_str(&a.attname).to_string())
+            .collect();
+
+        let mut instance = instance::create_fdw_instance::<W>(ftable_id, pg_sys::InvalidOid);
+        let sample = instance
+            .sample_rows(&columns, targrows as usize, &opts)
+            .report_err();
+
+        // the tuples are allocated in the caller's memory context
+        let mut nrows = 0;
+        for row in sample.rows.into_iter().take(targrows as usize) {
+            let mut values = vec![pg_sys::Datum::from(0); natts];
+            let mut nulls = vec![true; natts];
+            utils::set_row_values(row, tup_desc, &mut values, &mut nulls);
+            *rows.add(nrows) =
+                pg_sys::heap_form_tuple(tup_desc, values.as_mut_ptr(), nulls.as_mut_ptr());
+            nrows += 1;
+        }
+
+        *totalrows = sample.total_rows.max(nrows as f64);
+        *totaldeadrows = 0.0;
+
+        let msg = format!(
+            "\"{}\": table contains {:.0} rows, {} rows in sample",
+            pgx::name_data_to_str(&(*(*relation).rd_rel).relname),
+            *totalrows,
+            nrows
+        );
+        if elevel >= pg_sys::INFO as c_int {
+            utils::report_info(&msg);
+        } else {
+            debug2!("{}", msg);
+        }
+
+        nrows as c_int
+    }
+}
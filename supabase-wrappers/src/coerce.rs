@@ -0,0 +1,169 @@
+use crate::error::{FdwError, FdwResult};
+use crate::interface::Cell;
+use pgx::pg_sys::panic::CaughtError;
+use pgx::{pg_sys, IntoDatum, PgSqlErrorCode, PgTryBuilder};
+use std::ffi::CStr;
+
+// type oid of the datum converted from a cell
+fn cell_type_oid(cell: &Cell) -> pg_sys::Oid {
+    match cell {
+        Cell::Bool(_) => pg_sys::BOOLOID,
+        Cell::I8(_) => pg_sys::CHAROID,
+        Cell::I16(_) => pg_sys::INT2OID,
+        Cell::F32(_) => pg_sys::FLOAT4OID,
+        Cell::I32(_) => pg_sys::INT4OID,
+        Cell::F64(_) => pg_sys::FLOAT8OID,
+        Cell::I64(_) => pg_sys::INT8OID,
+        Cell::Numeric(_) => pg_sys::NUMERICOID,
+        Cell::String(_) => pg_sys::TEXTOID,
+        Cell::Date(_) => pg_sys::DATEOID,
+        Cell::Time(_) => pg_sys::TIMEOID,
+        Cell::Timestamp(_) => pg_sys::TIMESTAMPOID,
+        Cell::Timestamptz(_) => pg_sys::TIMESTAMPTZOID,
+        Cell::Interval(_) => pg_sys::INTERVALOID,
+        Cell::Uuid(_) => pg_sys::UUIDOID,
+        Cell::Bytea(_) => pg_sys::BYTEAOID,
+        Cell::Json(_) => pg_sys::JSONBOID,
+        Cell::PlainJson(_) => pg_sys::JSONOID,
+        Cell::BoolArray(_) => pg_sys::BOOLARRAYOID,
+        Cell::I16Array(_) => pg_sys::INT2ARRAYOID,
+        Cell::I32Array(_) => pg_sys::INT4ARRAYOID,
+        Cell::I64Array(_) => pg_sys::INT8ARRAYOID,
+        Cell::F32Array(_) => pg_sys::FLOAT4ARRAYOID,
+        Cell::F64Array(_) => pg_sys::FLOAT8ARRAYOID,
+        Cell::StringArray(_) => pg_sys::TEXTARRAYOID,
+    }
+}
+
+fn int_value(cell: &Cell) -> Option<i64> {
+    match cell {
+        Cell::I8(v) => Some(*v as i64),
+        Cell::I16(v) => Some(*v as i64),
+        Cell::I32(v) => Some(*v as i64),
+        Cell::I64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+pub(crate) unsafe fn type_name(typoid: pg_sys::Oid) -> String {
+    CStr::from_ptr(pg_sys::format_type_be(typoid))
+        .to_string_lossy()
+        .into_owned()
+}
+
+unsafe fn type_error(
+    src_oid: pg_sys::Oid,
+    typoid: pg_sys::Oid,
+    col: &str,
+    detail: Option<&str>,
+) -> FdwError {
+    let err = FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+        format!(
+            "cannot convert {} value to type {} of column \"{}\"",
+            type_name(src_oid),
+            type_name(typoid),
+            col
+        ),
+    );
+    match detail {
+        Some(detail) => err.with_detail(detail),
+        None => err,
+    }
+}
+
+unsafe fn out_of_range(value: i64, typoid: pg_sys::Oid, col: &str) -> FdwError {
+    FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+        format!(
+            "value {} is out of range for type {} of column \"{}\"",
+            value,
+            type_name(typoid),
+            col
+        ),
+    )
+}
+
+// convert a cell to datum of the target column type
+//
+// The cell is converted directly if it is already of the column type, integers
+// are widened or narrowed with overflow check, and any other combination goes
+// through the output function of the cell type and the input function of the
+// column type, e.g. text -> date or json -> jsonb.
+pub(crate) unsafe fn cell_to_datum(
+    cell: Cell,
+    typoid: pg_sys::Oid,
+    typmod: i32,
+    col: &str,
+) -> FdwResult<pg_sys::Datum> {
+    let src_oid = cell_type_oid(&cell);
+    if src_oid == typoid {
+        return cell
+            .into_datum()
+            .ok_or_else(|| type_error(src_oid, typoid, col, None));
+    }
+
+    if let Some(v) = int_value(&cell) {
+        let datum = match typoid {
+            pg_sys::INT2OID => i16::try_from(v)
+                .map_err(|_| out_of_range(v, typoid, col))?
+                .into_datum(),
+            pg_sys::INT4OID => i32::try_from(v)
+                .map_err(|_| out_of_range(v, typoid, col))?
+                .into_datum(),
+            pg_sys::INT8OID => v.into_datum(),
+            pg_sys::FLOAT4OID => (v as f32).into_datum(),
+            pg_sys::FLOAT8OID => (v as f64).into_datum(),
+            _ => None,
+        };
+        if let Some(datum) = datum {
+            return Ok(datum);
+        }
+    }
+
+    match (&cell, typoid) {
+        (Cell::F32(v), pg_sys::FLOAT8OID) => return Ok(f64::from(*v).into_datum().unwrap()),
+        (Cell::F64(v), pg_sys::FLOAT4OID) => return Ok((*v as f32).into_datum().unwrap()),
+        _ => {}
+    }
+
+    // an array can only be converted to another array, and vice versa
+    let src_is_array = pg_sys::get_element_type(src_oid) != pg_sys::InvalidOid;
+    let tgt_is_array = pg_sys::get_element_type(typoid) != pg_sys::InvalidOid;
+    if src_is_array != tgt_is_array {
+        return Err(type_error(src_oid, typoid, col, None));
+    }
+
+    let datum = cell
+        .into_datum()
+        .ok_or_else(|| type_error(src_oid, typoid, col, None))?;
+    io_coerce(datum, src_oid, typoid, typmod, col)
+}
+
+// convert datum through the output function of its type and the input function
+// of the target type
+unsafe fn io_coerce(
+    datum: pg_sys::Datum,
+    src_oid: pg_sys::Oid,
+    typoid: pg_sys::Oid,
+    typmod: i32,
+    col: &str,
+) -> FdwResult<pg_sys::Datum> {
+    let mut out_fn = pg_sys::InvalidOid;
+    let mut is_varlena = false;
+    pg_sys::getTypeOutputInfo(src_oid, &mut out_fn, &mut is_varlena);
+    let value = pg_sys::OidOutputFunctionCall(out_fn, datum);
+
+    let mut in_fn = pg_sys::InvalidOid;
+    let mut io_param = pg_sys::InvalidOid;
+    pg_sys::getTypeInputInfo(typoid, &mut in_fn, &mut io_param);
+
+    PgTryBuilder::new(|| Ok(pg_sys::OidInputFunctionCall(in_fn, value, io_param, typmod)))
+        .catch_others(|e| match e {
+            CaughtError::PostgresError(report) => {
+                Err(type_error(src_oid, typoid, col, Some(report.message())))
+            }
+            _ => e.rethrow(),
+        })
+        .execute()
+}
@@ -0,0 +1,654 @@
+//! Provides SQL dialects to deparse query restrictions safely
+//!
+//! Foreign data wrappers for SQL databases can use a [`SqlDialect`] to
+//! deparse [`Qual`], [`QualExpr`], [`FieldExpr`], [`Sort`], [`Aggregate`] and [`Cell`] into
+//! the remote SQL, with identifiers quoted and literals escaped properly.
+//!
+//! ```rust,no_run
+//! # use supabase_wrappers::prelude::*;
+//! fn deparse(quals: &[Qual], table: &str) -> String {
+//!     let dialect = ClickHouseDialect;
+//!
+//!     // the quals which cannot be deparsed are checked by Postgres locally
+//!     let conds: Vec<String> = quals
+//!         .iter()
+//!         .filter_map(|q| dialect.deparse_qual(q))
+//!         .collect();
+//!     if conds.is_empty() {
+//!         format!("select * from {}", table)
+//!     } else {
+//!         format!("select * from {} where {}", table, conds.join(" and "))
+//!     }
+//! }
+//! ```
+
+use crate::interface::{
+    Aggregate, AggregateKind, Cell, ExprQual, FieldExpr, Qual, QualExpr, Sort, Value,
+};
+use pgx::{pg_sys, Date, Timestamp};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+// format date in ISO 8601, e.g. 2023-01-02, returns None if it is infinite
+fn iso_date(date: &Date) -> Option<String> {
+    if date.is_infinity() || date.is_neg_infinity() {
+        return None;
+    }
+    let mut buf = [0 as c_char; pg_sys::MAXDATELEN as usize * 2];
+    unsafe {
+        let mut tm: pg_sys::pg_tm = Default::default();
+        pg_sys::j2date(
+            date.to_julian_days(),
+            &mut tm.tm_year,
+            &mut tm.tm_mon,
+            &mut tm.tm_mday,
+        );
+        pg_sys::EncodeDateOnly(&mut tm, pg_sys::USE_XSD_DATES as _, buf.as_mut_ptr());
+        Some(CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned())
+    }
+}
+
+// format timestamp in ISO 8601, e.g. 2023-01-02T03:04:05.678, returns None
+// if it is infinite
+fn iso_timestamp(ts: &Timestamp) -> Option<String> {
+    if ts.is_infinity() || ts.is_neg_infinity() {
+        return None;
+    }
+    let mut buf = [0 as c_char; pg_sys::MAXDATELEN as usize * 2];
+    unsafe {
+        let mut tm = pg_sys::pg_tm {
+            tm_zone: std::ptr::null_mut(),
+            ..Default::default()
+        };
+        let mut fsec = 0 as pg_sys::fsec_t;
+        // no time zone is given, so the value is formatted as is in UTC
+        // rather than converted to the session time zone
+        if pg_sys::timestamp2tm(
+            i64::from(ts.clone()),
+            std::ptr::null_mut(),
+            &mut tm,
+            &mut fsec,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        ) != 0
+        {
+            return None;
+        }
+        pg_sys::EncodeDateTime(
+            &mut tm,
+            fsec,
+            false,
+            0,
+            std::ptr::null(),
+            pg_sys::USE_XSD_DATES as _,
+            buf.as_mut_ptr(),
+        );
+        Some(CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned())
+    }
+}
+
+// deparse a condition on `field`, with column values quoted by `quote`
+fn deparse_cond<D: SqlDialect + ?Sized>(
+    dialect: &D,
+    field: &str,
+    operator: &str,
+    value: &Value,
+    use_or: bool,
+    quote: impl Fn(&str) -> String,
+) -> Option<String> {
+    match value {
+        Value::Cell(cell) => match operator {
+            "is" | "is not" => match cell {
+                Cell::String(s) if s == "null" => Some(format!("{} {} null", field, operator)),
+                _ => None,
+            },
+            op => Some(dialect.binary_expr(
+                field,
+                dialect.map_operator(op)?,
+                &dialect.cell_literal(cell)?,
+            )),
+        },
+        Value::Array(cells) => {
+            // only `in` and `not in` list are supported
+            let op = match (operator, use_or) {
+                ("=", true) => "in",
+                ("<>", false) => "not in",
+                _ => return None,
+            };
+            let list = cells
+                .iter()
+                .map(|cell| dialect.cell_literal(cell))
+                .collect::<Option<Vec<String>>>()?;
+            Some(format!("{} {} ({})", field, op, list.join(", ")))
+        }
+        Value::Column(col) => {
+            Some(dialect.binary_expr(field, dialect.map_operator(operator)?, &quote(col)))
+        }
+        Value::Param(param) => {
+            let value = match &param.value {
+                Some(cell) => dialect.cell_literal(cell)?,
+                None => "null".to_string(),
+            };
+            Some(dialect.binary_expr(field, dialect.map_operator(operator)?, &value))
+        }
+    }
+}
+
+// deparse a qual, with column names quoted by `quote`
+fn deparse_qual_with<D: SqlDialect + ?Sized>(
+    dialect: &D,
+    qual: &Qual,
+    quote: impl Fn(&str) -> String,
+) -> Option<String> {
+    let field = quote(&qual.field);
+    deparse_cond(
+        dialect,
+        &field,
+        &qual.operator,
+        &qual.value,
+        qual.use_or,
+        quote,
+    )
+}
+
+// the unit and timestamp arguments of `date_trunc(unit, ts)`
+fn date_trunc_args(args: &[FieldExpr]) -> Option<(String, &FieldExpr)> {
+    match args {
+        [FieldExpr::Const(Cell::String(unit)), ts] => Some((unit.to_lowercase(), ts)),
+        _ => None,
+    }
+}
+
+/// SQL dialect of a remote database
+///
+/// All the methods have defaults following ANSI SQL, a dialect only needs to
+/// override what is different on the remote side. The `deparse_*` and
+/// `*_literal` methods return `None` if the remote side cannot express the
+/// item, in which case the item should not be pushed down.
+pub trait SqlDialect {
+    /// Quote an identifier, e.g. a column name
+    fn quote_identifier(&self, ident: &str) -> String {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+
+    /// Quote a column name qualified with table alias, e.g. `r1.id`
+    fn quote_qualified(&self, name: &str) -> String {
+        match name.split_once('.') {
+            Some((alias, col)) => format!(
+                "{}.{}",
+                self.quote_identifier(alias),
+                self.quote_identifier(col)
+            ),
+            None => self.quote_identifier(name),
+        }
+    }
+
+    /// Format a string literal
+    fn string_literal(&self, s: &str) -> String {
+        format!("'{}'", s.replace('\'', "''"))
+    }
+
+    /// Format a date literal from ISO 8601 date, e.g. `2023-01-02`
+    fn date_literal(&self, date: &str) -> String {
+        format!("date {}", self.string_literal(date))
+    }
+
+    /// Format a time literal, e.g. `03:04:05.678000`
+    fn time_literal(&self, time: &str) -> String {
+        format!("time {}", self.string_literal(time))
+    }
+
+    /// Format a timestamp literal from ISO 8601 timestamp without time zone,
+    /// e.g. `2023-01-02T03:04:05.678`
+    fn timestamp_literal(&self, ts: &str) -> String {
+        format!("timestamp {}", self.string_literal(ts))
+    }
+
+    /// Format a timestamp literal from ISO 8601 timestamp in UTC, e.g.
+    /// `2023-01-02T03:04:05.678+00:00`
+    fn timestamptz_literal(&self, ts: &str) -> String {
+        format!("timestamp with time zone {}", self.string_literal(ts))
+    }
+
+    /// Format an array literal from formatted elements
+    fn array_literal(&self, elems: &[String]) -> String {
+        format!("array[{}]", elems.join(", "))
+    }
+
+    /// Postgres operators which can be pushed down
+    fn supported_operators(&self) -> &[&str] {
+        &[
+            "=",
+            "<>",
+            "<",
+            "<=",
+            ">",
+            ">=",
+            "~~",
+            "!~~",
+            "is distinct from",
+            "is not distinct from",
+        ]
+    }
+
+    /// Map a Postgres operator to the remote operator, returns `None` if it
+    /// is not supported
+    fn map_operator<'a>(&self, op: &'a str) -> Option<&'a str> {
+        if !self.supported_operators().contains(&op) {
+            return None;
+        }
+        Some(match op {
+            "~~" => "like",
+            "!~~" => "not like",
+            "~~*" => "ilike",
+            "!~~*" => "not ilike",
+            _ => op,
+        })
+    }
+
+    /// Format a binary operator expression, `op` is the remote operator mapped
+    /// by [`SqlDialect::map_operator`]
+    fn binary_expr(&self, left: &str, op: &str, right: &str) -> String {
+        format!("{} {} {}", left, op, right)
+    }
+
+    /// Deparse a call of the Postgres built-in function, returns `None` if
+    /// the remote side has no equivalent function
+    fn deparse_function(&self, name: &str, args: &[FieldExpr]) -> Option<String> {
+        if !["lower", "upper", "abs"].contains(&name) {
+            return None;
+        }
+        let args = args
+            .iter()
+            .map(|arg| self.deparse_field_expr(arg))
+            .collect::<Option<Vec<String>>>()?;
+        Some(format!("{}({})", name, args.join(", ")))
+    }
+
+    /// Deparse a cast of the deparsed expression to the Postgres type, returns
+    /// `None` if the remote side has no equivalent type
+    fn deparse_cast(&self, expr: &str, type_name: &str) -> Option<String> {
+        let ty = match type_name {
+            "text" | "character varying" => "varchar",
+            "timestamp without time zone" => "timestamp",
+            "date" | "smallint" | "integer" | "bigint" | "numeric" | "real"
+            | "double precision" | "boolean" => type_name,
+            _ => return None,
+        };
+        Some(format!("cast({} as {})", expr, ty))
+    }
+
+    /// Deparse a [`FieldExpr`], returns `None` if any part of it cannot be
+    /// deparsed
+    fn deparse_field_expr(&self, expr: &FieldExpr) -> Option<String> {
+        match expr {
+            FieldExpr::Column(col) => Some(self.quote_identifier(col)),
+            FieldExpr::Const(cell) => self.cell_literal(cell),
+            FieldExpr::Func { name, args } => self.deparse_function(name, args),
+            FieldExpr::Cast { expr, type_name } => {
+                self.deparse_cast(&self.deparse_field_expr(expr)?, type_name)
+            }
+        }
+    }
+
+    /// Format a [`Cell`] as a literal
+    fn cell_literal(&self, cell: &Cell) -> Option<String> {
+        fn list<T>(
+            dialect: &(impl SqlDialect + ?Sized),
+            arr: &[Option<T>],
+            fmt_elem: impl Fn(&T) -> Option<String>,
+        ) -> Option<String> {
+            let elems = arr
+                .iter()
+                .map(|e| match e {
+                    Some(v) => fmt_elem(v),
+                    None => Some("null".to_string()),
+                })
+                .collect::<Option<Vec<String>>>()?;
+            Some(dialect.array_literal(&elems))
+        }
+        let finite = |v: f64| {
+            if v.is_finite() {
+                Some(v.to_string())
+            } else {
+                None
+            }
+        };
+
+        match cell {
+            Cell::Bool(v) => Some(v.to_string()),
+            Cell::I8(v) => Some(v.to_string()),
+            Cell::I16(v) => Some(v.to_string()),
+            Cell::I32(v) => Some(v.to_string()),
+            Cell::I64(v) => Some(v.to_string()),
+            Cell::F32(v) => finite(*v as f64),
+            Cell::F64(v) => finite(*v),
+            Cell::Numeric(v) => {
+                let s = v.to_string();
+                s.parse::<f64>().ok().filter(|f| f.is_finite()).map(|_| s)
+            }
+            Cell::String(v) => Some(self.string_literal(v)),
+            Cell::Date(v) => iso_date(v).map(|d| self.date_literal(&d)),
+            Cell::Time(v) => {
+                let (h, m, s, micro) = v.clone().to_hms_micro();
+                Some(self.time_literal(&format!("{:02}:{:02}:{:02}.{:06}", h, m, s, micro)))
+            }
+            Cell::Timestamp(v) => iso_timestamp(v).map(|ts| self.timestamp_literal(&ts)),
+            Cell::Timestamptz(v) => iso_timestamp(&Timestamp::from(v.clone()))
+                .map(|ts| self.timestamptz_literal(&format!("{}+00:00", ts))),
+            Cell::Uuid(v) => Some(self.string_literal(&v.to_string())),
+            Cell::Interval(_) | Cell::Bytea(_) | Cell::Json(_) | Cell::PlainJson(_) => None,
+            Cell::BoolArray(v) => list(self, v, |e| Some(e.to_string())),
+            Cell::I16Array(v) => list(self, v, |e| Some(e.to_string())),
+            Cell::I32Array(v) => list(self, v, |e| Some(e.to_string())),
+            Cell::I64Array(v) => list(self, v, |e| Some(e.to_string())),
+            Cell::F32Array(v) => list(self, v, |e| finite(*e as f64)),
+            Cell::F64Array(v) => list(self, v, |e| finite(*e)),
+            Cell::StringArray(v) => list(self, v, |e| Some(self.string_literal(e))),
+        }
+    }
+
+    /// Deparse a [`Qual`] on a single table
+    fn deparse_qual(&self, qual: &Qual) -> Option<String> {
+        deparse_qual_with(self, qual, |col| self.quote_identifier(col))
+    }
+
+    /// Deparse a [`Qual`] whose columns are qualified with table alias, e.g.
+    /// a join condition in [`Join`](crate::interface::Join)
+    fn deparse_join_qual(&self, qual: &Qual) -> Option<String> {
+        deparse_qual_with(self, qual, |col| self.quote_qualified(col))
+    }
+
+    /// Deparse an [`ExprQual`] on a single table
+    fn deparse_expr_qual(&self, qual: &ExprQual) -> Option<String> {
+        let field = self.deparse_field_expr(&qual.field)?;
+        deparse_cond(self, &field, &qual.operator, &qual.value, false, |col| {
+            self.quote_identifier(col)
+        })
+    }
+
+    /// Deparse a [`QualExpr`] on a single table, returns `None` if any of its
+    /// leaves cannot be deparsed
+    fn deparse_qual_expr(&self, expr: &QualExpr) -> Option<String> {
+        let join = |exprs: &[QualExpr], sep: &str| {
+            let conds = exprs
+                .iter()
+                .map(|e| self.deparse_qual_expr(e))
+                .collect::<Option<Vec<String>>>()?;
+            Some(format!("({})", conds.join(sep)))
+        };
+        match expr {
+            QualExpr::And(exprs) => join(exprs, " and "),
+            QualExpr::Or(exprs) => join(exprs, " or "),
+            QualExpr::Not(expr) => Some(format!("not ({})", self.deparse_qual_expr(expr)?)),
+            QualExpr::Leaf(qual) => self.deparse_qual(qual),
+            QualExpr::Expr(qual) => self.deparse_expr_qual(qual),
+        }
+    }
+
+    /// Deparse all the quals combined with `and`, returns `None` if any of
+    /// them cannot be deparsed
+    fn deparse_quals(&self, quals: &[Qual]) -> Option<String> {
+        let conds = quals
+            .iter()
+            .map(|q| self.deparse_qual(q))
+            .collect::<Option<Vec<String>>>()?;
+        Some(conds.join(" and "))
+    }
+
+    /// Deparse a [`Sort`] to `ORDER BY` item, collation is not included
+    fn deparse_sort(&self, sort: &Sort) -> String {
+        format!(
+            "{}{} nulls {}",
+            self.quote_identifier(&sort.field),
+            if sort.reversed { " desc" } else { "" },
+            if sort.nulls_first { "first" } else { "last" }
+        )
+    }
+
+    /// Deparse an [`Aggregate`] function call
+    fn deparse_aggregate(&self, aggregate: &Aggregate) -> String {
+        match &aggregate.field {
+            Some(field) if aggregate.distinct => format!(
+                "{}(distinct {})",
+                aggregate.kind,
+                self.quote_identifier(field)
+            ),
+            Some(field) => format!("{}({})", aggregate.kind, self.quote_identifier(field)),
+            None => format!("{}(*)", aggregate.kind),
+        }
+    }
+}
+
+/// ANSI SQL dialect
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnsiDialect;
+
+impl SqlDialect for AnsiDialect {}
+
+/// [ClickHouse](https://clickhouse.com/docs/en/sql-reference/syntax) SQL dialect
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClickHouseDialect;
+
+impl SqlDialect for ClickHouseDialect {
+    fn quote_identifier(&self, ident: &str) -> String {
+        format!("`{}`", ident.replace('\\', "\\\\").replace('`', "\\`"))
+    }
+
+    fn string_literal(&self, s: &str) -> String {
+        format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+
+    fn date_literal(&self, date: &str) -> String {
+        format!("toDate({})", self.string_literal(date))
+    }
+
+    // ClickHouse has no time type
+    fn time_literal(&self, time: &str) -> String {
+        self.string_literal(time)
+    }
+
+    fn timestamp_literal(&self, ts: &str) -> String {
+        format!("parseDateTime64BestEffort({}, 6)", self.string_literal(ts))
+    }
+
+    fn timestamptz_literal(&self, ts: &str) -> String {
+        format!("parseDateTime64BestEffort({}, 6)", self.string_literal(ts))
+    }
+
+    fn array_literal(&self, elems: &[String]) -> String {
+        format!("[{}]", elems.join(", "))
+    }
+
+    fn supported_operators(&self) -> &[&str] {
+        &[
+            "=",
+            "<>",
+            "<",
+            "<=",
+            ">",
+            ">=",
+            "~~",
+            "!~~",
+            "~~*",
+            "!~~*",
+            "is distinct from",
+            "is not distinct from",
+        ]
+    }
+
+    // ClickHouse only supports `is [not] distinct from` in join conditions
+    fn binary_expr(&self, left: &str, op: &str, right: &str) -> String {
+        let not_distinct = format!(
+            "(coalesce({l} = {r}, false) or ({l} is null and {r} is null))",
+            l = left,
+            r = right
+        );
+        match op {
+            "is not distinct from" => not_distinct,
+            "is distinct from" => format!("not {}", not_distinct),
+            _ => format!("{} {} {}", left, op, right),
+        }
+    }
+
+    fn deparse_function(&self, name: &str, args: &[FieldExpr]) -> Option<String> {
+        if name == "date_trunc" {
+            let (unit, ts) = date_trunc_args(args)?;
+            // week starts on Sunday in ClickHouse, but on Monday in Postgres
+            if ![
+                "second", "minute", "hour", "day", "month", "quarter", "year",
+            ]
+            .contains(&unit.as_str())
+            {
+                return None;
+            }
+            return Some(format!(
+                "dateTrunc({}, {})",
+                self.string_literal(&unit),
+                self.deparse_field_expr(ts)?
+            ));
+        }
+
+        let func = match name {
+            "lower" => "lowerUTF8",
+            "upper" => "upperUTF8",
+            "abs" => "abs",
+            _ => return None,
+        };
+        let args = args
+            .iter()
+            .map(|arg| self.deparse_field_expr(arg))
+            .collect::<Option<Vec<String>>>()?;
+        Some(format!("{}({})", func, args.join(", ")))
+    }
+
+    // ClickHouse returns the default value of the type rather than null for
+    // sum, min and max over no rows, and its result types differ from
+    // Postgres, e.g. sum(Float32) is Float64, so the `OrNull` combinator is
+    // used and the result is cast to the Postgres result type
+    fn deparse_aggregate(&self, aggregate: &Aggregate) -> String {
+        let field = match &aggregate.field {
+            Some(field) => self.quote_identifier(field),
+            None => return format!("{}(*)", aggregate.kind),
+        };
+        let args = if aggregate.distinct {
+            format!("distinct {}", field)
+        } else {
+            field
+        };
+        let call = match aggregate.kind {
+            AggregateKind::Count => format!("count({})", args),
+            kind => format!("{}OrNull({})", kind, args),
+        };
+        let ch_type = match aggregate.type_name.as_str() {
+            "smallint" => "Int16",
+            "integer" => "Int32",
+            "bigint" => "Int64",
+            "real" => "Float32",
+            "double precision" => "Float64",
+            "text" => "String",
+            "timestamp without time zone" => "DateTime64(6)",
+            _ => return call,
+        };
+        if aggregate.kind == AggregateKind::Count {
+            format!("CAST({} AS {})", call, ch_type)
+        } else {
+            format!("CAST({} AS Nullable({}))", call, ch_type)
+        }
+    }
+
+    fn deparse_cast(&self, expr: &str, type_name: &str) -> Option<String> {
+        let func = match type_name {
+            "text" | "character varying" => "toString",
+            "date" => "toDate",
+            "smallint" => "toInt16",
+            "integer" => "toInt32",
+            "bigint" => "toInt64",
+            "real" => "toFloat32",
+            "double precision" => "toFloat64",
+            "timestamp without time zone" => return Some(format!("toDateTime64({}, 6)", expr)),
+            _ => return None,
+        };
+        Some(format!("{}({})", func, expr))
+    }
+}
+
+/// [BigQuery](https://cloud.google.com/bigquery/docs/reference/standard-sql/lexical)
+/// Google Standard SQL dialect
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BigQueryDialect;
+
+impl SqlDialect for BigQueryDialect {
+    fn quote_identifier(&self, ident: &str) -> String {
+        format!("`{}`", ident.replace('\\', "\\\\").replace('`', "\\`"))
+    }
+
+    fn string_literal(&self, s: &str) -> String {
+        let mut ret = String::with_capacity(s.len() + 2);
+        ret.push('\'');
+        for c in s.chars() {
+            match c {
+                '\\' => ret.push_str("\\\\"),
+                '\'' => ret.push_str("\\'"),
+                '\n' => ret.push_str("\\n"),
+                '\r' => ret.push_str("\\r"),
+                _ => ret.push(c),
+            }
+        }
+        ret.push('\'');
+        ret
+    }
+
+    // string literal is coerced to either DATETIME or TIMESTAMP column
+    fn timestamp_literal(&self, ts: &str) -> String {
+        self.string_literal(ts)
+    }
+
+    fn timestamptz_literal(&self, ts: &str) -> String {
+        format!("timestamp {}", self.string_literal(ts))
+    }
+
+    fn array_literal(&self, elems: &[String]) -> String {
+        format!("[{}]", elems.join(", "))
+    }
+
+    fn deparse_function(&self, name: &str, args: &[FieldExpr]) -> Option<String> {
+        if name != "date_trunc" {
+            let args = args
+                .iter()
+                .map(|arg| self.deparse_field_expr(arg))
+                .collect::<Option<Vec<String>>>()?;
+            return match name {
+                "lower" | "upper" | "abs" => Some(format!("{}({})", name, args.join(", "))),
+                _ => None,
+            };
+        }
+
+        // both DATETIME and TIMESTAMP columns can be cast to DATETIME
+        let (unit, ts) = date_trunc_args(args)?;
+        let part = match unit.as_str() {
+            "second" | "minute" | "hour" | "day" | "month" | "quarter" | "year" => unit.clone(),
+            "week" => "isoweek".to_string(),
+            _ => return None,
+        };
+        Some(format!(
+            "datetime_trunc(cast({} as datetime), {})",
+            self.deparse_field_expr(ts)?,
+            part
+        ))
+    }
+
+    fn deparse_cast(&self, expr: &str, type_name: &str) -> Option<String> {
+        let ty = match type_name {
+            "text" | "character varying" => "string",
+            "date" => "date",
+            "smallint" | "integer" | "bigint" => "int64",
+            "real" | "double precision" => "float64",
+            "numeric" => "numeric",
+            "boolean" => "bool",
+            "timestamp without time zone" => "datetime",
+            _ => return None,
+        };
+        Some(format!("cast({} as {})", expr, ty))
+    }
+}
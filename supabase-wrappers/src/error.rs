@@ -0,0 +1,277 @@
+//! Provides the error type returned by foreign data wrapper callbacks
+//!
+
+use pgx::pg_sys::{self, AsPgCStr};
+use pgx::{PgMemoryContexts, PgSqlErrorCode};
+use std::ffi::CString;
+use std::fmt;
+
+/// Result type of [`ForeignDataWrapper`](crate::interface::ForeignDataWrapper) callbacks
+pub type FdwResult<T> = Result<T, FdwError>;
+
+/// Error returned by [`ForeignDataWrapper`](crate::interface::ForeignDataWrapper) callbacks
+///
+/// The framework reports the error to Postgres with `ereport`, which aborts
+/// the current transaction. A plain message can be converted into an error
+/// with `ERRCODE_FDW_ERROR`, so the wrappers can use `?` like below,
+///
+/// ```rust,no_run
+/// # use supabase_wrappers::prelude::*;
+/// # fn fetch(url: &str) -> Result<String, std::io::Error> { Ok(url.to_string()) }
+/// fn get_body(url: &str) -> FdwResult<String> {
+///     let body = fetch(url).map_err(|err| format!("request failed: {}", err))?;
+///     Ok(body)
+/// }
+/// ```
+///
+/// or build a structured error,
+///
+/// ```rust,no_run
+/// # use supabase_wrappers::prelude::*;
+/// # use pgx::PgSqlErrorCode;
+/// let err = FdwError::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, "request failed")
+///     .with_detail("rate limit exceeded")
+///     .with_hint("retry later")
+///     .with_remote_status(429);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdwError {
+    /// SQLSTATE of the error
+    pub code: PgSqlErrorCode,
+
+    /// primary error message
+    pub message: String,
+
+    /// optional detail message
+    pub detail: Option<String>,
+
+    /// optional hint message
+    pub hint: Option<String>,
+
+    /// optional status code returned by the foreign source, e.g. HTTP status
+    pub remote_status: Option<u16>,
+}
+
+impl FdwError {
+    /// Create an error with SQLSTATE and message
+    pub fn new<S: Into<String>>(code: PgSqlErrorCode, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            detail: None,
+            hint: None,
+            remote_status: None,
+        }
+    }
+
+    /// Set the detail message
+    pub fn with_detail<S: Into<String>>(mut self, detail: S) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Set the hint message
+    pub fn with_hint<S: Into<String>>(mut self, hint: S) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    /// Set the status code returned by the foreign source
+    pub fn with_remote_status(mut self, status: u16) -> Self {
+        self.remote_status = Some(status);
+        self
+    }
+
+    // detail message reported to Postgres, which includes the remote status
+    fn report_detail(&self) -> Option<String> {
+        let mut details = Vec::new();
+        if let Some(detail) = &self.detail {
+            details.push(detail.clone());
+        }
+        if let Some(status) = self.remote_status {
+            details.push(format!("remote status: {}", status));
+        }
+        if details.is_empty() {
+            None
+        } else {
+            Some(details.join("\n"))
+        }
+    }
+
+    /// Report the error to Postgres, which aborts the current transaction
+    #[track_caller]
+    pub fn report(self) -> ! {
+        // pgx error report has no hint, so the error is raised by Postgres
+        // directly. The guarded `errfinish()` turns it into a panic, which is
+        // re-thrown as it is once the Rust frames have been unwound.
+        let location = std::panic::Location::caller();
+        let message = errfmt(&self.message);
+        let detail = self.report_detail().map(|detail| errfmt(&detail));
+        let hint = self.hint.as_ref().map(|hint| errfmt(hint));
+        unsafe {
+            // the location is referenced by the error data, so it must live
+            // in the error context
+            let (file, funcname) = PgMemoryContexts::ErrorContext
+                .switch_to(|_| (location.file().as_pg_cstr(), "Wrappers".as_pg_cstr()));
+            let lineno = location.line() as _;
+
+            #[cfg(any(feature = "pg11", feature = "pg12"))]
+            let started =
+                pg_sys::errstart(pg_sys::ERROR as _, file, lineno, funcname, std::ptr::null());
+            #[cfg(not(any(feature = "pg11", feature = "pg12")))]
+            let started = pg_sys::errstart(pg_sys::ERROR as _, std::ptr::null());
+
+            if started {
+                pg_sys::errcode(self.code as _);
+                pg_sys::errmsg_internal(message.as_ptr());
+                if let Some(detail) = &detail {
+                    pg_sys::errdetail_internal(detail.as_ptr());
+                }
+                if let Some(hint) = &hint {
+                    pg_sys::errhint(hint.as_ptr());
+                }
+
+                #[cfg(any(feature = "pg11", feature = "pg12"))]
+                pg_sys::errfinish(0);
+                #[cfg(not(any(feature = "pg11", feature = "pg12")))]
+                pg_sys::errfinish(file, lineno, funcname);
+            }
+        }
+        unreachable!()
+    }
+}
+
+// make a message to be used as the format string of ereport
+fn errfmt(message: &str) -> CString {
+    let fmt = message.replace('%', "%%").replace('\0', "");
+    CString::new(fmt).unwrap_or_default()
+}
+
+impl fmt::Display for FdwError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(detail) = &self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        if let Some(status) = self.remote_status {
+            write!(f, " (remote status: {})", status)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FdwError {}
+
+impl From<String> for FdwError {
+    fn from(message: String) -> Self {
+        Self::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, message)
+    }
+}
+
+impl From<&str> for FdwError {
+    fn from(message: &str) -> Self {
+        Self::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, message)
+    }
+}
+
+// report the error of a callback result to Postgres, or return the value
+pub(crate) trait ReportResult<T> {
+    fn report_err(self) -> T;
+}
+
+impl<T> ReportResult<T> for FdwResult<T> {
+    fn report_err(self) -> T {
+        match self {
+            Ok(value) => value,
+            Err(err) => err.report(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let err = FdwError::new(PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE, "bad type");
+        assert_eq!(err.code, PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE);
+        assert_eq!(err.message, "bad type");
+        assert_eq!(err.detail, None);
+        assert_eq!(err.hint, None);
+        assert_eq!(err.remote_status, None);
+    }
+
+    #[test]
+    fn test_builder() {
+        let err = FdwError::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, "request failed")
+            .with_detail("rate limit exceeded")
+            .with_hint("retry later")
+            .with_remote_status(429);
+        assert_eq!(err.detail.as_deref(), Some("rate limit exceeded"));
+        assert_eq!(err.hint.as_deref(), Some("retry later"));
+        assert_eq!(err.remote_status, Some(429));
+    }
+
+    #[test]
+    fn test_from() {
+        let expected = FdwError::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, "request failed");
+        assert_eq!(FdwError::from("request failed"), expected);
+        assert_eq!(FdwError::from("request failed".to_string()), expected);
+
+        fn fails() -> FdwResult<()> {
+            Err(format!("request failed: {}", 42))?
+        }
+        assert_eq!(
+            fails(),
+            Err(FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_ERROR,
+                "request failed: 42"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let err = FdwError::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, "request failed");
+        assert_eq!(err.to_string(), "request failed");
+
+        let err = err.with_hint("retry later");
+        assert_eq!(err.to_string(), "request failed");
+
+        let err = err.with_detail("rate limit exceeded");
+        assert_eq!(err.to_string(), "request failed: rate limit exceeded");
+
+        let err = err.with_remote_status(429);
+        assert_eq!(
+            err.to_string(),
+            "request failed: rate limit exceeded (remote status: 429)"
+        );
+    }
+
+    #[test]
+    fn test_report_detail() {
+        let err = FdwError::new(PgSqlErrorCode::ERRCODE_FDW_ERROR, "request failed");
+        assert_eq!(err.report_detail(), None);
+
+        // hint is not a part of the detail
+        let err = err.with_hint("retry later");
+        assert_eq!(err.report_detail(), None);
+
+        let err = err.with_remote_status(429);
+        assert_eq!(err.report_detail().as_deref(), Some("remote status: 429"));
+
+        let err = err.with_detail("rate limit exceeded");
+        assert_eq!(
+            err.report_detail().as_deref(),
+            Some("rate limit exceeded\nremote status: 429")
+        );
+    }
+
+    #[test]
+    fn test_errfmt() {
+        assert_eq!(errfmt("request failed").to_str(), Ok("request failed"));
+        assert_eq!(errfmt("100% done").to_str(), Ok("100%% done"));
+        assert_eq!(errfmt("%s\0%d").to_str(), Ok("%%s%%d"));
+    }
+}
@@ -0,0 +1,64 @@
+use pgx::{debug2, memcxt::PgMemoryContexts, prelude::*, PgList};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+use crate::error::ReportResult;
+use crate::instance;
+use crate::interface::{ImportForeignSchemaStmt, ImportSchemaType};
+use crate::prelude::ForeignDataWrapper;
+use crate::utils;
+
+// convert Postgres's IMPORT FOREIGN SCHEMA statement node to Rust struct
+unsafe fn to_import_stmt(stmt: *mut pg_sys::ImportForeignSchemaStmt) -> ImportForeignSchemaStmt {
+    let list_type = match (*stmt).list_type {
+        pg_sys::ImportForeignSchemaType_FDW_IMPORT_SCHEMA_LIMIT_TO => ImportSchemaType::LimitTo,
+        pg_sys::ImportForeignSchemaType_FDW_IMPORT_SCHEMA_EXCEPT => ImportSchemaType::Except,
+        _ => ImportSchemaType::All,
+    };
+
+    let tables: PgList<pg_sys::RangeVar> = PgList::from_pg((*stmt).table_list);
+    let table_list = tables
+        .iter_ptr()
+        .map(|rv| CStr::from_ptr((*rv).relname).to_str().unwrap().to_owned())
+        .collect();
+
+    ImportForeignSchemaStmt {
+        server_name: CStr::from_ptr((*stmt).server_name)
+            .to_str()
+            .unwrap()
+            .to_owned(),
+        remote_schema: CStr::from_ptr((*stmt).remote_schema)
+            .to_str()
+            .unwrap()
+            .to_owned(),
+        local_schema: CStr::from_ptr((*stmt).local_schema)
+            .to_str()
+            .unwrap()
+            .to_owned(),
+        list_type,
+        table_list,
+        options: utils::options_to_hashmap((*stmt).options),
+    }
+}
+
+#[pg_guard]
+pub(super) extern "C" fn import_foreign_schema<W: ForeignDataWrapper>(
+    stmt: *mut pg_sys::ImportForeignSchemaStmt,
+    server_oid: pg_sys::Oid,
+) -> *mut pg_sys::List {
+    debug2!("---> import_foreign_schema");
+    unsafe {
+        let import_stmt = to_import_stmt(stmt);
+
+        let mut instance =
+            instance::create_fdw_instance_from_server_id::<W>(server_oid, pg_sys::InvalidOid);
+        let cmds = instance.import_foreign_schema(&import_stmt).report_err();
+
+        // the command list must be allocated in the caller's memory context
+        let mut ret: PgList<c_char> = PgList::new();
+        for cmd in cmds {
+            ret.push(PgMemoryContexts::CurrentMemoryContext.pstrdup(&cmd));
+        }
+        ret.into_pg()
+    }
+}
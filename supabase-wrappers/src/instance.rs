@@ -1,12 +1,373 @@
 use crate::prelude::*;
-use pgx::prelude::*;
+use pgx::{debug2, prelude::*};
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::ops::{Deref, DerefMut};
+use std::os::raw::{c_int, c_void};
 
+use super::error::ReportResult;
+use super::polyfill;
 use super::utils;
+use super::xact::{self, XactEvent};
+
+// a fdw instance cached in current backend, so its runtime, client and
+// connections can be reused by later queries
+struct CachedInstance {
+    type_id: TypeId,
+    server_id: pg_sys::Oid,
+    user_id: pg_sys::Oid,
+
+    // user mapping the instance is created with, InvalidOid if none
+    um_id: pg_sys::Oid,
+
+    // syscache hash values of the foreign server and user mapping, to match
+    // the invalidations
+    server_hash: u32,
+    um_hash: u32,
+
+    instance: Box<dyn Any>,
+
+    // whether the instance is used by the current transaction, it can be
+    // shared by the planner states of the transaction
+    in_use: bool,
+
+    // whether the instance is taken by a running scan or modify, which has
+    // exclusive use of it until the scan or modify ends
+    active: bool,
+
+    // the server or user mapping options are changed, the instance will be
+    // dropped once it is not in use
+    invalid: bool,
+}
+
+impl CachedInstance {
+    // address of the instance, which is not moved while it is cached
+    fn as_ptr(&self) -> *mut c_void {
+        self.instance.as_ref() as *const dyn Any as *mut c_void
+    }
+}
+
+thread_local! {
+    static CALLBACKS_REGISTERED: Cell<bool> = const { Cell::new(false) };
+    static INSTANCES: RefCell<Vec<CachedInstance>> = const { RefCell::new(Vec::new()) };
+}
+
+// fdw instance taken from the cache, it can only be used in the current
+// transaction and is given back to the cache when the transaction ends
+pub(super) struct FdwInstance<W: ForeignDataWrapper> {
+    instance: *mut W,
+
+    // foreign server and user the instance is created for
+    server_id: pg_sys::Oid,
+    user_id: pg_sys::Oid,
+
+    // whether the instance is taken by activate()
+    active: bool,
+}
+
+impl<W: ForeignDataWrapper> FdwInstance<W> {
+    pub(super) fn as_ptr(&self) -> *mut c_void {
+        self.instance as _
+    }
+
+    // whether the instance is taken by activate(), otherwise it may be shared
+    // with the other planner states
+    pub(super) fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // take the instance for a scan or modify to run, another instance is
+    // used instead if it is taken by a running one already
+    pub(super) unsafe fn activate(&mut self) {
+        if self.active {
+            return;
+        }
+        let ptr = self.as_ptr();
+        let taken = INSTANCES.with(|i| {
+            let mut instances = i.borrow_mut();
+            match instances.iter_mut().find(|inst| inst.as_ptr() == ptr) {
+                Some(inst) if !inst.active => {
+                    inst.active = true;
+                    false
+                }
+                _ => true,
+            }
+        });
+        if taken {
+            self.instance = take_instance::<W>(self.server_id, self.user_id, true) as _;
+        }
+        self.active = true;
+    }
+
+    // release the instance taken by activate(), so it can be reused by the
+    // later scans and modifies in the same transaction
+    pub(super) fn deactivate(&mut self) {
+        if !self.active {
+            return;
+        }
+        let ptr = self.as_ptr();
+        INSTANCES.with(|i| {
+            if let Some(inst) = i.borrow_mut().iter_mut().find(|inst| inst.as_ptr() == ptr) {
+                inst.active = false;
+            }
+        });
+        self.active = false;
+    }
+}
+
+impl<W: ForeignDataWrapper> Deref for FdwInstance<W> {
+    type Target = W;
+
+    fn deref(&self) -> &W {
+        unsafe { &*self.instance }
+    }
+}
+
+impl<W: ForeignDataWrapper> DerefMut for FdwInstance<W> {
+    fn deref_mut(&mut self) -> &mut W {
+        unsafe { &mut *self.instance }
+    }
+}
+
+// drop the instances which are invalidated or duplicated, only one idle
+// instance is kept for each server and user
+fn purge_instances() {
+    let purged: Vec<CachedInstance> = INSTANCES.with(|i| {
+        let mut instances = i.borrow_mut();
+        let mut kept: Vec<CachedInstance> = Vec::new();
+        let mut purged = Vec::new();
+        for inst in instances.drain(..) {
+            let is_dup = !inst.in_use
+                && kept.iter().any(|k| {
+                    !k.in_use
+                        && k.type_id == inst.type_id
+                        && k.server_id == inst.server_id
+                        && k.user_id == inst.user_id
+                });
+            if inst.in_use || !(inst.invalid || is_dup) {
+                kept.push(inst);
+            } else {
+                purged.push(inst);
+            }
+        }
+        *instances = kept;
+        purged
+    });
+
+    // the instances are dropped without holding the cache
+    drop(purged);
+}
+
+// give the instance back to the cache at the end of transaction, in case it
+// is still taken by a scan or modify which is not ended, e.g. on error
+unsafe fn xact_handler(instance: *mut c_void, event: XactEvent) {
+    if matches!(event, XactEvent::Commit | XactEvent::Abort) {
+        INSTANCES.with(|i| {
+            if let Some(inst) = i
+                .borrow_mut()
+                .iter_mut()
+                .find(|inst| inst.as_ptr() == instance)
+            {
+                inst.in_use = false;
+                inst.active = false;
+            }
+        });
+    }
+}
+
+#[pg_guard]
+extern "C" fn inval_callback(_arg: pg_sys::Datum, cacheid: c_int, hashvalue: u32) {
+    debug2!("---> inval_callback");
+
+    INSTANCES.with(|i| {
+        for inst in i.borrow_mut().iter_mut() {
+            let hash = if cacheid == pg_sys::SysCacheIdentifier_USERMAPPINGOID as c_int {
+                inst.um_hash
+            } else {
+                inst.server_hash
+            };
+            if hashvalue == 0 || hash == hashvalue {
+                inst.invalid = true;
+            }
+        }
+    });
+}
+
+unsafe fn syscache_hash(cacheid: pg_sys::SysCacheIdentifier, oid: pg_sys::Oid) -> u32 {
+    pg_sys::GetSysCacheHashValue(
+        cacheid as c_int,
+        oid.into(),
+        pg_sys::Datum::from(0),
+        pg_sys::Datum::from(0),
+        pg_sys::Datum::from(0),
+    )
+}
+
+// get the user mapping of a server for the user, or the PUBLIC one if the
+// user has none, returns None if neither exists
+unsafe fn get_user_mapping(
+    fserver_id: pg_sys::Oid,
+    user_id: pg_sys::Oid,
+) -> Option<*mut pg_sys::UserMapping> {
+    let exists = |user_id: pg_sys::Oid| {
+        pg_sys::SearchSysCacheExists(
+            pg_sys::SysCacheIdentifier_USERMAPPINGUSERSERVER as c_int,
+            user_id.into(),
+            fserver_id.into(),
+            pg_sys::Datum::from(0),
+            pg_sys::Datum::from(0),
+        )
+    };
+    if exists(user_id) || exists(pg_sys::InvalidOid) {
+        Some(pg_sys::GetUserMapping(user_id, fserver_id))
+    } else {
+        None
+    }
+}
+
+unsafe fn register_callbacks() {
+    // syscache callbacks are registered once per backend
+    if !CALLBACKS_REGISTERED.with(|r| r.replace(true)) {
+        polyfill::CacheRegisterSyscacheCallback(
+            pg_sys::SysCacheIdentifier_FOREIGNSERVEROID as c_int,
+            Some(inval_callback),
+            pg_sys::Datum::from(0),
+        );
+        polyfill::CacheRegisterSyscacheCallback(
+            pg_sys::SysCacheIdentifier_USERMAPPINGOID as c_int,
+            Some(inval_callback),
+            pg_sys::Datum::from(0),
+        );
+    }
+}
+
+// take a fdw instance of the server and user from the cache, or create it if
+// there is none. The instances used by the current transaction are shared,
+// except the ones taken by a running scan or modify.
+unsafe fn take_instance<W: ForeignDataWrapper>(
+    fserver_id: pg_sys::Oid,
+    user_id: pg_sys::Oid,
+    active: bool,
+) -> *mut c_void {
+    register_callbacks();
+
+    let type_id = TypeId::of::<W>();
+    let um = get_user_mapping(fserver_id, user_id);
+    let um_id = um.map(|um| (*um).umid).unwrap_or(pg_sys::InvalidOid);
+
+    // the user may have got another user mapping, e.g. a new one is created
+    // to replace the PUBLIC one
+    INSTANCES.with(|i| {
+        for inst in i.borrow_mut().iter_mut().filter(|inst| {
+            inst.type_id == type_id
+                && inst.server_id == fserver_id
+                && inst.user_id == user_id
+                && inst.um_id != um_id
+        }) {
+            inst.invalid = true;
+        }
+    });
+    purge_instances();
+
+    let cached = INSTANCES.with(|i| {
+        i.borrow_mut()
+            .iter_mut()
+            .find(|inst| {
+                inst.type_id == type_id
+                    && inst.server_id == fserver_id
+                    && inst.user_id == user_id
+                    && !inst.active
+                    && !inst.invalid
+            })
+            .map(|inst| {
+                inst.in_use = true;
+                inst.active = active;
+                inst.as_ptr()
+            })
+    });
+
+    let ptr = match cached {
+        Some(ptr) => ptr,
+        None => {
+            let fserver = pg_sys::GetForeignServer(fserver_id);
+            let mut opts = utils::options_to_hashmap((*fserver).options);
+            if let Some(um) = um {
+                opts.extend(utils::options_to_hashmap((*um).options));
+            }
+            let instance: Box<dyn Any> = Box::new(W::new(&opts).report_err());
+            let inst = CachedInstance {
+                type_id,
+                server_id: fserver_id,
+                user_id,
+                um_id,
+                server_hash: syscache_hash(pg_sys::SysCacheIdentifier_FOREIGNSERVEROID, fserver_id),
+                um_hash: match um {
+                    Some(_) => syscache_hash(pg_sys::SysCacheIdentifier_USERMAPPINGOID, um_id),
+                    None => 0,
+                },
+                instance,
+                in_use: true,
+                active,
+                invalid: false,
+            };
+            let ptr = inst.as_ptr();
+            INSTANCES.with(|i| i.borrow_mut().push(inst));
+            ptr
+        }
+    };
+
+    xact::register_participant(ptr, xact_handler);
+
+    ptr
+}
+
+// create a fdw instance from its foreign server id, with the server options
+// overridden by the user mapping options of `user_id`, which is the user to
+// check access as, e.g. owner of the view referring the foreign table, or
+// InvalidOid for the current user. An instance cached for the server and user
+// is reused if any.
+pub(super) unsafe fn create_fdw_instance_from_server_id<W: ForeignDataWrapper>(
+    fserver_id: pg_sys::Oid,
+    user_id: pg_sys::Oid,
+) -> FdwInstance<W> {
+    let user_id = if user_id == pg_sys::InvalidOid {
+        pg_sys::GetUserId()
+    } else {
+        user_id
+    };
+    FdwInstance {
+        instance: take_instance::<W>(fserver_id, user_id, false) as _,
+        server_id: fserver_id,
+        user_id,
+        active: false,
+    }
+}
 
 // create a fdw instance
-pub(super) unsafe fn create_fdw_instance<W: ForeignDataWrapper>(ftable_id: pg_sys::Oid) -> W {
+pub(super) unsafe fn create_fdw_instance<W: ForeignDataWrapper>(
+    ftable_id: pg_sys::Oid,
+    user_id: pg_sys::Oid,
+) -> FdwInstance<W> {
     let ftable = pg_sys::GetForeignTable(ftable_id);
-    let fserver = pg_sys::GetForeignServer((*ftable).serverid);
-    let fserver_opts = utils::options_to_hashmap((*fserver).options);
-    W::new(&fserver_opts)
+    create_fdw_instance_from_server_id((*ftable).serverid, user_id)
+}
+
+// drop the cached instances of a foreign server, the instances in use are
+// dropped once the current transaction ends, returns true if any instance is
+// found
+pub(super) fn disconnect(fserver_id: pg_sys::Oid) -> bool {
+    let found = INSTANCES.with(|i| {
+        let mut found = false;
+        for inst in i
+            .borrow_mut()
+            .iter_mut()
+            .filter(|inst| inst.server_id == fserver_id)
+        {
+            inst.invalid = true;
+            found = true;
+        }
+        found
+    });
+    purge_instances();
+    found
 }
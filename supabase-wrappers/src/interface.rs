@@ -1,22 +1,30 @@
 //! Provides interface types and trait to develop Postgres foreign data wrapper
 //!
 
+use crate::deparse::{AnsiDialect, SqlDialect};
+use crate::error::FdwResult;
 use crate::FdwRoutine;
-use pgx::prelude::{Date, Timestamp};
+use pgx::prelude::{Date, Time, Timestamp, TimestampWithTimeZone};
 use pgx::{
+    direct_function_call,
     pg_sys::{self, Datum, Oid},
-    AllocatedByRust, FromDatum, IntoDatum, JsonB, PgBuiltInOids, PgOid,
+    AllocatedByRust, AnyNumeric, FromDatum, IntoDatum, Json, JsonB, PgBuiltInOids, PgOid, Uuid,
 };
 use std::collections::HashMap;
+use std::ffi::CStr;
 use std::fmt;
+use std::io::Write;
 use std::iter::Zip;
 use std::mem;
+use std::os::unix::net::UnixStream;
 use std::slice::Iter;
+use std::time::Duration;
 
 // fdw system catalog oids
 // https://doxygen.postgresql.org/pg__foreign__data__wrapper_8h.html
 // https://doxygen.postgresql.org/pg__foreign__server_8h.html
 // https://doxygen.postgresql.org/pg__foreign__table_8h.html
+// https://doxygen.postgresql.org/pg__user__mapping_8h.html
 
 /// Constant can be used in [validator](ForeignDataWrapper::validator)
 pub const FOREIGN_DATA_WRAPPER_RELATION_ID: pg_sys::Oid = 2328;
@@ -27,6 +35,56 @@ pub const FOREIGN_SERVER_RELATION_ID: pg_sys::Oid = 1417;
 /// Constant can be used in [validator](ForeignDataWrapper::validator)
 pub const FOREIGN_TABLE_RELATION_ID: pg_sys::Oid = 3118;
 
+/// Constant can be used in [validator](ForeignDataWrapper::validator)
+pub const USER_MAPPING_RELATION_ID: pg_sys::Oid = 1418;
+
+/// A time interval, same as Postgres `interval` type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Interval {
+    /// months part
+    pub months: i32,
+
+    /// days part
+    pub days: i32,
+
+    /// microseconds part
+    pub micros: i64,
+}
+
+impl FromDatum for Interval {
+    unsafe fn from_polymorphic_datum(datum: Datum, is_null: bool, _typoid: Oid) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        if is_null {
+            return None;
+        }
+        let interval = datum.cast_mut_ptr::<pg_sys::Interval>();
+        Some(Interval {
+            months: (*interval).month,
+            days: (*interval).day,
+            micros: (*interval).time,
+        })
+    }
+}
+
+impl IntoDatum for Interval {
+    fn into_datum(self) -> Option<Datum> {
+        unsafe {
+            let interval =
+                pg_sys::palloc(mem::size_of::<pg_sys::Interval>()) as *mut pg_sys::Interval;
+            (*interval).month = self.months;
+            (*interval).day = self.days;
+            (*interval).time = self.micros;
+            Some(Datum::from(interval))
+        }
+    }
+
+    fn type_oid() -> Oid {
+        pg_sys::INTERVALOID
+    }
+}
+
 /// A data cell in a data row
 #[derive(Debug)]
 pub enum Cell {
@@ -37,10 +95,24 @@ pub enum Cell {
     I32(i32),
     F64(f64),
     I64(i64),
+    Numeric(AnyNumeric),
     String(String),
     Date(Date),
+    Time(Time),
     Timestamp(Timestamp),
+    Timestamptz(TimestampWithTimeZone),
+    Interval(Interval),
+    Uuid(Uuid),
+    Bytea(Vec<u8>),
     Json(JsonB),
+    PlainJson(Json),
+    BoolArray(Vec<Option<bool>>),
+    I16Array(Vec<Option<i16>>),
+    I32Array(Vec<Option<i32>>),
+    I64Array(Vec<Option<i64>>),
+    F32Array(Vec<Option<f32>>),
+    F64Array(Vec<Option<f64>>),
+    StringArray(Vec<Option<String>>),
 }
 
 impl Clone for Cell {
@@ -53,14 +125,54 @@ impl Clone for Cell {
             Cell::I32(v) => Cell::I32(*v),
             Cell::F64(v) => Cell::F64(*v),
             Cell::I64(v) => Cell::I64(*v),
+            Cell::Numeric(v) => Cell::Numeric(v.clone()),
             Cell::String(v) => Cell::String(v.clone()),
             Cell::Date(v) => Cell::Date(v.clone()),
+            Cell::Time(v) => Cell::Time(v.clone()),
             Cell::Timestamp(v) => Cell::Timestamp(v.clone()),
+            Cell::Timestamptz(v) => Cell::Timestamptz(v.clone()),
+            Cell::Interval(v) => Cell::Interval(*v),
+            Cell::Uuid(v) => Cell::Uuid(*v),
+            Cell::Bytea(v) => Cell::Bytea(v.clone()),
             Cell::Json(v) => Cell::Json(JsonB(v.0.clone())),
+            Cell::PlainJson(v) => Cell::PlainJson(Json(v.0.clone())),
+            Cell::BoolArray(v) => Cell::BoolArray(v.clone()),
+            Cell::I16Array(v) => Cell::I16Array(v.clone()),
+            Cell::I32Array(v) => Cell::I32Array(v.clone()),
+            Cell::I64Array(v) => Cell::I64Array(v.clone()),
+            Cell::F32Array(v) => Cell::F32Array(v.clone()),
+            Cell::F64Array(v) => Cell::F64Array(v.clone()),
+            Cell::StringArray(v) => Cell::StringArray(v.clone()),
         }
     }
 }
 
+// format a datum to string using its type output function
+fn datum_out(out_fn: unsafe fn(pg_sys::FunctionCallInfo) -> Datum, datum: Option<Datum>) -> String {
+    unsafe { direct_function_call::<&CStr>(out_fn, vec![datum]) }
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// format array elements as a list, e.g. [1, null, 3]
+fn write_array<T>(
+    f: &mut fmt::Formatter<'_>,
+    arr: &[Option<T>],
+    fmt_elem: impl Fn(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+) -> fmt::Result {
+    write!(f, "[")?;
+    for (i, elem) in arr.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        match elem {
+            Some(v) => fmt_elem(f, v)?,
+            None => write!(f, "null")?,
+        }
+    }
+    write!(f, "]")
+}
+
 impl fmt::Display for Cell {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -71,10 +183,38 @@ impl fmt::Display for Cell {
             Cell::I32(v) => write!(f, "{}", v),
             Cell::F64(v) => write!(f, "{}", v),
             Cell::I64(v) => write!(f, "{}", v),
+            Cell::Numeric(v) => write!(f, "{}", v),
             Cell::String(v) => write!(f, "'{}'", v),
             Cell::Date(v) => write!(f, "{:?}", v),
+            Cell::Time(v) => write!(
+                f,
+                "'{}'",
+                datum_out(pg_sys::time_out, v.clone().into_datum())
+            ),
             Cell::Timestamp(v) => write!(f, "{:?}", v),
+            Cell::Timestamptz(v) => write!(
+                f,
+                "'{}'",
+                datum_out(pg_sys::timestamptz_out, v.clone().into_datum())
+            ),
+            Cell::Interval(v) => write!(f, "'{}'", datum_out(pg_sys::interval_out, v.into_datum())),
+            Cell::Uuid(v) => write!(f, "'{}'", v),
+            Cell::Bytea(v) => {
+                write!(f, "'\\x")?;
+                for b in v {
+                    write!(f, "{:02x}", b)?;
+                }
+                write!(f, "'")
+            }
             Cell::Json(v) => write!(f, "{:?}", v),
+            Cell::PlainJson(v) => write!(f, "{:?}", v),
+            Cell::BoolArray(v) => write_array(f, v, |f, e| write!(f, "{}", e)),
+            Cell::I16Array(v) => write_array(f, v, |f, e| write!(f, "{}", e)),
+            Cell::I32Array(v) => write_array(f, v, |f, e| write!(f, "{}", e)),
+            Cell::I64Array(v) => write_array(f, v, |f, e| write!(f, "{}", e)),
+            Cell::F32Array(v) => write_array(f, v, |f, e| write!(f, "{}", e)),
+            Cell::F64Array(v) => write_array(f, v, |f, e| write!(f, "{}", e)),
+            Cell::StringArray(v) => write_array(f, v, |f, e| write!(f, "'{}'", e)),
         }
     }
 }
@@ -89,10 +229,24 @@ impl IntoDatum for Cell {
             Cell::I32(v) => v.into_datum(),
             Cell::F64(v) => v.into_datum(),
             Cell::I64(v) => v.into_datum(),
+            Cell::Numeric(v) => v.into_datum(),
             Cell::String(v) => v.into_datum(),
             Cell::Date(v) => v.into_datum(),
+            Cell::Time(v) => v.into_datum(),
             Cell::Timestamp(v) => v.into_datum(),
+            Cell::Timestamptz(v) => v.into_datum(),
+            Cell::Interval(v) => v.into_datum(),
+            Cell::Uuid(v) => v.into_datum(),
+            Cell::Bytea(v) => v.into_datum(),
             Cell::Json(v) => v.into_datum(),
+            Cell::PlainJson(v) => v.into_datum(),
+            Cell::BoolArray(v) => v.into_datum(),
+            Cell::I16Array(v) => v.into_datum(),
+            Cell::I32Array(v) => v.into_datum(),
+            Cell::I64Array(v) => v.into_datum(),
+            Cell::F32Array(v) => v.into_datum(),
+            Cell::F64Array(v) => v.into_datum(),
+            Cell::StringArray(v) => v.into_datum(),
         }
     }
 
@@ -132,18 +286,64 @@ impl FromDatum for Cell {
             PgOid::BuiltIn(PgBuiltInOids::INT8OID) => {
                 Some(Cell::I64(i64::from_datum(datum, false).unwrap()))
             }
-            PgOid::BuiltIn(PgBuiltInOids::TEXTOID) => {
-                Some(Cell::String(String::from_datum(datum, false).unwrap()))
+            PgOid::BuiltIn(PgBuiltInOids::NUMERICOID) => {
+                Some(Cell::Numeric(AnyNumeric::from_datum(datum, false).unwrap()))
             }
+            PgOid::BuiltIn(
+                PgBuiltInOids::TEXTOID | PgBuiltInOids::VARCHAROID | PgBuiltInOids::BPCHAROID,
+            ) => Some(Cell::String(String::from_datum(datum, false).unwrap())),
             PgOid::BuiltIn(PgBuiltInOids::DATEOID) => {
                 Some(Cell::Date(Date::from_datum(datum, false).unwrap()))
             }
+            PgOid::BuiltIn(PgBuiltInOids::TIMEOID) => {
+                Some(Cell::Time(Time::from_datum(datum, false).unwrap()))
+            }
             PgOid::BuiltIn(PgBuiltInOids::TIMESTAMPOID) => Some(Cell::Timestamp(
                 Timestamp::from_datum(datum, false).unwrap(),
             )),
+            PgOid::BuiltIn(PgBuiltInOids::TIMESTAMPTZOID) => Some(Cell::Timestamptz(
+                TimestampWithTimeZone::from_datum(datum, false).unwrap(),
+            )),
+            PgOid::BuiltIn(PgBuiltInOids::INTERVALOID) => {
+                Some(Cell::Interval(Interval::from_datum(datum, false).unwrap()))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::UUIDOID) => {
+                Some(Cell::Uuid(Uuid::from_datum(datum, false).unwrap()))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BYTEAOID) => {
+                Some(Cell::Bytea(Vec::<u8>::from_datum(datum, false).unwrap()))
+            }
             PgOid::BuiltIn(PgBuiltInOids::JSONBOID) => {
                 Some(Cell::Json(JsonB::from_datum(datum, false).unwrap()))
             }
+            PgOid::BuiltIn(PgBuiltInOids::JSONOID) => {
+                Some(Cell::PlainJson(Json::from_datum(datum, false).unwrap()))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BOOLARRAYOID) => {
+                Vec::<Option<bool>>::from_polymorphic_datum(datum, false, typoid)
+                    .map(Cell::BoolArray)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::INT2ARRAYOID) => {
+                Vec::<Option<i16>>::from_polymorphic_datum(datum, false, typoid).map(Cell::I16Array)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::INT4ARRAYOID) => {
+                Vec::<Option<i32>>::from_polymorphic_datum(datum, false, typoid).map(Cell::I32Array)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::INT8ARRAYOID) => {
+                Vec::<Option<i64>>::from_polymorphic_datum(datum, false, typoid).map(Cell::I64Array)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::FLOAT4ARRAYOID) => {
+                Vec::<Option<f32>>::from_polymorphic_datum(datum, false, typoid).map(Cell::F32Array)
+            }
+            PgOid::BuiltIn(PgBuiltInOids::FLOAT8ARRAYOID) => {
+                Vec::<Option<f64>>::from_polymorphic_datum(datum, false, typoid).map(Cell::F64Array)
+            }
+            PgOid::BuiltIn(
+                PgBuiltInOids::TEXTARRAYOID
+                | PgBuiltInOids::VARCHARARRAYOID
+                | PgBuiltInOids::BPCHARARRAYOID,
+            ) => Vec::<Option<String>>::from_polymorphic_datum(datum, false, typoid)
+                .map(Cell::StringArray),
             _ => None,
         }
     }
@@ -204,11 +404,38 @@ impl Row {
     }
 }
 
-/// A restiction value used in [`Qual`], either a [`Cell`] or an array of [`Cell`]
+/// A query parameter whose value is only known at execution time
+///
+/// It is either a column of the outer relation in a parameterized nested-loop
+/// join, or a parameter of a prepared statement or sub query.
+///
+/// ## Examples
+///
+/// ```sql
+/// select * from local_tbl l join foreign_tbl f on f.customer = l.id;
+/// -- [Qual { field: "customer", operator: "=", value: Param(Param { id: 0, type_oid: 25, value: Some(String("cus_1")) }), use_or: false }]
+/// ```
+#[derive(Debug, Clone)]
+pub struct Param {
+    /// index of the parameter in the foreign scan
+    pub id: usize,
+
+    /// type oid of the parameter value
+    pub type_oid: pg_sys::Oid,
+
+    /// the evaluated value, it is `None` during planning or if the value is
+    /// null, and is re-evaluated each time the scan restarts
+    pub value: Option<Cell>,
+}
+
+/// A restiction value used in [`Qual`], either a [`Cell`], an array of [`Cell`],
+/// a column of another table in a [`Join`] or a query [`Param`]
 #[derive(Debug, Clone)]
 pub enum Value {
     Cell(Cell),
     Array(Vec<Cell>),
+    Column(String),
+    Param(Param),
 }
 
 /// Query restrictions, a.k.a conditions in `WHERE` clause
@@ -239,6 +466,19 @@ pub enum Value {
 /// ```
 ///
 /// ```sql
+/// where col is distinct from 'foo'
+/// -- [Qual { field: "col", operator: "is distinct from", value: Cell(String("foo")), use_or: false }]
+/// ```
+///
+/// ```sql
+/// where id between 1 and 5;
+/// -- [
+/// --   Qual { field: "id", operator: ">=", value: Cell(I32(1)), use_or: false },
+/// --   Qual { field: "id", operator: "<=", value: Cell(I32(5)), use_or: false }
+/// -- ]
+/// ```
+///
+/// ```sql
 /// where id > 1 and col = 'foo';
 /// -- [
 /// --   Qual { field: "id", operator: ">", value: Cell(I32(1)), use_or: false },
@@ -254,24 +494,160 @@ pub struct Qual {
 }
 
 impl Qual {
+    /// Deparse to SQL in [`AnsiDialect`], returns an empty string if it
+    /// cannot be deparsed
+    ///
+    /// Use [`SqlDialect::deparse_qual`] to deparse for other remote databases.
     pub fn deparse(&self) -> String {
-        if self.use_or {
-            "".to_string()
-        } else {
-            match &self.value {
-                Value::Cell(cell) => match self.operator.as_str() {
-                    "is" | "is not" => match cell {
-                        Cell::String(cell) if cell == "null" => {
-                            format!("{} {} null", self.field, self.operator)
-                        }
-                        _ => format!("{} {} {}", self.field, self.operator, cell),
-                    },
-                    _ => format!("{} {} {}", self.field, self.operator, cell),
-                },
-                Value::Array(_) => unreachable!(),
+        AnsiDialect.deparse_qual(self).unwrap_or_default()
+    }
+}
+
+/// An expression on columns of the foreign table, used in [`ExprQual`]
+///
+/// Only built-in immutable functions and type casts are extracted, a wrapper
+/// decides which of them can be pushed down by their names.
+///
+/// ## Examples
+///
+/// ```sql
+/// lower(email)
+/// -- Func { name: "lower", args: [Column("email")] }
+///
+/// created_at::date
+/// -- Cast { expr: Column("created_at"), type_name: "date" }
+///
+/// date_trunc('day', created_at)
+/// -- Func { name: "date_trunc", args: [Const(String("day")), Column("created_at")] }
+/// ```
+#[derive(Debug, Clone)]
+pub enum FieldExpr {
+    /// a column of the foreign table
+    Column(String),
+
+    /// a constant function argument
+    Const(Cell),
+
+    /// a function call, `name` is the Postgres function name
+    Func { name: String, args: Vec<FieldExpr> },
+
+    /// a type cast, `type_name` is the Postgres type name, e.g. `date` or
+    /// `timestamp without time zone`
+    Cast {
+        expr: Box<FieldExpr>,
+        type_name: String,
+    },
+}
+
+impl FieldExpr {
+    /// Get all the column names in the expression
+    pub fn columns(&self) -> Vec<&String> {
+        match self {
+            FieldExpr::Column(col) => vec![col],
+            FieldExpr::Const(_) => Vec::new(),
+            FieldExpr::Func { args, .. } => args.iter().flat_map(|a| a.columns()).collect(),
+            FieldExpr::Cast { expr, .. } => expr.columns(),
+        }
+    }
+}
+
+/// A restriction on a [`FieldExpr`], like a [`Qual`] whose field is a function
+/// call or type cast of columns
+///
+/// ## Examples
+///
+/// ```sql
+/// where lower(email) like 'foo%';
+/// -- ExprQual { field: Func { name: "lower", args: [Column("email")] }, operator: "~~", value: Cell(String("foo%")) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExprQual {
+    pub field: FieldExpr,
+    pub operator: String,
+    pub value: Value,
+}
+
+/// A boolean expression tree of [`Qual`]s
+///
+/// It represents a restriction which cannot be flattened into a single
+/// [`Qual`], like an `OR` of conditions on different columns or a condition
+/// on a function call of a column. The restrictions are combined with `AND`,
+/// together with the flat quals.
+///
+/// ## Examples
+///
+/// ```sql
+/// where id = 1 or (col = 'foo' and not bool_col);
+/// -- Or([
+/// --   Leaf(Qual { field: "id", operator: "=", value: Cell(I32(1)), use_or: false }),
+/// --   And([
+/// --     Leaf(Qual { field: "col", operator: "=", value: Cell(String("foo")), use_or: false }),
+/// --     Leaf(Qual { field: "bool_col", operator: "=", value: Cell(Bool(false)), use_or: false })
+/// --   ])
+/// -- ])
+/// ```
+///
+/// ```sql
+/// where created_at::date = '2023-01-02';
+/// -- Expr(ExprQual { field: Cast { expr: Column("created_at"), type_name: "date" }, operator: "=", value: Cell(Date(..)) })
+/// ```
+#[derive(Debug, Clone)]
+pub enum QualExpr {
+    And(Vec<QualExpr>),
+    Or(Vec<QualExpr>),
+    Not(Box<QualExpr>),
+    Leaf(Qual),
+    Expr(ExprQual),
+}
+
+impl QualExpr {
+    /// Get all the leaf quals in the expression, [`ExprQual`] leaves are
+    /// not included
+    pub fn quals(&self) -> Vec<&Qual> {
+        match self {
+            QualExpr::And(exprs) | QualExpr::Or(exprs) => {
+                exprs.iter().flat_map(|e| e.quals()).collect()
+            }
+            QualExpr::Not(expr) => expr.quals(),
+            QualExpr::Leaf(qual) => vec![qual],
+            QualExpr::Expr(_) => Vec::new(),
+        }
+    }
+
+    /// Get the values of all the leaves in the expression
+    pub fn values(&self) -> Vec<&Value> {
+        match self {
+            QualExpr::And(exprs) | QualExpr::Or(exprs) => {
+                exprs.iter().flat_map(|e| e.values()).collect()
             }
+            QualExpr::Not(expr) => expr.values(),
+            QualExpr::Leaf(qual) => vec![&qual.value],
+            QualExpr::Expr(qual) => vec![&qual.value],
         }
     }
+
+    pub(crate) fn for_each_value_mut(&mut self, f: &mut impl FnMut(&mut Value)) {
+        match self {
+            QualExpr::And(exprs) | QualExpr::Or(exprs) => {
+                exprs.iter_mut().for_each(|e| e.for_each_value_mut(f))
+            }
+            QualExpr::Not(expr) => expr.for_each_value_mut(f),
+            QualExpr::Leaf(qual) => f(&mut qual.value),
+            QualExpr::Expr(qual) => f(&mut qual.value),
+        }
+    }
+}
+
+/// Whether a [`Qual`] is enforced by the foreign source, see
+/// [`ForeignDataWrapper::classify_quals`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualPushdown {
+    /// the foreign source only returns the rows satisfying the qual
+    Remote,
+
+    /// the qual must be checked by Postgres on each returned row
+    #[default]
+    Local,
 }
 
 /// Query sort, a.k.a `ORDER BY` clause
@@ -292,7 +668,7 @@ impl Qual {
 /// order by id desc, col;
 /// -- [
 /// --   Sort { field: "id", field_no: 1, reversed: true, nulls_first: true, collate: None },
-/// --   Sort { field: "col", field_no: 2, reversed: false, nulls_first: false, collate: None }
+/// --   Sort { field: "col", field_no: 2, reversed: false, nulls_first: false, collate: Some("default") }
 /// -- ]
 /// ```
 ///
@@ -300,7 +676,12 @@ impl Qual {
 /// order by id collate "de_DE";
 /// -- [Sort { field: "col", field_no: 2, reversed: false, nulls_first: false, collate: Some("de_DE") }]
 /// ```
-#[derive(Debug, Clone, Default)]
+///
+/// `collate` is the collation of a sort on text column, which is `default` for
+/// the database default collation, and `None` for the types not collatable.
+/// The remote side may not sort text in the same order, unless it is sorted by
+/// bytes in a `C` or `POSIX` collation.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Sort {
     pub field: String,
     pub field_no: usize,
@@ -309,6 +690,22 @@ pub struct Sort {
     pub collate: Option<String>,
 }
 
+impl Sort {
+    /// Deparse to a SQL `ORDER BY` item, collation is not included
+    ///
+    /// ```sql
+    /// id desc nulls first
+    /// ```
+    pub fn deparse(&self) -> String {
+        format!(
+            "{}{} nulls {}",
+            self.field,
+            if self.reversed { " desc" } else { "" },
+            if self.nulls_first { "first" } else { "last" }
+        )
+    }
+}
+
 /// Query limit, a.k.a `LIMIT count OFFSET offset` clause
 ///
 /// ## Examples
@@ -322,17 +719,331 @@ pub struct Sort {
 /// limit 42 offset 7;
 /// -- Limit { count: 42, offset: 7 }
 /// ```
+///
+/// The limit is only given to a scan if the foreign table is the only relation
+/// in the query, all the restrictions are pushed down without query
+/// parameters, and no aggregation, window function, set-returning function or
+/// `DISTINCT` is between the scan and the limit. If the query has `ORDER BY`,
+/// all of its sorts must also be accepted by
+/// [`can_sort`](ForeignDataWrapper::can_sort).
 #[derive(Debug, Clone, Default)]
 pub struct Limit {
     pub count: i64,
     pub offset: i64,
 }
 
+/// Join type of a pushed down [`Join`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinType {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+/// One side of a pushed down [`Join`]
+#[derive(Debug, Clone, Default)]
+pub struct JoinTable {
+    /// table alias used to qualify column names, e.g. `r1`
+    pub alias: String,
+
+    /// columns of this table needed by the join, not qualified
+    pub columns: Vec<String>,
+
+    /// restrictions on this table only, not qualified
+    pub quals: Vec<Qual>,
+
+    /// the options defined when `CREATE FOREIGN TABLE`
+    pub options: HashMap<String, String>,
+}
+
+/// A join between two foreign tables on the same server
+///
+/// The join conditions are [`Qual`]s whose `field` is an outer table column
+/// and whose `value` is a [`Value::Column`] of the inner table, both qualified
+/// with their table alias.
+///
+/// ## Examples
+///
+/// ```sql
+/// select * from orders o join customers c on o.customer_id = c.id where c.name = 'foo';
+/// -- Join {
+/// --   join_type: Inner,
+/// --   outer: JoinTable { alias: "r1", columns: ["id", "customer_id"], quals: [], options: {...} },
+/// --   inner: JoinTable {
+/// --     alias: "r2",
+/// --     columns: ["id", "name"],
+/// --     quals: [Qual { field: "name", operator: "=", value: Cell(String("foo")), use_or: false }],
+/// --     options: {...}
+/// --   },
+/// --   quals: [Qual { field: "r1.customer_id", operator: "=", value: Column("r2.id"), use_or: false }]
+/// -- }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub join_type: JoinType,
+    pub outer: JoinTable,
+    pub inner: JoinTable,
+    pub quals: Vec<Qual>,
+}
+
+/// Aggregate function of an [`Aggregate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateKind {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl fmt::Display for AggregateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AggregateKind::Count => write!(f, "count"),
+            AggregateKind::Sum => write!(f, "sum"),
+            AggregateKind::Min => write!(f, "min"),
+            AggregateKind::Max => write!(f, "max"),
+            AggregateKind::Avg => write!(f, "avg"),
+        }
+    }
+}
+
+/// An aggregate function call in a pushed down [`Aggregation`]
+///
+/// ## Examples
+///
+/// ```sql
+/// count(*)
+/// -- Aggregate { kind: Count, field: None, distinct: false, type_name: "bigint" }
+/// ```
+///
+/// ```sql
+/// sum(distinct amount)
+/// -- Aggregate { kind: Sum, field: Some("amount"), distinct: true, type_name: "bigint" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    pub kind: AggregateKind,
+    pub field: Option<String>,
+    pub distinct: bool,
+
+    /// the Postgres result type name, e.g. `bigint` or `double precision`
+    pub type_name: String,
+}
+
+impl Aggregate {
+    /// Deparse to SQL, which is also used as the target column name
+    pub fn deparse(&self) -> String {
+        match &self.field {
+            Some(field) if self.distinct => format!("{}(distinct {})", self.kind, field),
+            Some(field) => format!("{}({})", self.kind, field),
+            None => format!("{}(*)", self.kind),
+        }
+    }
+}
+
+/// Aggregates and `GROUP BY` clause pushed down on a foreign table
+///
+/// ## Examples
+///
+/// ```sql
+/// select status, count(*), max(amount) from orders where amount > 0 group by status;
+/// -- Aggregation {
+/// --   group_by: ["status"],
+/// --   aggregates: [
+/// --     Aggregate { kind: Count, field: None, distinct: false, type_name: "bigint" },
+/// --     Aggregate { kind: Max, field: Some("amount"), distinct: false, type_name: "integer" }
+/// --   ],
+/// --   quals: [Qual { field: "amount", operator: ">", value: Cell(I32(0)), use_or: false }],
+/// --   options: {...}
+/// -- }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Aggregation {
+    /// `GROUP BY` columns
+    pub group_by: Vec<String>,
+
+    /// aggregate function calls
+    pub aggregates: Vec<Aggregate>,
+
+    /// `WHERE` clause restrictions, applied before aggregation
+    pub quals: Vec<Qual>,
+
+    /// the options defined when `CREATE FOREIGN TABLE`
+    pub options: HashMap<String, String>,
+}
+
+/// The statement type of a [`DirectModify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectModifyType {
+    Update,
+    Delete,
+}
+
+/// `UPDATE` or `DELETE` statement pushed down on a foreign table
+///
+/// ## Examples
+///
+/// ```sql
+/// update orders set status = 'done' where id = 42;
+/// -- DirectModify {
+/// --   modify_type: Update,
+/// --   sets: Row { cols: ["status"], cells: [Some(String("done"))] },
+/// --   quals: [Qual { field: "id", operator: "=", value: Cell(I64(42)), use_or: false }],
+/// --   options: {...}
+/// -- }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DirectModify {
+    /// statement type
+    pub modify_type: DirectModifyType,
+
+    /// `SET` clause columns and their new values, empty for `DELETE`
+    pub sets: Row,
+
+    /// `WHERE` clause restrictions
+    pub quals: Vec<Qual>,
+
+    /// the options defined when `CREATE FOREIGN TABLE`
+    pub options: HashMap<String, String>,
+}
+
+/// The `LIMIT TO` / `EXCEPT` list type of an [`ImportForeignSchemaStmt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportSchemaType {
+    #[default]
+    All,
+    LimitTo,
+    Except,
+}
+
+/// `IMPORT FOREIGN SCHEMA` statement
+///
+/// ## Examples
+///
+/// ```sql
+/// import foreign schema bar
+///   from server my_server into public;
+/// -- ImportForeignSchemaStmt { server_name: "my_server", remote_schema: "bar", local_schema: "public", list_type: All, table_list: [], options: {} }
+/// ```
+///
+/// ```sql
+/// import foreign schema bar limit to (t1, t2)
+///   from server my_server into public
+///   options (foo 'baz');
+/// -- ImportForeignSchemaStmt { server_name: "my_server", remote_schema: "bar", local_schema: "public", list_type: LimitTo, table_list: ["t1", "t2"], options: {"foo": "baz"} }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ImportForeignSchemaStmt {
+    pub server_name: String,
+    pub remote_schema: String,
+    pub local_schema: String,
+    pub list_type: ImportSchemaType,
+    pub table_list: Vec<String>,
+    pub options: HashMap<String, String>,
+}
+
+impl ImportForeignSchemaStmt {
+    /// Check if a remote table should be imported according to the `LIMIT TO`
+    /// or `EXCEPT` list
+    pub fn is_importable(&self, table: &str) -> bool {
+        match self.list_type {
+            ImportSchemaType::All => true,
+            ImportSchemaType::LimitTo => self.table_list.iter().any(|t| t == table),
+            ImportSchemaType::Except => !self.table_list.iter().any(|t| t == table),
+        }
+    }
+}
+
+/// Size and cost estimate of a foreign table scan, see
+/// [`ForeignDataWrapper::get_rel_size`]
+///
+/// The costs are in the units of Postgres planner costs. The costs which are
+/// `None` are taken from the `fdw_startup_cost` and `fdw_tuple_cost` options
+/// of the foreign server, which default to 100 and 0.01 respectively, the
+/// same as `postgres_fdw`.
+#[derive(Debug, Clone, Default)]
+pub struct ScanEstimate {
+    /// expected number of rows returned by the scan
+    pub rows: f64,
+
+    /// mean row width in bytes, 0 to use the Postgres estimate
+    pub width: i32,
+
+    /// cost of starting the scan before the first row is returned
+    pub startup_cost: Option<f64>,
+
+    /// cost of fetching each row from the foreign source
+    pub cost_per_row: Option<f64>,
+
+    /// latency of each request to the foreign source, it is added to the
+    /// startup cost so it is paid again each time a scan is restarted
+    pub request_latency: f64,
+
+    /// whether `rows` is estimated by the foreign source; otherwise it is a
+    /// guess, and Postgres estimates the rows from the table statistics if
+    /// the foreign table has been analyzed
+    pub is_remote: bool,
+}
+
+/// Rows sampled from a foreign table for `ANALYZE`, see
+/// [`ForeignDataWrapper::sample_rows`]
+#[derive(Debug, Clone, Default)]
+pub struct RowSample {
+    /// the sampled rows, only the first target number of rows are used
+    pub rows: Vec<Row>,
+
+    /// estimated total number of rows in the foreign table
+    pub total_rows: f64,
+}
+
+/// Notifier of an asynchronous foreign scan, see
+/// [`ForeignDataWrapper::begin_async_scan`]
+///
+/// It can be sent to another thread. Dropping it without notifying also wakes
+/// up Postgres, e.g. when the request fails.
+#[derive(Debug)]
+pub struct AsyncNotifier(UnixStream);
+
+impl AsyncNotifier {
+    pub(crate) fn new(stream: UnixStream) -> Self {
+        Self(stream)
+    }
+
+    /// Notify Postgres that the rows of the scan are ready to be fetched
+    pub fn notify(&self) {
+        // the scan may have ended already, then nobody is waiting for it
+        let _ = (&self.0).write_all(&[1]);
+    }
+}
+
+/// Statistics of the requests made to the foreign source, see
+/// [`ForeignDataWrapper::remote_stats`]
+#[derive(Debug, Clone, Default)]
+pub struct RemoteStats {
+    /// number of requests made
+    pub requests: u64,
+
+    /// number of bytes received, 0 if unknown
+    pub bytes: u64,
+
+    /// number of bytes processed by the foreign source for the requests, such
+    /// as the bytes scanned by a query, 0 if unknown
+    pub bytes_processed: u64,
+
+    /// total time spent waiting for the responses
+    pub latency: Duration,
+}
+
 /// The Foreign Data Wrapper trait
 ///
 /// This is the main interface for your foreign data wrapper. Required functions
 /// are listed below, all the others are optional.
 ///
+/// The callbacks which may fail return [`FdwResult`], the framework reports
+/// the returned [`FdwError`](crate::error::FdwError) to Postgres.
+///
 /// 1. new
 /// 2. begin_scan
 /// 3. iter_scan
@@ -340,7 +1051,7 @@ pub struct Limit {
 ///
 /// See the module-level document for more details.
 ///
-pub trait ForeignDataWrapper {
+pub trait ForeignDataWrapper: 'static {
     /// Create a FDW instance
     ///
     /// `options` is the key-value pairs defined in `CREATE SERVER` SQL. For example,
@@ -355,15 +1066,42 @@ pub trait ForeignDataWrapper {
     ///
     /// `options` passed here will be a hashmap { 'foo' -> 'bar' }.
     ///
+    /// The options of the user mapping for the current user, or the `PUBLIC`
+    /// one if the user has none, are merged over the server options. Like the
+    /// permission checks, the user is the owner of the view if the foreign
+    /// table is queried through a view. So the credentials can be defined per
+    /// user,
+    ///
+    /// ```sql
+    /// create user mapping for my_role
+    ///   server my_helloworld_server
+    ///   options (
+    ///     foo 'baz'
+    /// );
+    /// ```
+    ///
+    /// then `options` will be { 'foo' -> 'baz' } for `my_role`. The foreign
+    /// table options are not included, they are passed separately to the scan
+    /// and modify callbacks.
+    ///
     /// You can do any initalization in this function, like saving connection
     /// info or API url in an variable, but don't do heavy works like database
     /// connection or API call.
-    fn new(options: &HashMap<String, String>) -> Self;
+    ///
+    /// The instance is cached in the backend and reused by later queries on
+    /// the same server by the same user, until the server or user mapping
+    /// options are changed or `wrappers_disconnect()` is called. So the client
+    /// and connections created by the instance are reused as well, and any
+    /// state of a scan or modify should be reset when it begins.
+    fn new(options: &HashMap<String, String>) -> FdwResult<Self>
+    where
+        Self: Sized;
 
     /// Obtain relation size estimates for a foreign table
     ///
-    /// Return the expected number of rows and row size (in bytes) by the
-    /// foreign table scan.
+    /// Return the expected number of rows, row size (in bytes) and costs of
+    /// the foreign table scan. The default is a guessed estimate of 0 rows,
+    /// which makes Postgres use the table statistics if there is any.
     ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-SCAN).
     fn get_rel_size(
@@ -373,13 +1111,14 @@ pub trait ForeignDataWrapper {
         _sorts: &[Sort],
         _limit: &Option<Limit>,
         _options: &HashMap<String, String>,
-    ) -> (i64, i32) {
-        (0, 0)
+    ) -> FdwResult<ScanEstimate> {
+        Ok(ScanEstimate::default())
     }
 
     /// Called when begin executing a foreign scan
     ///
-    /// - `quals` - `WHERE` clause pushed down
+    /// - `quals` - `WHERE` clause pushed down, including the join conditions
+    ///   of a parameterized scan, whose values are [`Param`]
     /// - `columns` - target columns to be queried
     /// - `sorts` - `ORDER BY` clause pushed down
     /// - `limit` - `LIMIT` clause pushed down
@@ -393,24 +1132,260 @@ pub trait ForeignDataWrapper {
         sorts: &[Sort],
         limit: &Option<Limit>,
         options: &HashMap<String, String>,
-    );
+    ) -> FdwResult<()>;
 
     /// Called when fetch one row from the foreign source
     ///
     /// FDW must save fetched foreign data into the [`Row`], or return `None` if no more rows to read.
+    /// The row columns are matched to the target columns by name, so they can be in any order,
+    /// and the columns not in the targets are ignored.
     ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-SCAN).
-    fn iter_scan(&mut self, row: &mut Row) -> Option<()>;
+    fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>>;
 
     /// Called when restart the scan from the beginning.
     ///
+    /// If the quals contain any [`Param`], the scan is instead restarted by
+    /// `end_scan` and then `begin_scan` with the re-evaluated parameters.
+    ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-SCAN).
-    fn re_scan(&mut self) {}
+    fn re_scan(&mut self) -> FdwResult<()> {
+        Ok(())
+    }
 
     /// Called when end the scan
     ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-SCAN).
-    fn end_scan(&mut self);
+    fn end_scan(&mut self) -> FdwResult<()>;
+
+    /// Describe the foreign scan for `EXPLAIN`
+    ///
+    /// Return a list of `(label, value)` properties shown under the foreign
+    /// scan node, for example the remote query or request URL. `verbose` is
+    /// true for `EXPLAIN (VERBOSE)`.
+    ///
+    /// This is called after `begin_scan` for `EXPLAIN ANALYZE`. For a plain
+    /// `EXPLAIN`, it is called right after `get_rel_size` when the scan is
+    /// planned, because the instance may be shared with the other scans of the
+    /// query during planning. Nothing is shown for a pushed down join or
+    /// aggregation in a plain `EXPLAIN`.
+    ///
+    /// By default, no properties are shown.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-EXPLAIN).
+    fn explain(&self, _verbose: bool) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Obtain the statistics of the requests made to the foreign source
+    ///
+    /// The statistics are shown by `EXPLAIN ANALYZE`, they should cover all
+    /// the requests made since the FDW instance was created. The requests made
+    /// by previous queries using the cached instance are excluded.
+    ///
+    /// By default, no statistics are shown.
+    fn remote_stats(&self) -> Option<RemoteStats> {
+        None
+    }
+
+    /// Classify the quals of a foreign table scan as enforced remotely or locally
+    ///
+    /// - `quals` - `WHERE` clause restrictions on the foreign table, the join
+    ///   conditions of a parameterized scan are not included
+    /// - `options` - the options defined when `CREATE FOREIGN TABLE`
+    ///
+    /// Return one [`QualPushdown`] for each qual in `quals`. Postgres skips the
+    /// recheck of the [`QualPushdown::Remote`] quals, and `LIMIT`/`OFFSET` is only
+    /// pushed down when all the restrictions of the query are remote. All quals
+    /// are still passed to `begin_scan`.
+    ///
+    /// By default, all quals are local.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-planning.html).
+    fn classify_quals(
+        &mut self,
+        quals: &[Qual],
+        _options: &HashMap<String, String>,
+    ) -> Vec<QualPushdown> {
+        vec![QualPushdown::Local; quals.len()]
+    }
+
+    /// Called with the boolean expression restrictions of a foreign table scan
+    ///
+    /// - `exprs` - `WHERE` clause restrictions which cannot be flattened into
+    ///   [`Qual`]s, e.g. `a = 1 or b = 2`
+    ///
+    /// It is called during planning before `get_rel_size`, and again before each
+    /// `begin_scan` with the query parameters evaluated. The expressions are
+    /// combined with the `quals` by `AND`. By default, they are ignored and
+    /// Postgres checks them locally.
+    fn set_qual_exprs(&mut self, _exprs: &[QualExpr]) {}
+
+    /// Classify the boolean expression restrictions as enforced remotely or
+    /// locally
+    ///
+    /// Return one [`QualPushdown`] for each expression in `exprs`, same as
+    /// `classify_quals`. By default, all expressions are local.
+    fn classify_qual_exprs(
+        &mut self,
+        exprs: &[QualExpr],
+        _options: &HashMap<String, String>,
+    ) -> Vec<QualPushdown> {
+        vec![QualPushdown::Local; exprs.len()]
+    }
+
+    /// Decide which of the requested sorts can be satisfied remotely
+    ///
+    /// - `sorts` - the leading `ORDER BY` items of the query which can be
+    ///   applied to this foreign table
+    ///
+    /// Return the leading part of `sorts` the remote side guarantees to
+    /// return the rows ordered by, in the same order. The sorts are then
+    /// passed to `begin_scan` as usual and Postgres can skip the local sort,
+    /// so the remote ordering must match Postgres's, including `NULLS FIRST`
+    /// and collation.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-planning.html).
+    fn can_sort(&mut self, _sorts: &[Sort]) -> Vec<Sort> {
+        Vec::new()
+    }
+
+    /// Decide whether a join between two foreign tables can be pushed down
+    ///
+    /// Only joins between two foreign tables on the same server are
+    /// considered. Return `true` to execute the join remotely, in which case
+    /// [`begin_join_scan`](Self::begin_join_scan) is called instead of
+    /// `begin_scan`, followed by the usual `iter_scan` and `end_scan`.
+    ///
+    /// For inner joins, all the conditions are rechecked locally. For outer
+    /// joins, nothing is rechecked so the FDW must apply all the table quals
+    /// and join quals remotely, or reject the join.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-JOIN-SCAN).
+    fn can_join(&mut self, _join: &Join) -> bool {
+        false
+    }
+
+    /// Called when begin executing a pushed down join
+    ///
+    /// - `join` - the join accepted by [`can_join`](Self::can_join)
+    /// - `columns` - target columns to be queried, qualified with the table
+    ///   alias, e.g. `r1.id`. `iter_scan` must return the cells in this order.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-JOIN-SCAN).
+    fn begin_join_scan(&mut self, _join: &Join, _columns: &[String]) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Decide whether aggregates and `GROUP BY` on a foreign table can be
+    /// pushed down
+    ///
+    /// Only considered when all the `WHERE` clause restrictions can be pushed
+    /// down and there is no `HAVING` clause or grouping sets. Return `true` to
+    /// execute the aggregation remotely, in which case
+    /// [`begin_aggregate_scan`](Self::begin_aggregate_scan) is called instead
+    /// of `begin_scan`, followed by the usual `iter_scan` and `end_scan`.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPPER-PLANNING).
+    fn can_aggregate(&mut self, _aggregation: &Aggregation) -> bool {
+        false
+    }
+
+    /// Called when begin executing a pushed down aggregation
+    ///
+    /// - `aggregation` - the aggregation accepted by [`can_aggregate`](Self::can_aggregate)
+    /// - `columns` - target columns to be queried, either a `GROUP BY` column
+    ///   or a deparsed [`Aggregate`], e.g. `count(*)`. `iter_scan` must return
+    ///   the cells in this order, and each cell type must match the Postgres
+    ///   result type of the aggregate, e.g. `I64` for `count`.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPPER-PLANNING).
+    fn begin_aggregate_scan(
+        &mut self,
+        _aggregation: &Aggregation,
+        _columns: &[String],
+    ) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Split a foreign table scan into partitions for parallel workers
+    ///
+    /// - `quals` - `WHERE` clause pushed down, there is no [`Param`] in them
+    /// - `columns` - target columns to be queried
+    /// - `options` - the options defined when `CREATE FOREIGN TABLE`
+    ///
+    /// Return a list of partition descriptors, e.g. read stream names, shard
+    /// addresses or date ranges, which together cover all the rows matching
+    /// `quals`. Each partition is claimed by one of the parallel workers (or
+    /// the leader), and scanned by
+    /// [`begin_partition_scan`](Self::begin_partition_scan) in a separate FDW
+    /// instance, so a descriptor must contain everything needed to scan it.
+    ///
+    /// This is called during planning when Postgres considers a parallel plan.
+    /// Sorts and limit are not pushed down to the partitions.
+    ///
+    /// By default, no partitions are returned and the scan is never parallel.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-PARALLEL).
+    fn partition_scan(
+        &mut self,
+        _quals: &[Qual],
+        _columns: &[String],
+        _options: &HashMap<String, String>,
+    ) -> FdwResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Called when begin scanning a partition returned by
+    /// [`partition_scan`](Self::partition_scan)
+    ///
+    /// - `partition` - the partition descriptor
+    /// - `columns` - target columns to be queried
+    /// - `options` - the options defined when `CREATE FOREIGN TABLE`
+    ///
+    /// The partition rows are then fetched by `iter_scan`, followed by
+    /// `end_scan`. The same FDW instance may scan several partitions one
+    /// after another.
+    fn begin_partition_scan(
+        &mut self,
+        _partition: &str,
+        _columns: &[String],
+        _options: &HashMap<String, String>,
+    ) -> FdwResult<()> {
+        Err("partitioned scan is not supported".into())
+    }
+
+    /// Called when begin executing a foreign scan asynchronously
+    ///
+    /// On Postgres 14 and above, the scans of foreign tables with the
+    /// `async_capable` table or server option set to `true` are executed
+    /// asynchronously when they are under an `Append`, e.g. of `UNION ALL` or
+    /// a partitioned table, so the remote requests can run concurrently. The
+    /// option value should be checked by
+    /// [`check_options_bool`](crate::utils::check_options_bool) in the
+    /// [`validator`](ForeignDataWrapper::validator).
+    ///
+    /// This is called instead of `begin_scan` with the same arguments. Start
+    /// the remote request without waiting for it, e.g. in a spawned thread, and
+    /// call [`AsyncNotifier::notify`] once the rows can be fetched by
+    /// `iter_scan`. The rows are converted to cells by `iter_scan`, as no
+    /// Postgres function can be called from other threads.
+    ///
+    /// Return `false` if the scan is not started, `begin_scan` is then called
+    /// instead. By default, the scan is not started.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-ASYNC).
+    fn begin_async_scan(
+        &mut self,
+        _quals: &[Qual],
+        _columns: &[String],
+        _sorts: &[Sort],
+        _limit: &Option<Limit>,
+        _options: &HashMap<String, String>,
+        _notifier: AsyncNotifier,
+    ) -> FdwResult<bool> {
+        Ok(false)
+    }
 
     /// Called when begin executing a foreign table modification operation.
     ///
@@ -434,34 +1409,190 @@ pub trait ForeignDataWrapper {
     /// ```
     ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
-    fn begin_modify(&mut self, _options: &HashMap<String, String>) {}
+    fn begin_modify(&mut self, _options: &HashMap<String, String>) -> FdwResult<()> {
+        Ok(())
+    }
 
     /// Called when insert one row into the foreign table
     ///
     /// - row - the new row to be inserted
     ///
+    /// Return the inserted row from the foreign source, e.g. with server
+    /// generated values, for the `RETURNING` clause. Its cells are matched to
+    /// the table columns by name, and the columns not returned keep the
+    /// inserted values.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
+    fn insert(&mut self, _row: &Row) -> FdwResult<Option<Row>> {
+        Ok(None)
+    }
+
+    /// Called when insert a batch of rows into the foreign table
+    ///
+    /// - rows - the new rows to be inserted
+    ///
+    /// Only used on Postgres 14 and later when the `batch_size` foreign table
+    /// option is greater than 1, and the inserted rows are not needed by
+    /// `RETURNING` clause, `WITH CHECK OPTION` or row triggers. By default,
+    /// each row is inserted by [`insert`](Self::insert).
+    ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
-    fn insert(&mut self, _row: &Row) {}
+    fn insert_batch(&mut self, rows: &[Row]) -> FdwResult<()> {
+        for row in rows {
+            self.insert(row)?;
+        }
+        Ok(())
+    }
 
     /// Called when update one row into the foreign table
     ///
     /// - rowid - the `rowid_column` cell
     /// - new_row - the new row with updated cells
     ///
+    /// Return the updated row from the foreign source for the `RETURNING`
+    /// clause, the columns not returned keep the new values.
+    ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
-    fn update(&mut self, _rowid: &Cell, _new_row: &Row) {}
+    fn update(&mut self, _rowid: &Cell, _new_row: &Row) -> FdwResult<Option<Row>> {
+        Ok(None)
+    }
 
     /// Called when delete one row into the foreign table
     ///
     /// - rowid - the `rowid_column` cell
     ///
+    /// Return the deleted row from the foreign source for the `RETURNING`
+    /// clause, the columns not returned are null.
+    ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
-    fn delete(&mut self, _rowid: &Cell) {}
+    fn delete(&mut self, _rowid: &Cell) -> FdwResult<Option<Row>> {
+        Ok(None)
+    }
+
+    /// Called when planning an `UPDATE` or `DELETE` to check if it can be
+    /// executed directly on the foreign source
+    ///
+    /// - `modify` - the statement to be pushed down
+    ///
+    /// Only considered on Postgres 14 and later when the new values are
+    /// constants, all the `WHERE` clause restrictions can be pushed down and
+    /// there is no `RETURNING` clause. Return `true` to execute the statement
+    /// by [`direct_modify`](Self::direct_modify) instead of scanning and
+    /// modifying the rows one by one.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
+    fn can_direct_modify(&mut self, _modify: &DirectModify) -> bool {
+        false
+    }
+
+    /// Called when execute a pushed down `UPDATE` or `DELETE`
+    ///
+    /// - `modify` - the statement accepted by [`can_direct_modify`](Self::can_direct_modify)
+    ///
+    /// Return the number of affected rows on the foreign source.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
+    fn direct_modify(&mut self, _modify: &DirectModify) -> FdwResult<i64> {
+        Ok(0)
+    }
 
     /// Called when end the table update
     ///
     /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-UPDATE).
-    fn end_modify(&mut self) {}
+    fn end_modify(&mut self) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Called before the local transaction commits
+    ///
+    /// Only called on the instances which have begun modification in the
+    /// transaction, and they are kept alive until the transaction ends. This
+    /// is the last chance to abort the local transaction by raising an error,
+    /// so it is the place to flush the buffered writes to the foreign source.
+    fn on_pre_commit(&mut self) -> FdwResult<()> {
+        Ok(())
+    }
+
+    /// Called after the local transaction has committed
+    ///
+    /// The transaction cannot be aborted any more, so no errors should be
+    /// raised here.
+    fn on_commit(&mut self) {}
+
+    /// Called when the local transaction, or a subtransaction in which the
+    /// modification began, is aborted
+    ///
+    /// The buffered writes should be discarded here. For an aborted
+    /// subtransaction, `pg_sys::GetCurrentSubTransactionId()` is still the
+    /// aborted one, only the writes made since it began, i.e. under a
+    /// subtransaction id not less than it, are to be discarded. No errors
+    /// should be raised, as Postgres is already in error recovery.
+    fn on_abort(&mut self) {}
+
+    /// Obtain a sample of rows for `ANALYZE`
+    ///
+    /// Return at most `target_rows` rows of the `columns`, ideally sampled
+    /// randomly from the whole foreign table, along with the estimated total
+    /// number of rows. Postgres computes column statistics from the sample.
+    ///
+    /// The default implementation scans the first `target_rows` rows, and
+    /// estimates the total rows by `get_rel_size` if there are more rows. The
+    /// sample is not random, so the statistics can be skewed if the scan
+    /// returns rows in some order. Override it if the foreign source supports
+    /// sampling.
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-ANALYZE).
+    fn sample_rows(
+        &mut self,
+        columns: &[String],
+        target_rows: usize,
+        options: &HashMap<String, String>,
+    ) -> FdwResult<RowSample> {
+        let limit = Some(Limit {
+            count: target_rows as i64,
+            offset: 0,
+        });
+        let estimate = self.get_rel_size(&[], columns, &[], &None, options)?;
+        self.begin_scan(&[], columns, &[], &limit, options)?;
+
+        let mut rows = Vec::new();
+        let mut row = Row::new();
+        while rows.len() < target_rows {
+            row.clear();
+            if self.iter_scan(&mut row)?.is_none() {
+                break;
+            }
+            rows.push(row.clone());
+        }
+        self.end_scan()?;
+
+        // the scan is exhausted if there are fewer rows than the target
+        let total_rows = if rows.len() < target_rows {
+            rows.len() as f64
+        } else {
+            estimate.rows.max(rows.len() as f64)
+        };
+        Ok(RowSample { rows, total_rows })
+    }
+
+    /// Obtain a list of foreign table creation commands
+    ///
+    /// Return a list of `CREATE FOREIGN TABLE` statements which will be
+    /// executed by Postgres to create the foreign tables. Remote tables not
+    /// passing [`ImportForeignSchemaStmt::is_importable`] should be skipped,
+    /// Postgres will also ignore the commands for them.
+    ///
+    /// For example,
+    ///
+    /// ```sql
+    /// import foreign schema bar
+    ///   from server my_server into public;
+    /// ```
+    ///
+    /// [See more details](https://www.postgresql.org/docs/current/fdw-callbacks.html#FDW-CALLBACKS-IMPORT).
+    fn import_foreign_schema(&mut self, _stmt: &ImportForeignSchemaStmt) -> FdwResult<Vec<String>> {
+        Ok(Vec::new())
+    }
 
     /// Returns a FdwRoutine for the FDW
     ///
@@ -470,7 +1601,7 @@ pub trait ForeignDataWrapper {
     where
         Self: Sized,
     {
-        use crate::{modify, scan};
+        use crate::{analyze, import_foreign_schema, modify, scan};
         let mut fdw_routine =
             FdwRoutine::<AllocatedByRust>::alloc_node(pg_sys::NodeTag_T_FdwRoutine);
 
@@ -478,6 +1609,8 @@ pub trait ForeignDataWrapper {
         fdw_routine.GetForeignRelSize = Some(scan::get_foreign_rel_size::<Self>);
         fdw_routine.GetForeignPaths = Some(scan::get_foreign_paths::<Self>);
         fdw_routine.GetForeignPlan = Some(scan::get_foreign_plan::<Self>);
+        fdw_routine.GetForeignJoinPaths = Some(scan::get_foreign_join_paths::<Self>);
+        fdw_routine.GetForeignUpperPaths = Some(scan::get_foreign_upper_paths::<Self>);
         fdw_routine.ExplainForeignScan = Some(scan::explain_foreign_scan::<Self>);
 
         // scan phase
@@ -486,15 +1619,47 @@ pub trait ForeignDataWrapper {
         fdw_routine.ReScanForeignScan = Some(scan::re_scan_foreign_scan::<Self>);
         fdw_routine.EndForeignScan = Some(scan::end_foreign_scan::<Self>);
 
+        // parallel scan
+        fdw_routine.IsForeignScanParallelSafe = Some(scan::is_foreign_scan_parallel_safe);
+        fdw_routine.EstimateDSMForeignScan = Some(scan::estimate_dsm_foreign_scan);
+        fdw_routine.InitializeDSMForeignScan = Some(scan::initialize_dsm_foreign_scan::<Self>);
+        fdw_routine.ReInitializeDSMForeignScan = Some(scan::reinitialize_dsm_foreign_scan);
+        fdw_routine.InitializeWorkerForeignScan =
+            Some(scan::initialize_worker_foreign_scan::<Self>);
+
         // modify phase
         fdw_routine.AddForeignUpdateTargets = Some(modify::add_foreign_update_targets);
         fdw_routine.PlanForeignModify = Some(modify::plan_foreign_modify::<Self>);
         fdw_routine.BeginForeignModify = Some(modify::begin_foreign_modify::<Self>);
         fdw_routine.ExecForeignInsert = Some(modify::exec_foreign_insert::<Self>);
+        #[cfg(any(feature = "pg14", feature = "pg15"))]
+        {
+            fdw_routine.GetForeignModifyBatchSize =
+                Some(modify::get_foreign_modify_batch_size::<Self>);
+            fdw_routine.ExecForeignBatchInsert = Some(modify::exec_foreign_batch_insert::<Self>);
+            fdw_routine.PlanDirectModify = Some(scan::plan_direct_modify::<Self>);
+            fdw_routine.BeginDirectModify = Some(scan::begin_direct_modify::<Self>);
+            fdw_routine.IterateDirectModify = Some(scan::iterate_direct_modify::<Self>);
+            fdw_routine.EndDirectModify = Some(scan::end_direct_modify::<Self>);
+            fdw_routine.ExplainDirectModify = Some(scan::explain_foreign_scan::<Self>);
+            fdw_routine.IsForeignPathAsyncCapable =
+                Some(scan::is_foreign_path_async_capable::<Self>);
+            fdw_routine.ForeignAsyncRequest = Some(scan::foreign_async_request::<Self>);
+            fdw_routine.ForeignAsyncConfigureWait =
+                Some(scan::foreign_async_configure_wait::<Self>);
+            fdw_routine.ForeignAsyncNotify = Some(scan::foreign_async_notify::<Self>);
+        }
         fdw_routine.ExecForeignDelete = Some(modify::exec_foreign_delete::<Self>);
         fdw_routine.ExecForeignUpdate = Some(modify::exec_foreign_update::<Self>);
         fdw_routine.EndForeignModify = Some(modify::end_foreign_modify::<Self>);
 
+        // analyze
+        fdw_routine.AnalyzeForeignTable = Some(analyze::analyze_foreign_table::<Self>);
+
+        // import foreign schema
+        fdw_routine.ImportForeignSchema =
+            Some(import_foreign_schema::import_foreign_schema::<Self>);
+
         Self::fdw_routine_hook(&mut fdw_routine);
         fdw_routine.into_pg_boxed()
     }
@@ -512,15 +1677,20 @@ pub trait ForeignDataWrapper {
     /// # Example
     ///
     /// ```rust,no_run
-    /// fn validator(opt_list: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) {
+    /// fn validator(opt_list: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) -> FdwResult<()> {
     ///     if let Some(oid) = catalog {
     ///         match oid {
     ///             FOREIGN_DATA_WRAPPER_RELATION_ID => {
     ///                 // check a required option when create foreign data wrapper
-    ///                 check_options_contain(&opt_list, "required_option");
+    ///                 check_options_contain(&opt_list, "required_option")?;
     ///             }
     ///             FOREIGN_SERVER_RELATION_ID => {
     ///                 // check option here when create server
+    ///                 check_options_bool(&opt_list, "async_capable")?;
+    ///             }
+    ///             USER_MAPPING_RELATION_ID => {
+    ///                 // only credentials can be defined per user
+    ///                 check_options_allowed(&opt_list, &["api_key"])?;
     ///             }
     ///             FOREIGN_TABLE_RELATION_ID => {
     ///                 // check option here when create foreign table
@@ -528,7 +1698,10 @@ pub trait ForeignDataWrapper {
     ///             _ => {}
     ///         }
     ///     }
+    ///     Ok(())
     /// }
     /// ```
-    fn validator(_options: Vec<Option<String>>, _catalog: Option<pg_sys::Oid>) {}
+    fn validator(_options: Vec<Option<String>>, _catalog: Option<pg_sys::Oid>) -> FdwResult<()> {
+        Ok(())
+    }
 }
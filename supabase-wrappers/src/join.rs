@@ -0,0 +1,114 @@
+use crate::interface::{JoinType, Qual, Value};
+use crate::qual::{get_operator, unnest_clause};
+use pgx::{is_a, pg_sys, PgList};
+use std::ffi::CStr;
+use std::os::raw::c_int;
+
+// alias of a base relation in a pushed down join
+pub(crate) fn join_alias(relid: pg_sys::Index) -> String {
+    format!("r{}", relid)
+}
+
+// convert Postgres's join type, only inner and outer joins are supported
+pub(crate) fn to_join_type(jointype: pg_sys::JoinType) -> Option<JoinType> {
+    match jointype {
+        pg_sys::JoinType_JOIN_INNER => Some(JoinType::Inner),
+        pg_sys::JoinType_JOIN_LEFT => Some(JoinType::Left),
+        pg_sys::JoinType_JOIN_RIGHT => Some(JoinType::Right),
+        pg_sys::JoinType_JOIN_FULL => Some(JoinType::Full),
+        _ => None,
+    }
+}
+
+// get the column name of a var, not qualified
+pub(crate) unsafe fn var_column_name(
+    root: *mut pg_sys::PlannerInfo,
+    var: *mut pg_sys::Var,
+) -> Option<String> {
+    if (*var).varattno < 1 {
+        return None;
+    }
+    let rte = pg_sys::planner_rt_fetch((*var).varno as pg_sys::Index, root);
+    let attname = pg_sys::get_attname((*rte).relid, (*var).varattno, true);
+    if attname.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(attname).to_str().unwrap().to_owned())
+}
+
+// get the column name of a var, qualified with the relation alias
+pub(crate) unsafe fn var_qualified_name(
+    root: *mut pg_sys::PlannerInfo,
+    var: *mut pg_sys::Var,
+) -> Option<String> {
+    var_column_name(root, var).map(|col| format!("{}.{}", join_alias((*var).varno as _), col))
+}
+
+// check if a join clause is pushed down from above the join, e.g. a WHERE
+// clause which cannot be applied as a join condition of an outer join
+pub(crate) unsafe fn is_pushed_down(
+    rinfo: *mut pg_sys::RestrictInfo,
+    joinrel: *mut pg_sys::RelOptInfo,
+) -> bool {
+    (*rinfo).is_pushed_down || !pg_sys::bms_is_subset((*rinfo).required_relids, (*joinrel).relids)
+}
+
+// extract a join condition in the form of `outer_col op inner_col`
+pub(crate) unsafe fn extract_join_qual(
+    root: *mut pg_sys::PlannerInfo,
+    expr: *mut pg_sys::Node,
+    outerrel: *mut pg_sys::RelOptInfo,
+    innerrel: *mut pg_sys::RelOptInfo,
+) -> Option<Qual> {
+    if !is_a(expr, pg_sys::NodeTag_T_OpExpr) {
+        return None;
+    }
+    let expr = expr as *mut pg_sys::OpExpr;
+    let args: PgList<pg_sys::Node> = PgList::from_pg((*expr).args);
+
+    // only deal with binary operator
+    if args.len() != 2 {
+        return None;
+    }
+
+    let left = unnest_clause(args.head().unwrap());
+    let right = unnest_clause(args.tail().unwrap());
+    if !is_a(left, pg_sys::NodeTag_T_Var) || !is_a(right, pg_sys::NodeTag_T_Var) {
+        return None;
+    }
+    let left = left as *mut pg_sys::Var;
+    let right = right as *mut pg_sys::Var;
+
+    let is_member = |var: *mut pg_sys::Var, rel: *mut pg_sys::RelOptInfo| {
+        pg_sys::bms_is_member((*var).varno as c_int, (*rel).relids)
+    };
+
+    // make sure the outer column is on the left side, use the commutator
+    // operator if the operands are swapped
+    let (outer_var, inner_var, opno) = if is_member(left, outerrel) && is_member(right, innerrel) {
+        (left, right, (*expr).opno)
+    } else if is_member(left, innerrel) && is_member(right, outerrel) {
+        let opno = pg_sys::get_commutator((*expr).opno);
+        if opno == pg_sys::InvalidOid {
+            return None;
+        }
+        (right, left, opno)
+    } else {
+        return None;
+    };
+
+    let opr = get_operator(opno);
+    if opr.is_null() {
+        return None;
+    }
+
+    let field = var_qualified_name(root, outer_var)?;
+    let column = var_qualified_name(root, inner_var)?;
+
+    Some(Qual {
+        field,
+        operator: pgx::name_data_to_str(&(*opr).oprname).to_string(),
+        value: Value::Column(column),
+        use_or: false,
+    })
+}
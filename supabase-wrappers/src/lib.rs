@@ -43,17 +43,39 @@
 //!
 //! - Query planning phase
 //!   - [get_rel_size()](`interface::ForeignDataWrapper#method.get_rel_size`)
+//!   - [can_sort()](`interface::ForeignDataWrapper#method.can_sort`)
+//!   - [can_join()](`interface::ForeignDataWrapper#method.can_join`)
+//!   - [can_aggregate()](`interface::ForeignDataWrapper#method.can_aggregate`)
+//!   - [can_direct_modify()](`interface::ForeignDataWrapper#method.can_direct_modify`)
+//!   - [partition_scan()](`interface::ForeignDataWrapper#method.partition_scan`)
 //! - Scan phase
 //!   - [begin_scan()](`interface::ForeignDataWrapper#tymethod.begin_scan`) *required*
 //!   - [iter_scan()](`interface::ForeignDataWrapper#tymethod.iter_scan`) *required*
+//!   - [begin_join_scan()](`interface::ForeignDataWrapper#method.begin_join_scan`)
+//!   - [begin_aggregate_scan()](`interface::ForeignDataWrapper#method.begin_aggregate_scan`)
+//!   - [begin_partition_scan()](`interface::ForeignDataWrapper#method.begin_partition_scan`)
+//!   - [begin_async_scan()](`interface::ForeignDataWrapper#method.begin_async_scan`)
 //!   - [re_scan()](`interface::ForeignDataWrapper#method.re_scan`)
 //!   - [end_scan()](`interface::ForeignDataWrapper#tymethod.end_scan`) *required*
 //! - Modify phase
 //!   - [begin_modify()](`interface::ForeignDataWrapper#method.begin_modify`)
 //!   - [insert()](`interface::ForeignDataWrapper#method.insert`)
+//!   - [insert_batch()](`interface::ForeignDataWrapper#method.insert_batch`)
 //!   - [update()](`interface::ForeignDataWrapper#method.update`)
 //!   - [delete()](`interface::ForeignDataWrapper#method.delete`)
+//!   - [direct_modify()](`interface::ForeignDataWrapper#method.direct_modify`)
 //!   - [end_modify()](`interface::ForeignDataWrapper#method.end_modify`)
+//! - Transaction end
+//!   - [on_pre_commit()](`interface::ForeignDataWrapper#method.on_pre_commit`)
+//!   - [on_commit()](`interface::ForeignDataWrapper#method.on_commit`)
+//!   - [on_abort()](`interface::ForeignDataWrapper#method.on_abort`)
+//! - Analyze
+//!   - [sample_rows()](`interface::ForeignDataWrapper#method.sample_rows`)
+//! - Explain
+//!   - [explain()](`interface::ForeignDataWrapper#method.explain`)
+//!   - [remote_stats()](`interface::ForeignDataWrapper#method.remote_stats`)
+//! - Import foreign schema
+//!   - [import_foreign_schema()](`interface::ForeignDataWrapper#method.import_foreign_schema`)
 //!
 //! To give different functionalities to your FDW, you can choose different callback functions to implement. The required ones are `begin_scan`, `iter_scan` and `end_scan`, all the others are optional. See [Postgres FDW document](https://www.postgresql.org/docs/current/fdw-callbacks.html) for more details about FDW development.
 //!
@@ -76,7 +98,7 @@
 //! }
 //!
 //! impl ForeignDataWrapper for HelloWorldFdw {
-//!     pub fn new(options: &HashMap<String, String>) -> Self {
+//!     pub fn new(options: &HashMap<String, String>) -> FdwResult<Self> {
 //!         // 'options' is the key-value pairs defined in `CREATE SERVER` SQL, for example,
 //!         //
 //!         // create server my_helloworld_server
@@ -90,10 +112,10 @@
 //!         // You can do any initalization in this new() function, like saving connection
 //!         // info or API url in an variable, but don't do heavy works like database
 //!         // connection or API call.
-//!         Self {
+//!         Ok(Self {
 //!             row_cnt: 0,
 //!             tgt_cols: Vec::new(),
-//!         }
+//!         })
 //!     }
 //! }
 //! ```
@@ -104,6 +126,8 @@
 //! - `iter_scan` - called for each row to be returned to Postgres, return `None` to stop the scan
 //! - `end_scan` - called once at the end of `SELECT`
 //!
+//! All of them return [`FdwResult`](error::FdwResult), an [`FdwError`](error::FdwError) returned by them is reported to Postgres and aborts the current transaction.
+//!
 //! Suppose the foreign table DDL is like below,
 //!
 //! ```sql
@@ -128,15 +152,16 @@
 //!         _sorts: &[Sort],
 //!         _limit: &Option<Limit>,
 //!         _options: &HashMap<String, String>,
-//!     ) {
+//!     ) -> FdwResult<()> {
 //!         // reset row count
 //!         self.row_cnt = 0;
 //!
 //!         // save a copy of target columns
 //!         self.tgt_cols = columns.to_vec();
+//!         Ok(())
 //!     }
 //!
-//!     fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
+//!     fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>> {
 //!         // this is called on each row and we only return one row here
 //!         if self.row_cnt < 1 {
 //!             // add values to row if they are in target column list
@@ -151,15 +176,16 @@
 //!             self.row_cnt += 1;
 //!
 //!             // return the 'Some(())' to Postgres and continue data scan
-//!             return Some(());
+//!             return Ok(Some(()));
 //!         }
 //!
 //!         // return 'None' to stop data scan
-//!         None
+//!         Ok(None)
 //!     }
 //!
-//!     fn end_scan(&mut self) {
+//!     fn end_scan(&mut self) -> FdwResult<()> {
 //!         // we do nothing here, but you can things like resource cleanup and etc.
+//!         Ok(())
 //!     }
 //! }
 //! ```
@@ -198,23 +224,50 @@
 //!
 //! ### Pro Tips
 //!
-//! You can use `EXPLAIN` to check what have been pushed down. For example,
+//! You can use `EXPLAIN (VERBOSE)` to check what have been pushed down. For example,
 //!
 //! ```sql
-//! explain select * from hello where id = 1 order by col limit 1;
+//! explain (verbose) select * from hello where id = 1 order by col limit 1;
 //!
 //!                                                        QUERY PLAN
 //! --------------------------------------------------------------------------------------------------------------------------
 //!  Limit  (cost=1.01..1.01 rows=1 width=40)
+//!    Output: id, col
 //!    ->  Sort  (cost=1.01..1.01 rows=1 width=40)
-//!          Sort Key: col
-//!          ->  Foreign Scan on hello  (cost=0.00..1.00 rows=1 width=0)
-//!                Filter: (id = 1)
+//!          Output: id, col
+//!          Sort Key: hello.col
+//!          ->  Foreign Scan on public.hello  (cost=0.00..1.00 rows=1 width=0)
+//!                Output: id, col
+//!                Filter: (hello.id = 1)
 //!                Wrappers: quals = [Qual { field: "id", operator: "=", value: Cell(I32(1)), use_or: false }]
 //!                Wrappers: tgts = ["id", "col"]
-//!                Wrappers: sorts = [Sort { field: "col", field_no: 2, reversed: false, nulls_first: false, collate: None }]
-//!                Wrappers: limit = Some(Limit { count: 1, offset: 0 })
-//! (9 rows)
+//!                Wrappers: sorts = [Sort { field: "col", field_no: 2, reversed: false, nulls_first: false, collate: Some("default") }]
+//!                Wrappers: limit = None
+//! (13 rows)
+//! ```
+//!
+//! Note the `LIMIT` is not pushed down here, because the qual `id = 1` is
+//! checked by Postgres locally. A FDW can implement `classify_quals` to tell
+//! which quals it enforces remotely.
+//!
+//! A FDW can also implement `explain` to show the query or request sent to
+//! the foreign source, and `remote_stats` to show the request statistics in
+//! `EXPLAIN ANALYZE`.
+//!
+//! The FDW instances are cached in each session and reused by later queries,
+//! they are recreated when the foreign server or user mapping options are
+//! changed. Within a transaction, an instance is shared by the query planning
+//! and taken exclusively by a scan or modify only while it runs.
+//! [`utils::disconnect_server`] drops the cached instances of a foreign
+//! server, which can be exposed as a SQL function,
+//!
+//! ```rust,no_run
+//! use pgx::prelude::*;
+//!
+//! #[pg_extern]
+//! fn my_fdw_disconnect(server: &str) -> bool {
+//!     supabase_wrappers::utils::disconnect_server(server)
+//! }
 //! ```
 //!
 //! ### More FDW Examples
@@ -227,11 +280,15 @@
 //! - [Firebase](https://github.com/supabase/wrappers/tree/main/wrappers/src/fdw/firebase_fdw): A FDW for Google [Firebase](https://firebase.google.com/) which supports data read only.
 //! - [Airtable](https://github.com/supabase/wrappers/tree/main/wrappers/src/fdw/airtable_fdw): A FDW for [Airtable](https://airtable.com/) API which supports data read only.
 
+pub mod deparse;
+pub mod error;
 pub mod interface;
 pub mod utils;
 
 /// The prelude includes all necessary imports to make Wrappers work
 pub mod prelude {
+    pub use crate::deparse::*;
+    pub use crate::error::*;
     pub use crate::interface::*;
     pub use crate::utils::*;
     pub use crate::wrappers_fdw;
@@ -241,13 +298,19 @@ pub mod prelude {
 use pgx::prelude::*;
 use pgx::AllocatedByPostgres;
 
+mod analyze;
+mod coerce;
+mod import_foreign_schema;
 mod instance;
+mod join;
 mod limit;
 mod modify;
 mod polyfill;
 mod qual;
 mod scan;
 mod sort;
+mod upper;
+mod xact;
 
 /// PgBox'ed `FdwRoutine`, used in [`fdw_routine`](interface::ForeignDataWrapper::fdw_routine)
 pub type FdwRoutine<A = AllocatedByPostgres> = PgBox<pg_sys::FdwRoutine, A>;
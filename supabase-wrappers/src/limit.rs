@@ -4,13 +4,26 @@ use pgx::{is_a, pg_sys, FromDatum};
 // extract limit
 pub(crate) unsafe fn extract_limit(
     root: *mut pg_sys::PlannerInfo,
-    _baserel: *mut pg_sys::RelOptInfo,
+    baserel: *mut pg_sys::RelOptInfo,
     _baserel_id: pg_sys::Oid,
 ) -> Option<Limit> {
     let parse = (*root).parse;
 
-    // don't push down LIMIT if the query has a GROUP BY clause or aggregates
-    if !(*parse).groupClause.is_null() || (*parse).hasAggs {
+    // the LIMIT applies to the rows of the whole query, so don't push it down
+    // if the relation is joined with others or is a member of an append
+    // relation, e.g. UNION ALL
+    if !pg_sys::bms_equal((*root).all_baserels, (*baserel).relids) {
+        return None;
+    }
+
+    // don't push down LIMIT if the query has a GROUP BY clause or aggregates,
+    // or other processing that changes the number of rows before the LIMIT
+    if !(*parse).groupClause.is_null()
+        || (*parse).hasAggs
+        || (*parse).hasWindowFuncs
+        || (*parse).hasTargetSRFs
+        || !(*parse).distinctClause.is_null()
+    {
         return None;
     }
 
@@ -3,19 +3,21 @@ use pgx::{
     PgSqlErrorCode,
 };
 use std::collections::HashMap;
-use std::os::raw::c_int;
+use std::os::raw::{c_int, c_void};
 use std::ptr;
 
 use crate::prelude::*;
 
+use super::error::ReportResult;
 use super::instance;
 use super::polyfill;
 use super::utils;
+use super::xact::{self, XactEvent};
 
 // Fdw private state for modify
 struct FdwModifyState<W: ForeignDataWrapper> {
     // foreign data wrapper instance
-    instance: W,
+    instance: instance::FdwInstance<W>,
 
     // row id attribute number and type id
     rowid_name: String,
@@ -30,9 +32,9 @@ struct FdwModifyState<W: ForeignDataWrapper> {
 }
 
 impl<W: ForeignDataWrapper> FdwModifyState<W> {
-    unsafe fn new(foreigntableid: pg_sys::Oid) -> Self {
+    unsafe fn new(foreigntableid: pg_sys::Oid, user_id: pg_sys::Oid) -> Self {
         Self {
-            instance: instance::create_fdw_instance(foreigntableid),
+            instance: instance::create_fdw_instance(foreigntableid, user_id),
             rowid_name: String::default(),
             rowid_attno: 0,
             rowid_typid: 0,
@@ -43,26 +45,44 @@ impl<W: ForeignDataWrapper> FdwModifyState<W> {
     }
 
     fn begin_modify(&mut self) {
-        self.instance.begin_modify(&self.opts);
+        self.instance.begin_modify(&self.opts).report_err()
     }
 
-    fn insert(&mut self, row: &Row) {
-        self.instance.insert(row);
+    fn insert(&mut self, row: &Row) -> Option<Row> {
+        self.instance.insert(row).report_err()
     }
 
-    fn update(&mut self, rowid: &Cell, new_row: &Row) {
-        self.instance.update(rowid, new_row);
+    fn insert_batch(&mut self, rows: &[Row]) {
+        self.instance.insert_batch(rows).report_err()
     }
 
-    fn delete(&mut self, rowid: &Cell) {
-        self.instance.delete(rowid);
+    // get batch size from foreign table options
+    fn batch_size(&self) -> c_int {
+        self.opts
+            .get("batch_size")
+            .map(|s| match s.parse::<c_int>() {
+                Ok(v) if v > 0 => v,
+                _ => {
+                    pgx::error!("invalid option batch_size: {}", s);
+                }
+            })
+            .unwrap_or(1)
+    }
+
+    fn update(&mut self, rowid: &Cell, new_row: &Row) -> Option<Row> {
+        self.instance.update(rowid, new_row).report_err()
+    }
+
+    fn delete(&mut self, rowid: &Cell) -> Option<Row> {
+        self.instance.delete(rowid).report_err()
     }
 
     fn end_modify(&mut self) {
-        self.instance.end_modify();
+        self.instance.end_modify().report_err()
     }
 
     fn clear(&mut self) {
+        self.instance.deactivate();
         self.opts.clear();
         self.opts.shrink_to_fit();
         self.tmp_ctx.reset();
@@ -71,6 +91,22 @@ impl<W: ForeignDataWrapper> FdwModifyState<W> {
 
 impl<W: ForeignDataWrapper> utils::SerdeList for FdwModifyState<W> {}
 
+// notify the instance which has begun modification of a transaction event,
+// the instance may be shared by several modify states of the transaction
+unsafe fn xact_handler<W: ForeignDataWrapper>(instance: *mut c_void, event: XactEvent) {
+    let instance = &mut *(instance as *mut W);
+    match event {
+        XactEvent::PreCommit => instance.on_pre_commit().report_err(),
+        // no two-phase commit support on the foreign sources
+        XactEvent::PrePrepare => report_error(
+            PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+            "cannot PREPARE a transaction that has modified foreign tables",
+        ),
+        XactEvent::Commit => instance.on_commit(),
+        XactEvent::Abort => instance.on_abort(),
+    }
+}
+
 #[pg_guard]
 pub(super) extern "C" fn add_foreign_update_targets(
     root: *mut pg_sys::PlannerInfo,
@@ -83,11 +119,7 @@ pub(super) extern "C" fn add_foreign_update_targets(
         // get rowid column name from table options
         let ftable = pg_sys::GetForeignTable((*target_relation).rd_id);
         let opts = utils::options_to_hashmap((*ftable).options);
-        let rowid_name = if let Some(name) = require_option("rowid_column", &opts) {
-            name
-        } else {
-            return;
-        };
+        let rowid_name = require_option("rowid_column", &opts).report_err();
 
         // find rowid attribute
         let tup_desc = PgTupleDesc::from_pg_copy((*target_relation).rd_att);
@@ -119,19 +151,12 @@ pub(super) extern "C" fn add_foreign_update_targets(
 #[pg_guard]
 pub(super) extern "C" fn plan_foreign_modify<W: ForeignDataWrapper>(
     root: *mut pg_sys::PlannerInfo,
-    plan: *mut pg_sys::ModifyTable,
+    _plan: *mut pg_sys::ModifyTable,
     result_relation: pg_sys::Index,
     _subplan_index: c_int,
 ) -> *mut pg_sys::List {
     debug2!("---> plan_foreign_modify");
     unsafe {
-        if !(*plan).returningLists.is_null() {
-            report_error(
-                PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                "RETURNING is not supported",
-            )
-        }
-
         let rte = pg_sys::planner_rt_fetch(result_relation, root);
 
         // core code already has some lock on each rel being planned, so we can
@@ -157,7 +182,7 @@ pub(super) extern "C" fn plan_foreign_modify<W: ForeignDataWrapper>(
             let attname = pgx::name_data_to_str(&attr.attname);
             if attname == rowid_name {
                 // create modify state
-                let mut state = FdwModifyState::<W>::new(rel.oid());
+                let mut state = FdwModifyState::<W>::new(rel.oid(), (*rte).checkAsUser);
 
                 state.rowid_name = rowid_name.to_string();
                 state.rowid_typid = attr.atttypid;
@@ -204,8 +229,13 @@ pub(super) extern "C" fn begin_foreign_modify<W: ForeignDataWrapper>(
         state.rowid_attno =
             pg_sys::ExecFindJunkAttributeInTlist((*subplan).targetlist, rowid_name_c);
 
+        state.instance.activate();
         state.begin_modify();
 
+        // the instance is kept until the end of transaction to be notified,
+        // and notified as well if current subtransaction is aborted
+        xact::register_subxact_participant(state.instance.as_ptr(), xact_handler::<W>);
+
         (*rinfo).ri_FdwState = state.into_pg() as _;
 
         old_ctx.set_as_current();
@@ -227,8 +257,11 @@ pub(super) extern "C" fn exec_foreign_insert<W: ForeignDataWrapper>(
         state.tmp_ctx.reset();
         let mut old_ctx = state.tmp_ctx.set_as_current();
 
+        // save the returned row into the slot for RETURNING clause
         let row = utils::tuple_table_slot_to_row(slot);
-        state.insert(&row);
+        if let Some(ret_row) = state.insert(&row) {
+            utils::store_row_in_slot(ret_row, slot);
+        }
 
         old_ctx.set_as_current();
     }
@@ -236,6 +269,62 @@ pub(super) extern "C" fn exec_foreign_insert<W: ForeignDataWrapper>(
     slot
 }
 
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn get_foreign_modify_batch_size<W: ForeignDataWrapper>(
+    rinfo: *mut pg_sys::ResultRelInfo,
+) -> c_int {
+    debug2!("---> get_foreign_modify_batch_size");
+    unsafe {
+        let fdw_state = (*rinfo).ri_FdwState as *mut FdwModifyState<W>;
+        if fdw_state.is_null() {
+            return 1;
+        }
+
+        // no batching if the inserted rows are needed one by one
+        let trig_desc = (*rinfo).ri_TrigDesc;
+        if !(*rinfo).ri_projectReturning.is_null()
+            || !(*rinfo).ri_WithCheckOptions.is_null()
+            || (!trig_desc.is_null()
+                && ((*trig_desc).trig_insert_before_row || (*trig_desc).trig_insert_after_row))
+        {
+            return 1;
+        }
+
+        let state = PgBox::<FdwModifyState<W>>::from_pg(fdw_state);
+        state.batch_size()
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn exec_foreign_batch_insert<W: ForeignDataWrapper>(
+    _estate: *mut pg_sys::EState,
+    rinfo: *mut pg_sys::ResultRelInfo,
+    slots: *mut *mut pg_sys::TupleTableSlot,
+    _plan_slots: *mut *mut pg_sys::TupleTableSlot,
+    num_slots: *mut c_int,
+) -> *mut *mut pg_sys::TupleTableSlot {
+    debug2!("---> exec_foreign_batch_insert");
+    unsafe {
+        let mut state =
+            PgBox::<FdwModifyState<W>>::from_pg((*rinfo).ri_FdwState as *mut FdwModifyState<W>);
+
+        state.tmp_ctx.reset();
+        let mut old_ctx = state.tmp_ctx.set_as_current();
+
+        let rows: Vec<Row> = std::slice::from_raw_parts(slots, *num_slots as usize)
+            .iter()
+            .map(|slot| utils::tuple_table_slot_to_row(*slot))
+            .collect();
+        state.insert_batch(&rows);
+
+        old_ctx.set_as_current();
+    }
+
+    slots
+}
+
 unsafe fn get_rowid_cell<W: ForeignDataWrapper>(
     state: &FdwModifyState<W>,
     plan_slot: *mut pg_sys::TupleTableSlot,
@@ -260,9 +349,15 @@ pub(super) extern "C" fn exec_foreign_delete<W: ForeignDataWrapper>(
         state.tmp_ctx.reset();
         let mut old_ctx = state.tmp_ctx.set_as_current();
 
+        // the returning slot may still hold the row of a previous delete, so
+        // it is cleared and the columns not returned by the FDW are null
+        polyfill::exec_clear_tuple(slot);
+
         let cell = get_rowid_cell(&state, plan_slot);
         if let Some(rowid) = cell {
-            state.delete(&rowid);
+            if let Some(ret_row) = state.delete(&rowid) {
+                utils::store_row_in_slot(ret_row, slot);
+            }
         }
 
         old_ctx.set_as_current();
@@ -300,7 +395,9 @@ pub(super) extern "C" fn exec_foreign_update<W: ForeignDataWrapper>(
                 }) && state.rowid_name != col.as_str()
             });
 
-            state.update(&rowid, &new_row);
+            if let Some(ret_row) = state.update(&rowid, &new_row) {
+                utils::store_row_in_slot(ret_row, slot);
+            }
         }
 
         old_ctx.set_as_current();
@@ -318,7 +415,9 @@ pub(super) extern "C" fn end_foreign_modify<W: ForeignDataWrapper>(
     unsafe {
         let fdw_state = (*rinfo).ri_FdwState as *mut FdwModifyState<W>;
         if !fdw_state.is_null() {
-            let mut state = PgBox::<FdwModifyState<W>>::from_rust(fdw_state);
+            // the state is freed with the transaction memory context after
+            // the transaction ends
+            let mut state = PgBox::<FdwModifyState<W>>::from_pg(fdw_state);
             state.end_modify();
             state.clear();
         }
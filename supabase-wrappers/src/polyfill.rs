@@ -34,3 +34,17 @@ pub(super) unsafe fn slot_getattr(
 pub(super) unsafe fn outer_plan_state(node: *mut pg_sys::PlanState) -> *mut pg_sys::PlanState {
     (*node).lefttree
 }
+
+// syscache invalidation callback, which is not in the bindings
+pub(super) type SyscacheCallbackFunction =
+    Option<unsafe extern "C" fn(arg: Datum, cacheid: c_int, hashvalue: u32)>;
+
+// it raises FATAL only, which exits the backend without unwinding, so it needs
+// no guard
+extern "C" {
+    pub(super) fn CacheRegisterSyscacheCallback(
+        cacheid: c_int,
+        func: SyscacheCallbackFunction,
+        arg: Datum,
+    );
+}
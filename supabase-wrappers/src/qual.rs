@@ -1,3 +1,4 @@
+use crate::coerce;
 use crate::prelude::*;
 use pgx::{is_a, pg_sys, pg_sys::Datum, FromDatum, PgBuiltInOids, PgList, PgOid};
 use std::ffi::CStr;
@@ -36,18 +37,45 @@ pub(crate) unsafe fn form_array_from_datum(
         PgOid::BuiltIn(PgBuiltInOids::INT8ARRAYOID) => {
             Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::INT8OID)
         }
+        PgOid::BuiltIn(PgBuiltInOids::NUMERICARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::NUMERICOID)
+        }
         PgOid::BuiltIn(PgBuiltInOids::TEXTARRAYOID) => {
             Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::TEXTOID)
         }
+        PgOid::BuiltIn(PgBuiltInOids::VARCHARARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::VARCHAROID)
+        }
+        PgOid::BuiltIn(PgBuiltInOids::BPCHARARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::BPCHAROID)
+        }
         PgOid::BuiltIn(PgBuiltInOids::DATEARRAYOID) => {
             Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::DATEOID)
         }
+        PgOid::BuiltIn(PgBuiltInOids::TIMEARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::TIMEOID)
+        }
         PgOid::BuiltIn(PgBuiltInOids::TIMESTAMPARRAYOID) => {
             Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::TIMESTAMPOID)
         }
+        PgOid::BuiltIn(PgBuiltInOids::TIMESTAMPTZARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::TIMESTAMPTZOID)
+        }
+        PgOid::BuiltIn(PgBuiltInOids::INTERVALARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::INTERVALOID)
+        }
+        PgOid::BuiltIn(PgBuiltInOids::UUIDARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::UUIDOID)
+        }
+        PgOid::BuiltIn(PgBuiltInOids::BYTEAARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::BYTEAOID)
+        }
         PgOid::BuiltIn(PgBuiltInOids::JSONBARRAYOID) => {
             Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::JSONBOID)
         }
+        PgOid::BuiltIn(PgBuiltInOids::JSONARRAYOID) => {
+            Vec::<Cell>::from_polymorphic_datum(datum, false, pg_sys::JSONOID)
+        }
         _ => None,
     }
 }
@@ -76,11 +104,96 @@ pub(crate) unsafe fn unnest_clause(node: *mut pg_sys::Node) -> *mut pg_sys::Node
     }
 }
 
+// check if a node is a column of the base relation
+unsafe fn is_rel_column(node: *mut pg_sys::Node, baserel_ids: pg_sys::Relids) -> bool {
+    if !is_a(node, pg_sys::NodeTag_T_Var) {
+        return false;
+    }
+    let var = node as *mut pg_sys::Var;
+    (*var).varlevelsup == 0
+        && (*var).varattno >= 1
+        && pg_sys::bms_is_member((*var).varno as c_int, baserel_ids)
+}
+
+// check if a value of the type can be converted to a Cell
+pub(crate) fn is_cell_type(typoid: pg_sys::Oid) -> bool {
+    [
+        pg_sys::BOOLOID,
+        pg_sys::CHAROID,
+        pg_sys::INT2OID,
+        pg_sys::FLOAT4OID,
+        pg_sys::INT4OID,
+        pg_sys::FLOAT8OID,
+        pg_sys::INT8OID,
+        pg_sys::NUMERICOID,
+        pg_sys::TEXTOID,
+        pg_sys::VARCHAROID,
+        pg_sys::BPCHAROID,
+        pg_sys::DATEOID,
+        pg_sys::TIMEOID,
+        pg_sys::TIMESTAMPOID,
+        pg_sys::TIMESTAMPTZOID,
+        pg_sys::INTERVALOID,
+        pg_sys::UUIDOID,
+        pg_sys::BYTEAOID,
+        pg_sys::JSONBOID,
+        pg_sys::JSONOID,
+    ]
+    .contains(&typoid)
+}
+
+// check if a node is a value only known at execution time, either a query
+// parameter or a column of another relation
+unsafe fn is_param(node: *mut pg_sys::Node, baserel_ids: pg_sys::Relids) -> bool {
+    if !is_cell_type(pg_sys::exprType(node)) {
+        return false;
+    }
+    if is_a(node, pg_sys::NodeTag_T_Param) {
+        let param = node as *mut pg_sys::Param;
+        return (*param).paramkind == pg_sys::ParamKind_PARAM_EXTERN
+            || (*param).paramkind == pg_sys::ParamKind_PARAM_EXEC;
+    }
+    if is_a(node, pg_sys::NodeTag_T_Var) {
+        let var = node as *mut pg_sys::Var;
+        return (*var).varlevelsup == 0
+            && !pg_sys::bms_is_member((*var).varno as c_int, baserel_ids);
+    }
+    false
+}
+
+// extract the value side of a restriction, either a constant or a parameter
+unsafe fn extract_value(
+    node: *mut pg_sys::Node,
+    baserel_ids: pg_sys::Relids,
+    params: &mut Vec<*mut pg_sys::Node>,
+) -> Option<Value> {
+    if is_a(node, pg_sys::NodeTag_T_Const) {
+        let node = node as *mut pg_sys::Const;
+        Some(Value::Cell(Cell::from_polymorphic_datum(
+            (*node).constvalue,
+            (*node).constisnull,
+            (*node).consttype,
+        )?))
+    } else if is_param(node, baserel_ids) {
+        // the parameter value is evaluated at execution time
+        let param = Param {
+            id: params.len(),
+            type_oid: pg_sys::exprType(node),
+            value: None,
+        };
+        params.push(node);
+        Some(Value::Param(param))
+    } else {
+        None
+    }
+}
+
 pub(crate) unsafe fn extract_from_op_expr(
     _root: *mut pg_sys::PlannerInfo,
     baserel_id: pg_sys::Oid,
     baserel_ids: pg_sys::Relids,
     expr: *mut pg_sys::OpExpr,
+    params: &mut Vec<*mut pg_sys::Node>,
 ) -> Option<Qual> {
     let args: PgList<pg_sys::Node> = PgList::from_pg((*expr).args);
 
@@ -89,48 +202,184 @@ pub(crate) unsafe fn extract_from_op_expr(
         return None;
     }
 
+    let mut left = unnest_clause(args.head().unwrap());
+    let mut right = unnest_clause(args.tail().unwrap());
+    let mut opno = (*expr).opno;
+
+    // swap operands if needed, using the commutator operator
+    if is_rel_column(right, baserel_ids) && !is_rel_column(left, baserel_ids) {
+        opno = pg_sys::get_commutator(opno);
+        if opno == pg_sys::InvalidOid {
+            return None;
+        }
+        std::mem::swap(&mut left, &mut right);
+    }
+
+    if !is_rel_column(left, baserel_ids) {
+        return None;
+    }
+
     // get operator
-    let opno = (*expr).opno;
     let opr = get_operator(opno);
     if opr.is_null() {
         return None;
     }
 
-    let mut left = unnest_clause(args.head().unwrap());
-    let mut right = unnest_clause(args.tail().unwrap());
+    let left = left as *mut pg_sys::Var;
+    let value = extract_value(right, baserel_ids, params)?;
 
-    // swap operands if needed
-    if is_a(right, pg_sys::NodeTag_T_Var)
-        && !is_a(left, pg_sys::NodeTag_T_Var)
-        && (*opr).oprcom != 0
-    {
-        std::mem::swap(&mut left, &mut right);
+    let field = pg_sys::get_attname(baserel_id, (*left).varattno, false);
+    let qual = Qual {
+        field: CStr::from_ptr(field).to_str().unwrap().to_string(),
+        operator: pgx::name_data_to_str(&(*opr).oprname).to_string(),
+        value,
+        use_or: false,
+    };
+    Some(qual)
+}
+
+// `a is distinct from b` is represented as an OpExpr of the equality operator,
+// and `a is not distinct from b` is the negation of it
+pub(crate) unsafe fn extract_from_distinct_expr(
+    root: *mut pg_sys::PlannerInfo,
+    baserel_id: pg_sys::Oid,
+    baserel_ids: pg_sys::Relids,
+    expr: *mut pg_sys::DistinctExpr,
+    negated: bool,
+    params: &mut Vec<*mut pg_sys::Node>,
+) -> Option<Qual> {
+    let mut qual = extract_from_op_expr(root, baserel_id, baserel_ids, expr, params)?;
+    if qual.operator != "=" {
+        return None;
     }
+    qual.operator = if negated {
+        "is not distinct from".to_string()
+    } else {
+        "is distinct from".to_string()
+    };
+    Some(qual)
+}
 
-    if is_a(left, pg_sys::NodeTag_T_Var) && is_a(right, pg_sys::NodeTag_T_Const) {
-        let left = left as *mut pg_sys::Var;
-        let right = right as *mut pg_sys::Const;
+// extract a function call or type cast of columns, all of its arguments must
+// be columns of the base relation, constants or such expressions
+unsafe fn extract_field_expr(
+    baserel_id: pg_sys::Oid,
+    baserel_ids: pg_sys::Relids,
+    node: *mut pg_sys::Node,
+) -> Option<FieldExpr> {
+    let node = unnest_clause(node);
+    if is_rel_column(node, baserel_ids) {
+        let var = node as *mut pg_sys::Var;
+        let field = pg_sys::get_attname(baserel_id, (*var).varattno, false);
+        Some(FieldExpr::Column(
+            CStr::from_ptr(field).to_str().unwrap().to_string(),
+        ))
+    } else if is_a(node, pg_sys::NodeTag_T_Const) {
+        let node = node as *mut pg_sys::Const;
+        Some(FieldExpr::Const(Cell::from_polymorphic_datum(
+            (*node).constvalue,
+            (*node).constisnull,
+            (*node).consttype,
+        )?))
+    } else if is_a(node, pg_sys::NodeTag_T_FuncExpr) {
+        let func = node as *mut pg_sys::FuncExpr;
+
+        // user defined functions may shadow the built-in ones
+        if pg_sys::get_func_namespace((*func).funcid) != pg_sys::PG_CATALOG_NAMESPACE {
+            return None;
+        }
 
-        if pg_sys::bms_is_member((*left).varno as c_int, baserel_ids) && (*left).varattno >= 1 {
-            let field = pg_sys::get_attname(baserel_id, (*left).varattno, false);
-            let value = Cell::from_polymorphic_datum(
-                (*right).constvalue,
-                (*right).constisnull,
-                (*right).consttype,
-            );
-            if let Some(value) = value {
-                let qual = Qual {
-                    field: CStr::from_ptr(field).to_str().unwrap().to_string(),
-                    operator: pgx::name_data_to_str(&(*opr).oprname).to_string(),
-                    value: Value::Cell(value),
-                    use_or: false,
-                };
-                return Some(qual);
+        let args: PgList<pg_sys::Node> = PgList::from_pg((*func).args);
+        let mut args = args
+            .iter_ptr()
+            .map(|arg| extract_field_expr(baserel_id, baserel_ids, arg))
+            .collect::<Option<Vec<FieldExpr>>>()?;
+        let is_cast = (*func).funcformat == pg_sys::CoercionForm_COERCE_EXPLICIT_CAST
+            || (*func).funcformat == pg_sys::CoercionForm_COERCE_IMPLICIT_CAST;
+        if is_cast {
+            // length coercion casts, e.g. `::varchar(10)`, are not supported
+            if args.len() != 1 {
+                return None;
             }
+            Some(FieldExpr::Cast {
+                expr: Box::new(args.remove(0)),
+                type_name: coerce::type_name((*func).funcresulttype),
+            })
+        } else {
+            let name = pg_sys::get_func_name((*func).funcid);
+            Some(FieldExpr::Func {
+                name: CStr::from_ptr(name).to_str().unwrap().to_string(),
+                args,
+            })
         }
+    } else if is_a(node, pg_sys::NodeTag_T_CoerceViaIO) {
+        let coerce = node as *mut pg_sys::CoerceViaIO;
+        Some(FieldExpr::Cast {
+            expr: Box::new(extract_field_expr(
+                baserel_id,
+                baserel_ids,
+                (*coerce).arg as _,
+            )?),
+            type_name: coerce::type_name((*coerce).resulttype),
+        })
+    } else {
+        None
     }
+}
 
-    None
+// extract a restriction on a function call or type cast of columns, e.g.
+// `lower(email) = 'foo@bar.com'`
+pub(crate) unsafe fn extract_from_func_op_expr(
+    baserel_id: pg_sys::Oid,
+    baserel_ids: pg_sys::Relids,
+    expr: *mut pg_sys::OpExpr,
+    params: &mut Vec<*mut pg_sys::Node>,
+) -> Option<ExprQual> {
+    let args: PgList<pg_sys::Node> = PgList::from_pg((*expr).args);
+
+    // only deal with binary operator
+    if args.len() != 2 {
+        return None;
+    }
+
+    let left = args.head().unwrap();
+    let right = args.tail().unwrap();
+    let mut opno = (*expr).opno;
+
+    let is_field = |node| {
+        extract_field_expr(baserel_id, baserel_ids, node).filter(|e| !e.columns().is_empty())
+    };
+
+    // swap operands if needed, using the commutator operator
+    let (field, value) = match (is_field(left), is_field(right)) {
+        (Some(field), None) => (field, right),
+        (None, Some(field)) => {
+            opno = pg_sys::get_commutator(opno);
+            if opno == pg_sys::InvalidOid {
+                return None;
+            }
+            (field, left)
+        }
+        _ => return None,
+    };
+
+    // the remote side cannot evaluate the expressions which depend on session
+    // settings, e.g. `date_trunc('day', timestamptz_col)` depends on time zone
+    if pg_sys::contain_mutable_functions(expr as _) {
+        return None;
+    }
+
+    let opr = get_operator(opno);
+    if opr.is_null() {
+        return None;
+    }
+
+    let value = extract_value(unnest_clause(value), baserel_ids, params)?;
+    Some(ExprQual {
+        field,
+        operator: pgx::name_data_to_str(&(*opr).oprname).to_string(),
+        value,
+    })
 }
 
 pub(crate) unsafe fn extract_from_null_test(
@@ -236,10 +485,11 @@ pub(crate) unsafe fn extract_from_var(
 }
 
 pub(crate) unsafe fn extract_from_bool_expr(
-    _root: *mut pg_sys::PlannerInfo,
+    root: *mut pg_sys::PlannerInfo,
     baserel_id: pg_sys::Oid,
     baserel_ids: pg_sys::Relids,
     expr: *mut pg_sys::BoolExpr,
+    params: &mut Vec<*mut pg_sys::Node>,
 ) -> Option<Qual> {
     let args: PgList<pg_sys::Node> = PgList::from_pg((*expr).args);
 
@@ -247,8 +497,14 @@ pub(crate) unsafe fn extract_from_bool_expr(
         return None;
     }
 
-    let var = args.head().unwrap() as *mut pg_sys::Var;
-    if (*var).varattno < 1
+    let arg = args.head().unwrap();
+    if is_a(arg, pg_sys::NodeTag_T_DistinctExpr) {
+        return extract_from_distinct_expr(root, baserel_id, baserel_ids, arg as _, true, params);
+    }
+
+    let var = arg as *mut pg_sys::Var;
+    if !is_a(var as _, pg_sys::NodeTag_T_Var)
+        || (*var).varattno < 1
         || (*var).vartype != pg_sys::BOOLOID
         || !pg_sys::bms_is_member((*var).varno as c_int, baserel_ids)
     {
@@ -267,37 +523,183 @@ pub(crate) unsafe fn extract_from_bool_expr(
     Some(qual)
 }
 
+// extract a qual from a simple restriction expression
+unsafe fn extract_qual(
+    root: *mut pg_sys::PlannerInfo,
+    baserel_id: pg_sys::Oid,
+    baserel_ids: pg_sys::Relids,
+    expr: *mut pg_sys::Node,
+    params: &mut Vec<*mut pg_sys::Node>,
+) -> Option<Qual> {
+    if is_a(expr, pg_sys::NodeTag_T_OpExpr) {
+        extract_from_op_expr(root, baserel_id, baserel_ids, expr as _, params)
+    } else if is_a(expr, pg_sys::NodeTag_T_DistinctExpr) {
+        extract_from_distinct_expr(root, baserel_id, baserel_ids, expr as _, false, params)
+    } else if is_a(expr, pg_sys::NodeTag_T_NullTest) {
+        extract_from_null_test(baserel_id, expr as _)
+    } else if is_a(expr, pg_sys::NodeTag_T_ScalarArrayOpExpr) {
+        extract_from_scalar_array_op_expr(root, baserel_id, baserel_ids, expr as _)
+    } else if is_a(expr, pg_sys::NodeTag_T_Var) {
+        extract_from_var(root, baserel_id, baserel_ids, expr as _)
+    } else if is_a(expr, pg_sys::NodeTag_T_BoolExpr) {
+        extract_from_bool_expr(root, baserel_id, baserel_ids, expr as _, params)
+    } else {
+        None
+    }
+}
+
+// extract a boolean expression tree, all of its leaves must be simple
+// restriction expressions
+unsafe fn extract_qual_expr(
+    root: *mut pg_sys::PlannerInfo,
+    baserel_id: pg_sys::Oid,
+    baserel_ids: pg_sys::Relids,
+    expr: *mut pg_sys::Node,
+    params: &mut Vec<*mut pg_sys::Node>,
+) -> Option<QualExpr> {
+    if let Some(qual) = extract_qual(root, baserel_id, baserel_ids, expr, params) {
+        return Some(QualExpr::Leaf(qual));
+    }
+    if is_a(expr, pg_sys::NodeTag_T_OpExpr) {
+        return extract_from_func_op_expr(baserel_id, baserel_ids, expr as _, params)
+            .map(QualExpr::Expr);
+    }
+    if !is_a(expr, pg_sys::NodeTag_T_BoolExpr) {
+        return None;
+    }
+
+    let expr = expr as *mut pg_sys::BoolExpr;
+    let args: PgList<pg_sys::Node> = PgList::from_pg((*expr).args);
+    let mut exprs = args
+        .iter_ptr()
+        .map(|arg| extract_qual_expr(root, baserel_id, baserel_ids, arg, params))
+        .collect::<Option<Vec<QualExpr>>>()?;
+    match (*expr).boolop {
+        pg_sys::BoolExprType_AND_EXPR => Some(QualExpr::And(exprs)),
+        pg_sys::BoolExprType_OR_EXPR => Some(QualExpr::Or(exprs)),
+        pg_sys::BoolExprType_NOT_EXPR if exprs.len() == 1 => {
+            Some(QualExpr::Not(Box::new(exprs.remove(0))))
+        }
+        _ => None,
+    }
+}
+
+// restrictions extracted from the base relation, along with the restriction
+// clause each of them is extracted from
+#[derive(Default)]
+pub(crate) struct ExtractedQuals {
+    // simple restrictions
+    pub quals: Vec<Qual>,
+    pub qual_conds: Vec<*mut pg_sys::RestrictInfo>,
+
+    // boolean expression restrictions which cannot be flattened
+    pub exprs: Vec<QualExpr>,
+    pub expr_conds: Vec<*mut pg_sys::RestrictInfo>,
+}
+
 pub(crate) unsafe fn extract_quals(
     root: *mut pg_sys::PlannerInfo,
     baserel: *mut pg_sys::RelOptInfo,
     baserel_id: pg_sys::Oid,
-) -> Vec<Qual> {
-    let mut quals = Vec::new();
+    params: &mut Vec<*mut pg_sys::Node>,
+) -> ExtractedQuals {
+    let mut ret = ExtractedQuals::default();
 
     let conds = PgList::<pg_sys::RestrictInfo>::from_pg((*baserel).baserestrictinfo);
     for cond in conds.iter_ptr() {
         let expr = (*cond).clause as *mut pg_sys::Node;
-        let extracted = if is_a(expr, pg_sys::NodeTag_T_OpExpr) {
-            extract_from_op_expr(root, baserel_id, (*baserel).relids, expr as _)
-        } else if is_a(expr, pg_sys::NodeTag_T_NullTest) {
-            extract_from_null_test(baserel_id, expr as _)
-        } else if is_a(expr, pg_sys::NodeTag_T_ScalarArrayOpExpr) {
-            extract_from_scalar_array_op_expr(root, baserel_id, (*baserel).relids, expr as _)
-        } else if is_a(expr, pg_sys::NodeTag_T_Var) {
-            extract_from_var(root, baserel_id, (*baserel).relids, expr as _)
-        } else if is_a(expr, pg_sys::NodeTag_T_BoolExpr) {
-            extract_from_bool_expr(root, baserel_id, (*baserel).relids, expr as _)
-        } else {
-            if let Some(stm) = pgx::nodes::node_to_string(expr) {
-                report_warning(&format!("unsupported qual: {}", stm));
+        if let Some(qual) = extract_qual(root, baserel_id, (*baserel).relids, expr, params) {
+            ret.quals.push(qual);
+            ret.qual_conds.push(cond);
+            continue;
+        }
+
+        // discard the parameters of a partially extracted expression
+        let params_len = params.len();
+        if let Some(expr) = extract_qual_expr(root, baserel_id, (*baserel).relids, expr, params) {
+            ret.exprs.push(expr);
+            ret.expr_conds.push(cond);
+            continue;
+        }
+        params.truncate(params_len);
+
+        if let Some(stm) = pgx::nodes::node_to_string(expr) {
+            report_warning(&format!("unsupported qual: {}", stm));
+        }
+    }
+
+    ret
+}
+
+// extract the join conditions of a parameterized scan, whose values are the
+// columns of the outer relations
+pub(crate) unsafe fn extract_param_quals(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    baserel_id: pg_sys::Oid,
+    clauses: *mut pg_sys::List,
+    params: &mut Vec<*mut pg_sys::Node>,
+) -> Vec<Qual> {
+    let conds = PgList::<pg_sys::RestrictInfo>::from_pg(clauses);
+    conds
+        .iter_ptr()
+        .filter_map(|cond| {
+            let expr = (*cond).clause as *mut pg_sys::Node;
+            if !is_a(expr, pg_sys::NodeTag_T_OpExpr) {
+                return None;
             }
-            None
-        };
+            extract_from_op_expr(root, baserel_id, (*baserel).relids, expr as _, params)
+        })
+        .collect()
+}
+
+// collect the sets of outer relations which the join conditions of the base
+// relation depend on, each of them can be used to create a parameterized path
+pub(crate) unsafe fn extract_param_outer_relids(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+) -> Vec<pg_sys::Relids> {
+    let mut ret: Vec<pg_sys::Relids> = Vec::new();
+    let mut add_relids = |relids: pg_sys::Relids| {
+        if pg_sys::bms_is_empty(relids)
+            || pg_sys::bms_overlap(relids, (*baserel).lateral_referencers)
+            || ret.iter().any(|r| pg_sys::bms_equal(*r, relids))
+        {
+            return;
+        }
+        ret.push(relids);
+    };
+
+    // join clauses not in equivalence classes
+    let conds = PgList::<pg_sys::RestrictInfo>::from_pg((*baserel).joininfo);
+    for cond in conds.iter_ptr() {
+        if pg_sys::join_clause_is_movable_to(cond, baserel) {
+            add_relids(pg_sys::bms_difference(
+                (*cond).clause_relids,
+                (*baserel).relids,
+            ));
+        }
+    }
 
-        if let Some(qual) = extracted {
-            quals.push(qual);
+    // equivalence classes, e.g. `a.x = b.y`, their join clauses are generated
+    // when building the parameterized path info
+    if (*baserel).has_eclass_joins {
+        let ecs = PgList::<pg_sys::EquivalenceClass>::from_pg((*root).eq_classes);
+        for ec in ecs.iter_ptr() {
+            if (*ec).ec_has_const
+                || (*ec).ec_has_volatile
+                || !pg_sys::bms_is_subset((*baserel).relids, (*ec).ec_relids)
+            {
+                continue;
+            }
+            let ems = PgList::<pg_sys::EquivalenceMember>::from_pg((*ec).ec_members);
+            for em in ems.iter_ptr() {
+                if !(*em).em_is_child && !pg_sys::bms_overlap((*em).em_relids, (*baserel).relids) {
+                    add_relids((*em).em_relids);
+                }
+            }
         }
     }
 
-    quals
+    ret
 }
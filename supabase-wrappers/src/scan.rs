@@ -1,39 +1,178 @@
-use pgx::{debug2, memcxt::PgMemoryContexts, pg_sys::Datum, prelude::*, IntoDatum, PgSqlErrorCode};
+use pgx::{
+    debug2, is_a, memcxt::PgMemoryContexts, pg_sys::Datum, prelude::*, FromDatum, IntoDatum,
+    PgList, PgSqlErrorCode, PgTupleDesc,
+};
 use std::collections::HashMap;
 
-use std::os::raw::c_int;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::coerce;
+use crate::error::{FdwError, FdwResult, ReportResult};
 use crate::instance;
-use crate::interface::{Limit, Qual, Row, Sort};
+use crate::interface::{
+    Aggregation, AsyncNotifier, Cell, DirectModify, Join, JoinTable, JoinType, Limit, Qual,
+    QualExpr, QualPushdown, RemoteStats, Row, ScanEstimate, Sort, Value,
+};
+use crate::join::*;
 use crate::limit::*;
 use crate::polyfill;
 use crate::prelude::ForeignDataWrapper;
 use crate::qual::*;
 use crate::sort::*;
-use crate::utils::{self, report_error, SerdeList};
+use crate::upper::*;
+use crate::utils::{self, SerdeList};
+
+// default cost factors, same as postgres_fdw's
+const DEFAULT_FDW_STARTUP_COST: f64 = 100.0;
+const DEFAULT_FDW_TUPLE_COST: f64 = 0.01;
+
+// extra cost factor of a remotely sorted scan, same as postgres_fdw's
+const DEFAULT_FDW_SORT_MULTIPLIER: f64 = 1.2;
+
+// parse a cost option, raise an error if it is not a non-negative number
+fn cost_option(opts: &HashMap<String, String>, name: &str) -> Option<f64> {
+    opts.get(name).map(|c| match c.parse::<f64>() {
+        Ok(v) if v >= 0.0 => v,
+        _ => {
+            pgx::error!("invalid option {}: {}", name, c);
+        }
+    })
+}
+
+// parse a boolean option, raise an error if it is not a boolean, which should
+// have been rejected by the validator
+fn bool_option(opts: &HashMap<String, String>, name: &str) -> Option<bool> {
+    opts.get(name).map(|v| {
+        utils::parse_bool_option(v).unwrap_or_else(|| {
+            pgx::error!("invalid option {}: {}", name, v);
+        })
+    })
+}
+
+// expected number of rows processed by each parallel process, same as the
+// planner's get_parallel_divisor()
+fn parallel_divisor(workers: c_int) -> f64 {
+    let mut divisor = workers as f64;
+    if unsafe { pg_sys::parallel_leader_participation } {
+        let leader_contribution = 1.0 - 0.3 * workers as f64;
+        if leader_contribution > 0.0 {
+            divisor += leader_contribution;
+        }
+    }
+    divisor
+}
+
+// make a list of constants, which is copied to the parallel workers along
+// with the plan
+unsafe fn values_to_list<T: IntoDatum + Clone>(values: &[T]) -> *mut pg_sys::List {
+    let mut typlen = 0;
+    let mut typbyval = false;
+    pg_sys::get_typlenbyval(T::type_oid(), &mut typlen, &mut typbyval);
+    let mut list = PgList::<pg_sys::Const>::new();
+    for value in values {
+        list.push(pg_sys::makeConst(
+            T::type_oid(),
+            -1,
+            pg_sys::InvalidOid,
+            typlen as c_int,
+            value.clone().into_datum().unwrap(),
+            false,
+            typbyval,
+        ));
+    }
+    list.into_pg()
+}
+
+unsafe fn list_to_values<T: FromDatum>(list: *mut pg_sys::List) -> Vec<T> {
+    PgList::<pg_sys::Const>::from_pg(list)
+        .iter_ptr()
+        .filter_map(|cst| T::from_datum((*cst).constvalue, (*cst).constisnull))
+        .collect()
+}
 
 // Fdw private state for scan
 struct FdwState<W: ForeignDataWrapper> {
-    // foreign data wrapper instance
-    instance: W,
+    // foreign data wrapper instance, and its remote stats when the state is
+    // created, as a cached instance may have been used by previous queries
+    instance: instance::FdwInstance<W>,
+    stats_base: RemoteStats,
 
-    // query conditions
+    // query conditions, and the boolean expression conditions which cannot
+    // be flattened
     quals: Vec<Qual>,
+    qual_exprs: Vec<QualExpr>,
+
+    // restrictions enforced by the foreign source, which need no local recheck
+    remote_conds: Vec<*mut pg_sys::RestrictInfo>,
 
     // query target column list
     tgts: Vec<String>,
     tgt_attnos: Vec<usize>,
 
+    // query parameter expressions, referred by index from the parameters
+    // in quals
+    params: Vec<*mut pg_sys::Node>,
+
+    // join conditions of a parameterized scan
+    param_quals: Vec<Qual>,
+
+    // parameter expression states for evaluation, and whether the scan is
+    // pending to begin with newly evaluated parameters, or the direct modify
+    // is pending to execute
+    param_exprs: Vec<*mut pg_sys::ExprState>,
+    scan_pending: bool,
+
+    // whether the scan is executed asynchronously by an Append, and the
+    // socket to wait on until the asynchronous scan is ready
+    is_async: bool,
+    async_wait: Option<UnixStream>,
+
     // sort list
     sorts: Vec<Sort>,
 
     // limit
     limit: Option<Limit>,
 
+    // pushed down join, the target columns are qualified with table alias
+    join: Option<Join>,
+
+    // pushed down aggregation, the target columns are group by columns and
+    // deparsed aggregates
+    aggregation: Option<Aggregation>,
+
+    // pushed down UPDATE or DELETE
+    direct_modify: Option<DirectModify>,
+
     // foreign table options
     opts: HashMap<String, String>,
 
+    // foreign server options
+    server_opts: HashMap<String, String>,
+
+    // size and cost estimate of the scan
+    estimate: ScanEstimate,
+
+    // EXPLAIN properties of the scan given by the FDW when it is planned, for
+    // plain and verbose EXPLAIN, as the instance may be shared with the other
+    // scans until it is taken for execution
+    planned_explain: [Vec<(String, String)>; 2],
+
+    // partitions of a parallel scan, and whether the scan is split into them
+    // and is in one of them
+    partitions: Vec<String>,
+    is_partitioned: bool,
+    in_partition: bool,
+
+    // index of the next partition to be claimed, which is in the dynamic
+    // shared memory if the partitions are shared by the parallel workers
+    next_partition: usize,
+    shared_next_partition: *mut AtomicUsize,
+
     // temporary memory context
     tmp_ctx: PgMemoryContexts,
 
@@ -44,15 +183,39 @@ struct FdwState<W: ForeignDataWrapper> {
 }
 
 impl<W: ForeignDataWrapper> FdwState<W> {
-    unsafe fn new(foreigntableid: pg_sys::Oid) -> Self {
+    unsafe fn new(foreigntableid: pg_sys::Oid, user_id: pg_sys::Oid) -> Self {
+        Self::from_instance(instance::create_fdw_instance(foreigntableid, user_id))
+    }
+
+    fn from_instance(instance: instance::FdwInstance<W>) -> Self {
         Self {
-            instance: instance::create_fdw_instance(foreigntableid),
+            stats_base: instance.remote_stats().unwrap_or_default(),
+            instance,
             quals: Vec::new(),
+            qual_exprs: Vec::new(),
+            remote_conds: Vec::new(),
             tgts: Vec::new(),
             tgt_attnos: Vec::new(),
+            params: Vec::new(),
+            param_quals: Vec::new(),
+            param_exprs: Vec::new(),
+            scan_pending: false,
+            is_async: false,
+            async_wait: None,
             sorts: Vec::new(),
             limit: None,
+            join: None,
+            aggregation: None,
+            direct_modify: None,
             opts: HashMap::new(),
+            server_opts: HashMap::new(),
+            estimate: ScanEstimate::default(),
+            planned_explain: Default::default(),
+            partitions: Vec::new(),
+            is_partitioned: false,
+            in_partition: false,
+            next_partition: 0,
+            shared_next_partition: ptr::null_mut(),
             tmp_ctx: PgMemoryContexts::CurTransactionContext
                 .switch_to(|_| PgMemoryContexts::new("Wrappers temp data")),
             values: Vec::new(),
@@ -61,50 +224,312 @@ impl<W: ForeignDataWrapper> FdwState<W> {
         }
     }
 
-    fn get_rel_size(&mut self) -> (i64, i32) {
-        self.instance.get_rel_size(
-            &self.quals,
-            &self.tgts,
-            &self.sorts,
-            &self.limit,
-            &self.opts,
+    // rebuild the scan state in a parallel worker from the plan's private
+    // list, the serialized state made by the planner is not accessible
+    unsafe fn from_parallel_list(
+        foreigntableid: pg_sys::Oid,
+        user_id: pg_sys::Oid,
+        list: *mut pg_sys::List,
+    ) -> Self {
+        let mut state = Self::new(foreigntableid, user_id);
+        let ftable = pg_sys::GetForeignTable(foreigntableid);
+        state.opts = utils::options_to_hashmap((*ftable).options);
+
+        let private = PgList::<pg_sys::List>::from_pg(list);
+        state.tgts = list_to_values(private.get_ptr(1).unwrap());
+        state.tgt_attnos = list_to_values::<i32>(private.get_ptr(2).unwrap())
+            .into_iter()
+            .map(|attno| attno as usize)
+            .collect();
+        state.partitions = list_to_values(private.get_ptr(3).unwrap());
+        state
+    }
+
+    // lists of the target columns and partitions, which are read back by
+    // from_parallel_list()
+    unsafe fn parallel_lists(&self) -> Vec<*mut pg_sys::List> {
+        let attnos: Vec<i32> = self.tgt_attnos.iter().map(|attno| *attno as i32).collect();
+        vec![
+            values_to_list(&self.tgts),
+            values_to_list(&attnos),
+            values_to_list(&self.partitions),
+        ]
+    }
+
+    fn get_rel_size(&mut self) -> ScanEstimate {
+        self.instance
+            .get_rel_size(
+                &self.quals,
+                &self.tgts,
+                &self.sorts,
+                &self.limit,
+                &self.opts,
+            )
+            .report_err()
+    }
+
+    // save the EXPLAIN properties of the scan just planned by the instance
+    fn save_explain(&mut self) {
+        self.planned_explain = [self.instance.explain(false), self.instance.explain(true)];
+    }
+
+    // classify the quals and boolean expressions, returns the pushdown of each
+    // of them in the same order
+    fn classify_quals(&mut self) -> (Vec<QualPushdown>, Vec<QualPushdown>) {
+        self.instance.set_qual_exprs(&self.qual_exprs);
+        (
+            self.instance.classify_quals(&self.quals, &self.opts),
+            self.instance
+                .classify_qual_exprs(&self.qual_exprs, &self.opts),
         )
     }
 
+    // take the instance for execution, as it may be shared with the other
+    // states during planning
+    unsafe fn activate(&mut self) {
+        self.instance.activate();
+        self.stats_base = self.instance.remote_stats().unwrap_or_default();
+    }
+
     fn begin_scan(&mut self) {
-        self.instance.begin_scan(
-            &self.quals,
-            &self.tgts,
-            &self.sorts,
-            &self.limit,
-            &self.opts,
-        )
+        if let Some(join) = &self.join {
+            self.instance.begin_join_scan(join, &self.tgts).report_err();
+            return;
+        }
+        if let Some(aggregation) = &self.aggregation {
+            self.instance
+                .begin_aggregate_scan(aggregation, &self.tgts)
+                .report_err();
+            return;
+        }
+        let quals = [self.quals.as_slice(), self.param_quals.as_slice()].concat();
+        self.instance.set_qual_exprs(&self.qual_exprs);
+        self.instance
+            .begin_scan(&quals, &self.tgts, &self.sorts, &self.limit, &self.opts)
+            .report_err()
+    }
+
+    // begin the scan asynchronously, returns false if it is not started and
+    // should begin synchronously instead
+    fn begin_async_scan(&mut self) -> bool {
+        let (reader, writer) = match UnixStream::pair() {
+            Ok(pair) => pair,
+            Err(_) => return false,
+        };
+        let quals = [self.quals.as_slice(), self.param_quals.as_slice()].concat();
+        self.instance.set_qual_exprs(&self.qual_exprs);
+        let started = self
+            .instance
+            .begin_async_scan(
+                &quals,
+                &self.tgts,
+                &self.sorts,
+                &self.limit,
+                &self.opts,
+                AsyncNotifier::new(writer),
+            )
+            .report_err();
+        if started {
+            self.async_wait = Some(reader);
+        }
+        started
+    }
+
+    // evaluate the query parameters in quals
+    unsafe fn eval_params(&mut self, econtext: *mut pg_sys::ExprContext) {
+        let mut old_ctx = PgMemoryContexts::For((*econtext).ecxt_per_tuple_memory).set_as_current();
+        let param_exprs = &self.param_exprs;
+        let mut eval = |value: &mut Value| {
+            if let Value::Param(param) = value {
+                let expr_state = param_exprs[param.id];
+                let mut is_null = false;
+                let datum = (*expr_state).evalfunc.unwrap()(expr_state, econtext, &mut is_null);
+                param.value = Cell::from_polymorphic_datum(datum, is_null, param.type_oid);
+            }
+        };
+        self.quals
+            .iter_mut()
+            .chain(self.param_quals.iter_mut())
+            .for_each(|qual| eval(&mut qual.value));
+        for expr in self.qual_exprs.iter_mut() {
+            expr.for_each_value_mut(&mut eval);
+        }
+        old_ctx.set_as_current();
+    }
+
+    // startup cost of the scan, the `startup_cost` foreign table option takes
+    // precedence over the estimate
+    fn startup_cost(&self) -> f64 {
+        let startup_cost = cost_option(&self.opts, "startup_cost")
+            .or(self.estimate.startup_cost)
+            .or_else(|| cost_option(&self.server_opts, "fdw_startup_cost"))
+            .unwrap_or(DEFAULT_FDW_STARTUP_COST);
+        startup_cost + self.estimate.request_latency
+    }
+
+    // cost of each returned row, including the cost of local processing
+    fn cost_per_row(&self) -> f64 {
+        let cost_per_row = self
+            .estimate
+            .cost_per_row
+            .or_else(|| cost_option(&self.server_opts, "fdw_tuple_cost"))
+            .unwrap_or(DEFAULT_FDW_TUPLE_COST);
+        cost_per_row + unsafe { pg_sys::cpu_tuple_cost }
+    }
+
+    fn total_cost(&self, rows: f64) -> f64 {
+        self.startup_cost() + rows * self.cost_per_row()
+    }
+
+    // claim the next partition to scan, returns None if all the partitions
+    // have been claimed
+    fn claim_partition(&mut self) -> Option<usize> {
+        let idx = if self.shared_next_partition.is_null() {
+            self.next_partition += 1;
+            self.next_partition - 1
+        } else {
+            unsafe { (*self.shared_next_partition).fetch_add(1, Ordering::SeqCst) }
+        };
+        (idx < self.partitions.len()).then_some(idx)
     }
 
     fn iter_scan(&mut self) -> Option<()> {
-        self.instance.iter_scan(&mut self.row)
+        if !self.is_partitioned {
+            return self.instance.iter_scan(&mut self.row).report_err();
+        }
+
+        // scan the claimed partitions one after another
+        loop {
+            if self.in_partition {
+                if self
+                    .instance
+                    .iter_scan(&mut self.row)
+                    .report_err()
+                    .is_some()
+                {
+                    return Some(());
+                }
+                self.instance.end_scan().report_err();
+                self.in_partition = false;
+            }
+            let idx = self.claim_partition()?;
+            self.instance
+                .begin_partition_scan(&self.partitions[idx], &self.tgts, &self.opts)
+                .report_err();
+            self.in_partition = true;
+        }
+    }
+
+    // match the returned row columns to the target columns by name, returns
+    // the cell index of each target column
+    //
+    // Columns not in the targets are ignored, unless they don't exist in the
+    // scanned foreign table at all.
+    fn match_row_cols(&self, attrs: &PgTupleDesc) -> FdwResult<Vec<usize>> {
+        let is_base_scan = self.join.is_none() && self.aggregation.is_none();
+        for col in &self.row.cols {
+            if is_base_scan
+                && !self.tgts.contains(col)
+                && !attrs
+                    .iter()
+                    .any(|a| !a.attisdropped && pgx::name_data_to_str(&a.attname) == col)
+            {
+                return Err(FdwError::new(
+                    PgSqlErrorCode::ERRCODE_FDW_COLUMN_NAME_NOT_FOUND,
+                    format!(
+                        "returned column \"{}\" does not exist in foreign table",
+                        col
+                    ),
+                ));
+            }
+        }
+
+        self.tgts
+            .iter()
+            .map(|tgt| {
+                self.row
+                    .cols
+                    .iter()
+                    .position(|col| col == tgt)
+                    .ok_or_else(|| {
+                        FdwError::new(
+                            PgSqlErrorCode::ERRCODE_FDW_COLUMN_NAME_NOT_FOUND,
+                            format!("target column \"{}\" is missing in returned row", tgt),
+                        )
+                    })
+            })
+            .collect()
     }
 
     fn re_scan(&mut self) {
-        self.instance.re_scan()
+        // the shared partition index is reset separately when the dynamic
+        // shared memory is reinitialized
+        if self.is_partitioned {
+            self.end_scan();
+            self.next_partition = 0;
+            return;
+        }
+
+        // the parameters may have changed, so restart the scan on next fetch,
+        // or next request of an asynchronous scan
+        if !self.param_exprs.is_empty() || self.is_async {
+            if !self.scan_pending {
+                self.end_scan();
+                self.scan_pending = true;
+            }
+            return;
+        }
+        self.instance.re_scan().report_err()
     }
 
     fn end_scan(&mut self) {
-        self.instance.end_scan();
+        if self.is_partitioned {
+            if self.in_partition {
+                self.instance.end_scan().report_err();
+                self.in_partition = false;
+            }
+            return;
+        }
+        self.async_wait.take();
+        self.instance.end_scan().report_err()
+    }
+
+    // execute the pushed down UPDATE or DELETE, returns the affected row count
+    fn direct_modify(&mut self) -> i64 {
+        match &self.direct_modify {
+            Some(modify) => self.instance.direct_modify(modify).report_err(),
+            None => 0,
+        }
     }
 
     fn clear(&mut self) {
+        self.instance.deactivate();
         self.quals.clear();
         self.quals.shrink_to_fit();
+        self.qual_exprs.clear();
+        self.qual_exprs.shrink_to_fit();
+        self.remote_conds.clear();
+        self.remote_conds.shrink_to_fit();
         self.tgts.clear();
         self.tgts.shrink_to_fit();
         self.tgt_attnos.clear();
         self.tgt_attnos.shrink_to_fit();
+        self.params.clear();
+        self.params.shrink_to_fit();
+        self.param_quals.clear();
+        self.param_quals.shrink_to_fit();
+        self.param_exprs.clear();
+        self.param_exprs.shrink_to_fit();
         self.sorts.clear();
         self.sorts.shrink_to_fit();
         self.limit.take();
+        self.join.take();
+        self.aggregation.take();
+        self.direct_modify.take();
         self.opts.clear();
         self.opts.shrink_to_fit();
+        self.partitions.clear();
+        self.partitions.shrink_to_fit();
         self.values.clear();
         self.values.shrink_to_fit();
         self.nulls.clear();
@@ -123,31 +548,92 @@ pub(super) extern "C" fn get_foreign_rel_size<W: ForeignDataWrapper>(
 ) {
     debug2!("---> get_foreign_rel_size");
     unsafe {
-        let mut state = FdwState::<W>::new(foreigntableid);
+        // the foreign table is accessed as the user in its range table entry,
+        // e.g. owner of the view referring it
+        let rte = pg_sys::planner_rt_fetch((*baserel).relid, root);
+        let mut state = FdwState::<W>::new(foreigntableid, (*rte).checkAsUser);
 
         state.tmp_ctx.reset();
         let mut old_ctx = state.tmp_ctx.set_as_current();
 
-        // extract qual list
-        state.quals = extract_quals(root, baserel, foreigntableid);
+        // get foreign table options
+        let ftable = pg_sys::GetForeignTable(foreigntableid);
+        state.opts = utils::options_to_hashmap((*ftable).options);
+        let fserver = pg_sys::GetForeignServer((*ftable).serverid);
+        state.server_opts = utils::options_to_hashmap((*fserver).options);
+
+        // extract qual list, and let the FDW decide which of them are
+        // enforced remotely
+        let extracted = extract_quals(root, baserel, foreigntableid, &mut state.params);
+        state.quals = extracted.quals;
+        state.qual_exprs = extracted.exprs;
+        let (qual_pushdowns, expr_pushdowns) = state.classify_quals();
+        state.remote_conds = extracted
+            .qual_conds
+            .into_iter()
+            .zip(qual_pushdowns)
+            .chain(extracted.expr_conds.into_iter().zip(expr_pushdowns))
+            .filter(|(_, pushdown)| *pushdown == QualPushdown::Remote)
+            .map(|(cond, _)| cond)
+            .collect();
 
         // extract target column list from target and restriction expression
         (state.tgts, state.tgt_attnos) = utils::extract_target_columns(root, baserel);
 
-        // extract sort list
-        state.sorts = extract_sorts(root, baserel, foreigntableid);
+        // extract the leading part of query ordering which the FDW can sort
+        // remotely
+        let sorts: Vec<Sort> = extract_sort_pathkeys(root, baserel, foreigntableid)
+            .into_iter()
+            .map(|(_, sort)| sort)
+            .collect();
+        let supported = state.instance.can_sort(&sorts);
+        let sorted_cnt = sorts
+            .iter()
+            .zip(supported.iter())
+            .take_while(|(sort, supported)| sort == supported)
+            .count();
+        state.sorts = sorts.into_iter().take(sorted_cnt).collect();
 
-        // extract limit
-        state.limit = extract_limit(root, baserel, foreigntableid);
+        // extract limit, which can only be pushed down if all the restrictions
+        // are enforced remotely, otherwise the remote side may return too few
+        // rows, and there are no query parameters, as the limit would be
+        // applied again to each rescan with different parameters. The whole
+        // query ordering must also be sorted remotely, otherwise the remote
+        // side would return arbitrary rows to be sorted locally.
+        let conds = PgList::<pg_sys::RestrictInfo>::from_pg((*baserel).baserestrictinfo);
+        let query_pathkeys = PgList::<pg_sys::PathKey>::from_pg((*root).query_pathkeys);
+        if state.remote_conds.len() == conds.len()
+            && state.params.is_empty()
+            && state.sorts.len() == query_pathkeys.len()
+        {
+            state.limit = extract_limit(root, baserel, foreigntableid);
+        }
 
-        // get foreign table options
-        let ftable = pg_sys::GetForeignTable(foreigntableid);
-        state.opts = utils::options_to_hashmap((*ftable).options);
+        // get estimate row count, mean row width and costs
+        let estimate = state.get_rel_size();
+        if !estimate.is_remote && (*baserel).tuples >= 0.0 {
+            // estimate from the statistics collected by ANALYZE
+            pg_sys::set_baserel_size_estimates(root, baserel);
+        } else {
+            (*baserel).rows = pg_sys::clamp_row_est(estimate.rows);
+        }
+        if estimate.width > 0 {
+            (*(*baserel).reltarget).width = estimate.width;
+        }
+        state.estimate = estimate;
 
-        // get estimate row count and mean row width
-        let (rows, width) = state.get_rel_size();
-        (*baserel).rows = rows as f64;
-        (*(*baserel).reltarget).width = width;
+        // split the scan into partitions for a parallel plan, the scan cannot
+        // run in parallel workers if it is not split or has query parameters
+        if (*baserel).consider_parallel {
+            if state.params.is_empty() {
+                state.partitions = state
+                    .instance
+                    .partition_scan(&state.quals, &state.tgts, &state.opts)
+                    .report_err();
+            }
+            (*baserel).consider_parallel = !state.partitions.is_empty();
+        }
+        state.save_explain();
 
         old_ctx.set_as_current();
 
@@ -160,24 +646,14 @@ pub(super) extern "C" fn get_foreign_rel_size<W: ForeignDataWrapper>(
 pub(super) extern "C" fn get_foreign_paths<W: ForeignDataWrapper>(
     root: *mut pg_sys::PlannerInfo,
     baserel: *mut pg_sys::RelOptInfo,
-    _foreigntableid: pg_sys::Oid,
+    foreigntableid: pg_sys::Oid,
 ) {
     debug2!("---> get_foreign_paths");
     unsafe {
         let state = PgBox::<FdwState<W>>::from_pg((*baserel).fdw_private as _);
 
-        // get startup cost from foreign table options
-        let startup_cost = state
-            .opts
-            .get("startup_cost")
-            .map(|c| match c.parse::<f64>() {
-                Ok(v) => v,
-                Err(_) => {
-                    pgx::error!("invalid option startup_cost: {}", c);
-                }
-            })
-            .unwrap_or(0.0);
-        let total_cost = startup_cost + (*baserel).rows;
+        let startup_cost = state.startup_cost();
+        let total_cost = state.total_cost((*baserel).rows);
 
         // create a ForeignPath node and add it as the only possible path
         let path = pg_sys::create_foreignscan_path(
@@ -193,39 +669,515 @@ pub(super) extern "C" fn get_foreign_paths<W: ForeignDataWrapper>(
             ptr::null_mut(), // no fdw_private data
         );
         pg_sys::add_path(baserel, &mut ((*path).path));
+
+        // add a partial path for a parallel scan, whose partitions are shared
+        // by the parallel workers
+        if (*baserel).consider_parallel {
+            let workers = state
+                .partitions
+                .len()
+                .min(pg_sys::max_parallel_workers_per_gather as usize)
+                as c_int;
+            if workers > 0 {
+                let rows = (*baserel).rows / parallel_divisor(workers);
+                let path = pg_sys::create_foreignscan_path(
+                    root,
+                    baserel,
+                    ptr::null_mut(), // default pathtarget
+                    rows,
+                    startup_cost,
+                    state.total_cost(rows),
+                    ptr::null_mut(), // no pathkeys
+                    ptr::null_mut(), // no outer rel either
+                    ptr::null_mut(), // no extra plan
+                    ptr::null_mut(), // no fdw_private data
+                );
+                (*path).path.parallel_aware = true;
+                (*path).path.parallel_workers = workers;
+                pg_sys::add_partial_path(baserel, &mut ((*path).path));
+            }
+        }
+
+        // add parameterized paths, so the join conditions with other
+        // relations can be pushed down in a nested-loop join
+        for required_outer in extract_param_outer_relids(root, baserel) {
+            let ppi = pg_sys::get_baserel_parampathinfo(root, baserel, required_outer);
+            let mut params = state.params.clone();
+            let param_quals = extract_param_quals(
+                root,
+                baserel,
+                foreigntableid,
+                (*ppi).ppi_clauses,
+                &mut params,
+            );
+            if param_quals.is_empty() {
+                continue;
+            }
+
+            let rows = (*ppi).ppi_rows;
+            let path = pg_sys::create_foreignscan_path(
+                root,
+                baserel,
+                ptr::null_mut(), // default pathtarget
+                rows,
+                startup_cost,
+                state.total_cost(rows),
+                ptr::null_mut(), // no pathkeys
+                required_outer,
+                ptr::null_mut(), // no extra plan
+                ptr::null_mut(), // no fdw_private data
+            );
+            // the join conditions are not available to the parallel workers
+            (*path).path.parallel_safe = false;
+            pg_sys::add_path(baserel, &mut ((*path).path));
+        }
+
+        // add a sorted path if the FDW can return the rows in the leading
+        // part of the query ordering, so the local sort can be skipped
+        if state.sorts.is_empty() {
+            return;
+        }
+        let mut pathkeys = PgList::<pg_sys::PathKey>::new();
+        for (pathkey, _) in extract_sort_pathkeys(root, baserel, foreigntableid)
+            .into_iter()
+            .take(state.sorts.len())
+        {
+            pathkeys.push(pathkey);
+        }
+        let path = pg_sys::create_foreignscan_path(
+            root,
+            baserel,
+            ptr::null_mut(), // default pathtarget
+            (*baserel).rows,
+            startup_cost,
+            total_cost * DEFAULT_FDW_SORT_MULTIPLIER,
+            pathkeys.into_pg(),
+            ptr::null_mut(), // no outer rel either
+            ptr::null_mut(), // no extra plan
+            ptr::null_mut(), // no fdw_private data
+        );
+        // the partitions scanned by the parallel workers are not sorted
+        (*path).path.parallel_safe = false;
+        pg_sys::add_path(baserel, &mut ((*path).path));
+    }
+}
+
+#[pg_guard]
+pub(super) extern "C" fn get_foreign_join_paths<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    joinrel: *mut pg_sys::RelOptInfo,
+    outerrel: *mut pg_sys::RelOptInfo,
+    innerrel: *mut pg_sys::RelOptInfo,
+    jointype: pg_sys::JoinType,
+    extra: *mut pg_sys::JoinPathExtraData,
+) {
+    debug2!("---> get_foreign_join_paths");
+    unsafe {
+        // this join relation has already been considered
+        if !(*joinrel).fdw_private.is_null() {
+            return;
+        }
+
+        // don't push down joins for row locking or data modify, which need
+        // the base relation scans for rechecking
+        let parse = (*root).parse;
+        if (*parse).commandType != pg_sys::CmdType_CMD_SELECT || !(*root).rowMarks.is_null() {
+            return;
+        }
+
+        let join_type = match to_join_type(jointype) {
+            Some(join_type) => join_type,
+            None => return,
+        };
+        let is_outer_join = join_type != JoinType::Inner;
+
+        // only join two foreign tables on the same server with the same user
+        for rel in [outerrel, innerrel] {
+            if (*rel).reloptkind != pg_sys::RelOptKind_RELOPT_BASEREL
+                || (*rel).fdw_private.is_null()
+                || (*rel).serverid != (*joinrel).serverid
+                || (*rel).userid != (*joinrel).userid
+            {
+                return;
+            }
+        }
+        let outer_state = PgBox::<FdwState<W>>::from_pg((*outerrel).fdw_private as _);
+        let inner_state = PgBox::<FdwState<W>>::from_pg((*innerrel).fdw_private as _);
+
+        // query parameters are not supported in a pushed down join
+        if !outer_state.params.is_empty() || !inner_state.params.is_empty() {
+            return;
+        }
+
+        // for outer joins nothing can be rechecked locally, so all the table
+        // quals must be pushed down
+        if is_outer_join
+            && (outer_state.quals.len()
+                != PgList::<pg_sys::RestrictInfo>::from_pg((*outerrel).baserestrictinfo).len()
+                || inner_state.quals.len()
+                    != PgList::<pg_sys::RestrictInfo>::from_pg((*innerrel).baserestrictinfo).len())
+        {
+            return;
+        }
+
+        // extract join conditions
+        let mut quals = Vec::new();
+        let restrictlist = PgList::<pg_sys::RestrictInfo>::from_pg((*extra).restrictlist);
+        for rinfo in restrictlist.iter_ptr() {
+            if is_outer_join && is_pushed_down(rinfo, joinrel) {
+                return;
+            }
+            match extract_join_qual(root, (*rinfo).clause as _, outerrel, innerrel) {
+                Some(qual) => quals.push(qual),
+                None if is_outer_join => return,
+                None => {}
+            }
+        }
+
+        // don't push down cross joins
+        if quals.is_empty() {
+            return;
+        }
+
+        // for inner joins, all the conditions are rechecked locally. The
+        // planner's lists are copied, as list_concat can modify or share them.
+        let mut local_conds: *mut pg_sys::List = ptr::null_mut();
+        if !is_outer_join {
+            local_conds = pg_sys::list_copy((*extra).restrictlist);
+            local_conds =
+                pg_sys::list_concat(local_conds, pg_sys::list_copy((*outerrel).baserestrictinfo));
+            local_conds =
+                pg_sys::list_concat(local_conds, pg_sys::list_copy((*innerrel).baserestrictinfo));
+        }
+
+        // build scan target list from the join's target columns and the
+        // columns used by local conditions
+        let mut scan_vars: *mut pg_sys::List = ptr::null_mut();
+        let tgt_list: PgList<pg_sys::Node> = PgList::from_pg((*(*joinrel).reltarget).exprs);
+        for tgt in tgt_list.iter_ptr() {
+            if !is_a(tgt, pg_sys::NodeTag_T_Var) {
+                return;
+            }
+            scan_vars = pg_sys::lappend(scan_vars, tgt as _);
+        }
+        let conds: PgList<pg_sys::RestrictInfo> = PgList::from_pg(local_conds);
+        for cond in conds.iter_ptr() {
+            let vars = pg_sys::pull_var_clause(
+                (*cond).clause as _,
+                pg_sys::PVC_RECURSE_PLACEHOLDERS.try_into().unwrap(),
+            );
+            scan_vars = pg_sys::list_concat(scan_vars, vars);
+        }
+        let scan_tlist = pg_sys::add_to_flat_tlist(ptr::null_mut(), scan_vars);
+
+        let mut outer = JoinTable {
+            alias: join_alias((*outerrel).relid),
+            quals: outer_state.quals.clone(),
+            options: outer_state.opts.clone(),
+            ..Default::default()
+        };
+        let mut inner = JoinTable {
+            alias: join_alias((*innerrel).relid),
+            quals: inner_state.quals.clone(),
+            options: inner_state.opts.clone(),
+            ..Default::default()
+        };
+        let mut tgts = Vec::new();
+        let tles: PgList<pg_sys::TargetEntry> = PgList::from_pg(scan_tlist);
+        for tle in tles.iter_ptr() {
+            let var = (*tle).expr as *mut pg_sys::Var;
+            if !is_a(var as _, pg_sys::NodeTag_T_Var) {
+                return;
+            }
+            let side = if pg_sys::bms_is_member((*var).varno as _, (*outerrel).relids) {
+                &mut outer
+            } else if pg_sys::bms_is_member((*var).varno as _, (*innerrel).relids) {
+                &mut inner
+            } else {
+                return;
+            };
+            let col = match var_column_name(root, var) {
+                Some(col) => col,
+                None => return,
+            };
+            tgts.push(format!("{}.{}", side.alias, col));
+            side.columns.push(col);
+        }
+
+        let join = Join {
+            join_type,
+            outer,
+            inner,
+            quals,
+        };
+
+        // the joined relations are accessed as the same user, which is
+        // InvalidOid if it is the current user
+        let instance =
+            instance::create_fdw_instance_from_server_id((*joinrel).serverid, (*joinrel).userid);
+        let mut state = FdwState::<W>::from_instance(instance);
+        let accepted = state.instance.can_join(&join);
+        let startup_cost = outer_state.startup_cost() + inner_state.startup_cost();
+        let cost_per_row = outer_state.cost_per_row();
+        state.tgt_attnos = (1..=tgts.len()).collect();
+        state.tgts = tgts;
+        state.server_opts = outer_state.server_opts.clone();
+        state.join = Some(join);
+
+        // save the state even if the join is rejected, so it won't be
+        // considered again
+        (*joinrel).fdw_private =
+            PgBox::new_in_context(state, PgMemoryContexts::CurTransactionContext).into_pg() as _;
+
+        if !accepted {
+            return;
+        }
+
+        let mut fdw_private = PgList::<pg_sys::List>::new();
+        fdw_private.push(local_conds);
+        fdw_private.push(scan_tlist);
+
+        let rows = (*joinrel).rows;
+        let path = pg_sys::create_foreign_join_path(
+            root,
+            joinrel,
+            ptr::null_mut(), // default pathtarget
+            rows,
+            startup_cost,
+            startup_cost + rows * cost_per_row,
+            ptr::null_mut(), // no pathkeys
+            ptr::null_mut(), // no outer rel either
+            ptr::null_mut(), // no extra plan
+            fdw_private.into_pg(),
+        );
+        // only base relation scans can run in the parallel workers
+        (*path).path.parallel_safe = false;
+        pg_sys::add_path(joinrel, &mut ((*path).path));
+    }
+}
+
+#[pg_guard]
+pub(super) extern "C" fn get_foreign_upper_paths<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    stage: pg_sys::UpperRelationKind,
+    input_rel: *mut pg_sys::RelOptInfo,
+    output_rel: *mut pg_sys::RelOptInfo,
+    _extra: *mut ::std::os::raw::c_void,
+) {
+    debug2!("---> get_foreign_upper_paths");
+    unsafe {
+        // only aggregates on a foreign table are supported, and skip if the
+        // output relation has already been considered
+        if stage != pg_sys::UpperRelationKind_UPPERREL_GROUP_AGG
+            || (*input_rel).reloptkind != pg_sys::RelOptKind_RELOPT_BASEREL
+            || (*input_rel).fdw_private.is_null()
+            || !(*output_rel).fdw_private.is_null()
+        {
+            return;
+        }
+
+        let parse = (*root).parse;
+        if !(*parse).groupingSets.is_null() || (*root).hasHavingQual || (*parse).hasTargetSRFs {
+            return;
+        }
+
+        // nothing can be rechecked locally before aggregation, so all the
+        // table quals must be pushed down, and query parameters are not
+        // supported
+        let input_state = PgBox::<FdwState<W>>::from_pg((*input_rel).fdw_private as _);
+        if !input_state.params.is_empty()
+            || input_state.quals.len()
+                != PgList::<pg_sys::RestrictInfo>::from_pg((*input_rel).baserestrictinfo).len()
+        {
+            return;
+        }
+
+        let group_by = match extract_group_by(root, parse) {
+            Some(group_by) => group_by,
+            None => return,
+        };
+
+        // build scan target list from the grouping target, which can only
+        // contain group by columns and aggregates
+        let target = (*root).upper_targets[pg_sys::UpperRelationKind_UPPERREL_GROUP_AGG as usize];
+        let scan_tlist = pg_sys::add_to_flat_tlist(ptr::null_mut(), (*target).exprs);
+        pg_sys::apply_pathtarget_labeling_to_tlist(scan_tlist, target);
+
+        let mut tgts = Vec::new();
+        let mut aggregates = Vec::new();
+        let tles: PgList<pg_sys::TargetEntry> = PgList::from_pg(scan_tlist);
+        for tle in tles.iter_ptr() {
+            let expr = (*tle).expr as *mut pg_sys::Node;
+            if is_a(expr, pg_sys::NodeTag_T_Aggref) {
+                match extract_aggregate(root, expr as _) {
+                    Some(aggregate) => {
+                        tgts.push(aggregate.deparse());
+                        aggregates.push(aggregate);
+                    }
+                    None => return,
+                }
+            } else {
+                match expr_column_name(root, expr) {
+                    Some(col) if (*tle).ressortgroupref != 0 && group_by.contains(&col) => {
+                        tgts.push(col)
+                    }
+                    _ => return,
+                }
+            }
+        }
+
+        let aggregation = Aggregation {
+            group_by,
+            aggregates,
+            quals: input_state.quals.clone(),
+            options: input_state.opts.clone(),
+        };
+
+        let instance = instance::create_fdw_instance_from_server_id(
+            (*input_rel).serverid,
+            (*input_rel).userid,
+        );
+        let mut state = FdwState::<W>::from_instance(instance);
+        let accepted = state.instance.can_aggregate(&aggregation);
+        let startup_cost = input_state.startup_cost();
+        let cost_per_row = input_state.cost_per_row();
+        state.tgt_attnos = (1..=tgts.len()).collect();
+        state.tgts = tgts;
+        state.opts = input_state.opts.clone();
+        state.server_opts = input_state.server_opts.clone();
+        state.aggregation = Some(aggregation);
+
+        // save the state even if the aggregation is rejected, so it won't be
+        // considered again
+        (*output_rel).fdw_private =
+            PgBox::new_in_context(state, PgMemoryContexts::CurTransactionContext).into_pg() as _;
+
+        if !accepted {
+            return;
+        }
+
+        // estimate number of groups
+        let rows = if (*parse).groupClause.is_null() {
+            1.0
+        } else {
+            let group_exprs =
+                pg_sys::get_sortgrouplist_exprs((*parse).groupClause, (*parse).targetList);
+            pg_sys::estimate_num_groups(
+                root,
+                group_exprs,
+                (*input_rel).rows,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+
+        let mut fdw_private = PgList::<pg_sys::List>::new();
+        fdw_private.push(ptr::null_mut()); // no local conditions
+        fdw_private.push(scan_tlist);
+
+        let path = pg_sys::create_foreign_upper_path(
+            root,
+            output_rel,
+            target,
+            rows,
+            startup_cost,
+            startup_cost + rows * cost_per_row,
+            ptr::null_mut(), // no pathkeys
+            ptr::null_mut(), // no extra plan
+            fdw_private.into_pg(),
+        );
+        // only base relation scans can run in the parallel workers
+        (*path).path.parallel_safe = false;
+        pg_sys::add_path(output_rel, &mut ((*path).path));
     }
 }
 
 #[pg_guard]
 pub(super) extern "C" fn get_foreign_plan<W: ForeignDataWrapper>(
-    _root: *mut pg_sys::PlannerInfo,
+    root: *mut pg_sys::PlannerInfo,
     baserel: *mut pg_sys::RelOptInfo,
-    _foreigntableid: pg_sys::Oid,
-    _best_path: *mut pg_sys::ForeignPath,
+    foreigntableid: pg_sys::Oid,
+    best_path: *mut pg_sys::ForeignPath,
     tlist: *mut pg_sys::List,
     scan_clauses: *mut pg_sys::List,
     outer_plan: *mut pg_sys::Plan,
 ) -> *mut pg_sys::ForeignScan {
     debug2!("---> get_foreign_plan");
     unsafe {
-        let state = PgBox::<FdwState<W>>::from_pg((*baserel).fdw_private as _);
+        let mut state = PgBox::<FdwState<W>>::from_pg((*baserel).fdw_private as _);
 
         // Plan and plan data (e.g. scan_clauses) must live for the entire duration of the query
         // As such, it must be allocated in the caller's memory context
 
-        // make foreign scan plan
-        let scan_clauses = pg_sys::extract_actual_clauses(scan_clauses, false);
+        // for a pushed down join or aggregation, the local conditions and
+        // scan target list are saved in the path when it was created
+        let (scan_relid, scan_clauses, scan_tlist) =
+            if (*baserel).reloptkind == pg_sys::RelOptKind_RELOPT_BASEREL {
+                ((*baserel).relid, scan_clauses, ptr::null_mut())
+            } else {
+                let private = PgList::<pg_sys::List>::from_pg((*best_path).fdw_private);
+                let local_conds = private.get_ptr(0).unwrap();
+                let scan_tlist = private.get_ptr(1).unwrap();
+                (0, local_conds, scan_tlist)
+            };
 
-        let fdw_private = FdwState::serialize_to_list(state);
+        // extract the join conditions of a parameterized scan, their outer
+        // columns will be replaced with executor parameters by Postgres
+        let mut params = state.params.clone();
+        let param_info = (*best_path).path.param_info;
+        state.param_quals = if param_info.is_null() {
+            Vec::new()
+        } else {
+            // the limit cannot be applied to each rescan of a parameterized
+            // scan
+            state.limit = None;
+            extract_param_quals(
+                root,
+                baserel,
+                foreigntableid,
+                (*param_info).ppi_clauses,
+                &mut params,
+            )
+        };
+        let mut fdw_exprs = PgList::<pg_sys::Node>::new();
+        for param in params {
+            fdw_exprs.push(param);
+        }
+
+        // make foreign scan plan, the restrictions enforced remotely are not
+        // checked locally, except when a row is rechecked by EvalPlanQual
+        let mut local_clauses = PgList::<pg_sys::RestrictInfo>::new();
+        let mut remote_clauses = PgList::<pg_sys::RestrictInfo>::new();
+        for cond in PgList::<pg_sys::RestrictInfo>::from_pg(scan_clauses).iter_ptr() {
+            if state.remote_conds.contains(&cond) {
+                remote_clauses.push(cond);
+            } else {
+                local_clauses.push(cond);
+            }
+        }
+        let scan_clauses = pg_sys::extract_actual_clauses(local_clauses.into_pg(), false);
+        let recheck_quals = pg_sys::extract_actual_clauses(remote_clauses.into_pg(), false);
+
+        // the parallel workers cannot access the serialized state, they
+        // rebuild the scan state from the lists appended to it
+        let parallel_lists = if state.partitions.is_empty() {
+            Vec::new()
+        } else {
+            state.parallel_lists()
+        };
+        let mut fdw_private = PgList::<pg_sys::List>::from_pg(FdwState::serialize_to_list(state));
+        for list in parallel_lists {
+            fdw_private.push(list);
+        }
 
         pg_sys::make_foreignscan(
             tlist,
             scan_clauses,
-            (*baserel).relid,
-            ptr::null_mut(),
-            fdw_private,
-            ptr::null_mut(),
-            ptr::null_mut(),
+            scan_relid,
+            fdw_exprs.into_pg(),
+            fdw_private.into_pg(),
+            scan_tlist,
+            recheck_quals,
             outer_plan,
         )
     }
@@ -248,23 +1200,102 @@ pub(super) extern "C" fn explain_foreign_scan<W: ForeignDataWrapper>(
         state.tmp_ctx.reset();
         let mut old_ctx = state.tmp_ctx.set_as_current();
 
-        let label = PgMemoryContexts::CurrentMemoryContext.pstrdup("Wrappers");
+        // the pushed down parts of the scan are only shown for debugging
+        if (*es).verbose {
+            let label = PgMemoryContexts::CurrentMemoryContext.pstrdup("Wrappers");
+
+            let quals = [state.quals.as_slice(), state.param_quals.as_slice()].concat();
+            let value =
+                PgMemoryContexts::CurrentMemoryContext.pstrdup(&format!("quals = {:?}", quals));
+            pg_sys::ExplainPropertyText(label, value, es);
 
-        let value =
-            PgMemoryContexts::CurrentMemoryContext.pstrdup(&format!("quals = {:?}", state.quals));
-        pg_sys::ExplainPropertyText(label, value, es);
+            if !state.qual_exprs.is_empty() {
+                let value = PgMemoryContexts::CurrentMemoryContext
+                    .pstrdup(&format!("qual_exprs = {:?}", state.qual_exprs));
+                pg_sys::ExplainPropertyText(label, value, es);
+            }
+
+            let value =
+                PgMemoryContexts::CurrentMemoryContext.pstrdup(&format!("tgts = {:?}", state.tgts));
+            pg_sys::ExplainPropertyText(label, value, es);
+
+            let value = PgMemoryContexts::CurrentMemoryContext
+                .pstrdup(&format!("sorts = {:?}", state.sorts));
+            pg_sys::ExplainPropertyText(label, value, es);
+
+            let value = PgMemoryContexts::CurrentMemoryContext
+                .pstrdup(&format!("limit = {:?}", state.limit));
+            pg_sys::ExplainPropertyText(label, value, es);
+
+            if let Some(join) = &state.join {
+                let value =
+                    PgMemoryContexts::CurrentMemoryContext.pstrdup(&format!("join = {:?}", join));
+                pg_sys::ExplainPropertyText(label, value, es);
+            }
+
+            if let Some(aggregation) = &state.aggregation {
+                let value = PgMemoryContexts::CurrentMemoryContext
+                    .pstrdup(&format!("aggregation = {:?}", aggregation));
+                pg_sys::ExplainPropertyText(label, value, es);
+            }
+
+            if let Some(modify) = &state.direct_modify {
+                let value = PgMemoryContexts::CurrentMemoryContext
+                    .pstrdup(&format!("direct_modify = {:?}", modify));
+                pg_sys::ExplainPropertyText(label, value, es);
+            }
+
+            if !state.partitions.is_empty() {
+                let value = PgMemoryContexts::CurrentMemoryContext
+                    .pstrdup(&format!("partitions = {:?}", state.partitions));
+                pg_sys::ExplainPropertyText(label, value, es);
+            }
+        }
 
-        let value =
-            PgMemoryContexts::CurrentMemoryContext.pstrdup(&format!("tgts = {:?}", state.tgts));
-        pg_sys::ExplainPropertyText(label, value, es);
+        // the instance is not taken by a plain EXPLAIN, so the properties
+        // saved when the scan was planned are shown
+        let props = if state.instance.is_active() {
+            state.instance.explain((*es).verbose)
+        } else {
+            state.planned_explain[(*es).verbose as usize].clone()
+        };
+        for (name, value) in props {
+            let label = PgMemoryContexts::CurrentMemoryContext.pstrdup(&name);
+            let value = PgMemoryContexts::CurrentMemoryContext.pstrdup(&value);
+            pg_sys::ExplainPropertyText(label, value, es);
+        }
+
+        if (*es).analyze {
+            if let Some(stats) = state.instance.remote_stats() {
+                let base = &state.stats_base;
+                let stats = RemoteStats {
+                    requests: stats.requests.saturating_sub(base.requests),
+                    bytes: stats.bytes.saturating_sub(base.bytes),
+                    bytes_processed: stats.bytes_processed.saturating_sub(base.bytes_processed),
+                    latency: stats.latency.saturating_sub(base.latency),
+                };
+                let label = PgMemoryContexts::CurrentMemoryContext.pstrdup("Remote Requests");
+                pg_sys::ExplainPropertyUInteger(label, ptr::null(), stats.requests, es);
 
-        let value =
-            PgMemoryContexts::CurrentMemoryContext.pstrdup(&format!("sorts = {:?}", state.sorts));
-        pg_sys::ExplainPropertyText(label, value, es);
+                if stats.bytes > 0 {
+                    let label = PgMemoryContexts::CurrentMemoryContext.pstrdup("Remote Bytes");
+                    pg_sys::ExplainPropertyUInteger(label, ptr::null(), stats.bytes, es);
+                }
+                if stats.bytes_processed > 0 {
+                    let label =
+                        PgMemoryContexts::CurrentMemoryContext.pstrdup("Remote Bytes Processed");
+                    pg_sys::ExplainPropertyUInteger(label, ptr::null(), stats.bytes_processed, es);
+                }
 
-        let value =
-            PgMemoryContexts::CurrentMemoryContext.pstrdup(&format!("limit = {:?}", state.limit));
-        pg_sys::ExplainPropertyText(label, value, es);
+                // timings are omitted by EXPLAIN (ANALYZE, TIMING OFF)
+                if (*es).timing {
+                    let label = PgMemoryContexts::CurrentMemoryContext.pstrdup("Remote Latency");
+                    let unit = PgMemoryContexts::CurrentMemoryContext.pstrdup("ms");
+                    let latency = stats.latency.as_secs_f64() * 1000.0;
+                    pg_sys::ExplainPropertyFloat(label, unit, latency, 3, es);
+                }
+            }
+        }
 
         old_ctx.set_as_current();
 
@@ -281,14 +1312,52 @@ pub(super) extern "C" fn begin_foreign_scan<W: ForeignDataWrapper>(
     unsafe {
         let scan_state = (*node).ss;
         let plan = scan_state.ps.plan as *mut pg_sys::ForeignScan;
-        let mut state = FdwState::<W>::deserialize_from_list((*plan).fdw_private as _);
+
+        // a parallel worker scans the partitions, either shared with the other
+        // processes for a parallel aware scan, or all of them
+        let is_worker = pg_sys::ParallelWorkerNumber >= 0;
+        let mut state = if is_worker {
+            let estate = scan_state.ps.state;
+            let rte = pg_sys::rt_fetch((*plan).scan.scanrelid, (*estate).es_range_table);
+            let state = FdwState::<W>::from_parallel_list(
+                (*scan_state.ss_currentRelation).rd_id,
+                (*rte).checkAsUser,
+                (*plan).fdw_private,
+            );
+            PgBox::from_pg(
+                PgBox::new_in_context(state, PgMemoryContexts::CurTransactionContext).into_pg(),
+            )
+        } else {
+            FdwState::<W>::deserialize_from_list((*plan).fdw_private as _)
+        };
+        state.is_partitioned = is_worker || (*plan).scan.plan.parallel_aware;
 
         // begin scan if it is not EXPLAIN statement
         if eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as c_int <= 0 {
-            state.begin_scan();
+            state.activate();
 
-            let rel = scan_state.ss_currentRelation;
-            let tup_desc = (*rel).rd_att;
+            // the parameters are not available until the first fetch, and the
+            // partitions are claimed on the first fetch
+            if !(*plan).fdw_exprs.is_null() {
+                let exprs = PgList::<pg_sys::ExprState>::from_pg(pg_sys::ExecInitExprList(
+                    (*plan).fdw_exprs,
+                    &mut (*node).ss.ps,
+                ));
+                state.param_exprs = exprs.iter_ptr().collect();
+                state.scan_pending = true;
+            }
+
+            // an asynchronous scan begins on the first request from Append
+            state.is_async = is_async_node(node);
+            if state.is_async {
+                state.scan_pending = true;
+            } else if !state.scan_pending && !state.is_partitioned {
+                state.begin_scan();
+            }
+
+            // use the scan slot's tuple descriptor, as there is no scan
+            // relation for a pushed down join
+            let tup_desc = (*scan_state.ss_ScanTupleSlot).tts_tupleDescriptor;
             let natts = (*tup_desc).natts as usize;
 
             // initialize scan result lists
@@ -311,6 +1380,13 @@ pub(super) extern "C" fn iterate_foreign_scan<W: ForeignDataWrapper>(
     unsafe {
         let mut state = PgBox::<FdwState<W>>::from_pg((*node).fdw_state as _);
 
+        // begin scan with the evaluated parameters
+        if state.scan_pending {
+            state.eval_params((*node).ss.ps.ps_ExprContext);
+            state.begin_scan();
+            state.scan_pending = false;
+        }
+
         // clear slot
         let slot = (*node).ss.ss_ScanTupleSlot;
         polyfill::exec_clear_tuple(slot);
@@ -320,21 +1396,31 @@ pub(super) extern "C" fn iterate_foreign_scan<W: ForeignDataWrapper>(
 
         state.row.clear();
         if state.iter_scan().is_some() {
-            if state.row.cols.len() != state.tgts.len() {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_INVALID_COLUMN_NUMBER,
-                    "target column number not match",
-                );
-                old_ctx.set_as_current();
-                return slot;
-            }
+            let attrs = PgTupleDesc::from_pg_unchecked((*slot).tts_tupleDescriptor);
+
+            // the row columns are usually in the same order as the targets,
+            // otherwise match them by name
+            let cell_idxs = if state.row.cols == state.tgts {
+                None
+            } else {
+                Some(state.match_row_cols(&attrs).report_err())
+            };
 
-            for i in 0..state.row.cells.len() {
-                let att_idx = state.tgt_attnos[i] - 1;
+            // coerce the cells to the target column types
+            for j in 0..state.tgts.len() {
+                let i = cell_idxs.as_ref().map_or(j, |idxs| idxs[j]);
+                let att_idx = state.tgt_attnos[j] - 1;
                 let cell = state.row.cells.get_unchecked_mut(i);
                 match cell.take() {
                     Some(cell) => {
-                        state.values[att_idx] = cell.into_datum().unwrap();
+                        let attr = attrs.get(att_idx).unwrap();
+                        state.values[att_idx] = coerce::cell_to_datum(
+                            cell,
+                            attr.atttypid,
+                            attr.atttypmod,
+                            &state.tgts[j],
+                        )
+                        .report_err();
                         state.nulls[att_idx] = false;
                     }
                     None => state.nulls[att_idx] = true,
@@ -380,3 +1466,365 @@ pub(super) extern "C" fn end_foreign_scan<W: ForeignDataWrapper>(
         }
     }
 }
+
+#[pg_guard]
+pub(super) extern "C" fn is_foreign_scan_parallel_safe(
+    _root: *mut pg_sys::PlannerInfo,
+    _rel: *mut pg_sys::RelOptInfo,
+    _rte: *mut pg_sys::RangeTblEntry,
+) -> bool {
+    debug2!("---> is_foreign_scan_parallel_safe");
+    // the scan can only run in the parallel workers if the FDW splits it into
+    // partitions, which is decided in get_foreign_rel_size
+    true
+}
+
+#[pg_guard]
+pub(super) extern "C" fn estimate_dsm_foreign_scan(
+    _node: *mut pg_sys::ForeignScanState,
+    _pcxt: *mut pg_sys::ParallelContext,
+) -> pg_sys::Size {
+    debug2!("---> estimate_dsm_foreign_scan");
+    mem::size_of::<AtomicUsize>()
+}
+
+#[pg_guard]
+pub(super) extern "C" fn initialize_dsm_foreign_scan<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+    _pcxt: *mut pg_sys::ParallelContext,
+    coordinate: *mut c_void,
+) {
+    debug2!("---> initialize_dsm_foreign_scan");
+    unsafe {
+        let next_partition = coordinate as *mut AtomicUsize;
+        next_partition.write(AtomicUsize::new(0));
+
+        let fdw_state = (*node).fdw_state as *mut FdwState<W>;
+        if !fdw_state.is_null() {
+            let mut state = PgBox::<FdwState<W>>::from_pg(fdw_state);
+            state.shared_next_partition = next_partition;
+        }
+    }
+}
+
+#[pg_guard]
+pub(super) extern "C" fn reinitialize_dsm_foreign_scan(
+    _node: *mut pg_sys::ForeignScanState,
+    _pcxt: *mut pg_sys::ParallelContext,
+    coordinate: *mut c_void,
+) {
+    debug2!("---> reinitialize_dsm_foreign_scan");
+    unsafe {
+        let next_partition = coordinate as *mut AtomicUsize;
+        (*next_partition).store(0, Ordering::SeqCst);
+    }
+}
+
+#[pg_guard]
+pub(super) extern "C" fn initialize_worker_foreign_scan<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+    _toc: *mut pg_sys::shm_toc,
+    coordinate: *mut c_void,
+) {
+    debug2!("---> initialize_worker_foreign_scan");
+    unsafe {
+        let fdw_state = (*node).fdw_state as *mut FdwState<W>;
+        if !fdw_state.is_null() {
+            let mut state = PgBox::<FdwState<W>>::from_pg(fdw_state);
+            state.shared_next_partition = coordinate as _;
+        }
+    }
+}
+
+// whether the scan is executed asynchronously by an Append
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+unsafe fn is_async_node(node: *mut pg_sys::ForeignScanState) -> bool {
+    (*node).ss.ps.async_capable
+}
+
+#[cfg(not(any(feature = "pg14", feature = "pg15")))]
+unsafe fn is_async_node(_node: *mut pg_sys::ForeignScanState) -> bool {
+    false
+}
+
+// fetch the next row of an asynchronous scan and complete the request, same
+// as the static ExecAsyncRequestDone()
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+unsafe fn complete_async_request(areq: *mut pg_sys::AsyncRequest) {
+    let node = (*areq).requestee;
+    let slot = (*node).ExecProcNode.unwrap()(node);
+    (*areq).request_complete = true;
+    (*areq).result = slot;
+}
+
+// wait for the asynchronous scan to be ready, same as the static
+// ExecAsyncRequestPending()
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+unsafe fn pend_async_request(areq: *mut pg_sys::AsyncRequest) {
+    (*areq).callback_pending = true;
+    (*areq).request_complete = false;
+    (*areq).result = ptr::null_mut();
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn is_foreign_path_async_capable<W: ForeignDataWrapper>(
+    path: *mut pg_sys::ForeignPath,
+) -> bool {
+    debug2!("---> is_foreign_path_async_capable");
+    unsafe {
+        let rel = (*path).path.parent;
+        if (*rel).reloptkind != pg_sys::RelOptKind_RELOPT_BASEREL || (*rel).fdw_private.is_null() {
+            return false;
+        }
+
+        // the table option takes precedence over the server option
+        let state = PgBox::<FdwState<W>>::from_pg((*rel).fdw_private as _);
+        bool_option(&state.opts, "async_capable")
+            .or_else(|| bool_option(&state.server_opts, "async_capable"))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn foreign_async_request<W: ForeignDataWrapper>(
+    areq: *mut pg_sys::AsyncRequest,
+) {
+    debug2!("---> foreign_async_request");
+    unsafe {
+        let node = (*areq).requestee as *mut pg_sys::ForeignScanState;
+        let mut state = PgBox::<FdwState<W>>::from_pg((*node).fdw_state as _);
+
+        // start the scan in background on the first request, otherwise it
+        // begins synchronously on the first fetch
+        if state.scan_pending {
+            state.eval_params((*node).ss.ps.ps_ExprContext);
+            if state.begin_async_scan() {
+                state.scan_pending = false;
+            }
+        }
+
+        if state.async_wait.is_some() {
+            pend_async_request(areq);
+        } else {
+            complete_async_request(areq);
+        }
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn foreign_async_configure_wait<W: ForeignDataWrapper>(
+    areq: *mut pg_sys::AsyncRequest,
+) {
+    debug2!("---> foreign_async_configure_wait");
+    unsafe {
+        let node = (*areq).requestee as *mut pg_sys::ForeignScanState;
+        let state = PgBox::<FdwState<W>>::from_pg((*node).fdw_state as _);
+        if let Some(sock) = &state.async_wait {
+            let append = (*areq).requestor as *mut pg_sys::AppendState;
+            pg_sys::AddWaitEventToSet(
+                (*append).as_eventset,
+                pg_sys::WL_SOCKET_READABLE,
+                sock.as_raw_fd(),
+                ptr::null_mut(),
+                areq as _,
+            );
+        }
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn foreign_async_notify<W: ForeignDataWrapper>(
+    areq: *mut pg_sys::AsyncRequest,
+) {
+    debug2!("---> foreign_async_notify");
+    unsafe {
+        let node = (*areq).requestee as *mut pg_sys::ForeignScanState;
+        let mut state = PgBox::<FdwState<W>>::from_pg((*node).fdw_state as _);
+
+        // the rows are ready, fetch them without waiting anymore
+        state.async_wait.take();
+        complete_async_request(areq);
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn plan_direct_modify<W: ForeignDataWrapper>(
+    root: *mut pg_sys::PlannerInfo,
+    plan: *mut pg_sys::ModifyTable,
+    result_relation: pg_sys::Index,
+    _subplan_index: c_int,
+) -> bool {
+    debug2!("---> plan_direct_modify");
+    use crate::interface::DirectModifyType;
+    use std::ffi::CStr;
+    unsafe {
+        let modify_type = match (*plan).operation {
+            pg_sys::CmdType_CMD_UPDATE => DirectModifyType::Update,
+            pg_sys::CmdType_CMD_DELETE => DirectModifyType::Delete,
+            _ => return false,
+        };
+
+        // the modified rows are needed locally for RETURNING clause
+        if !(*plan).returningLists.is_null() {
+            return false;
+        }
+
+        // the row level triggers need the modified rows locally too, the
+        // relation is already locked by the planner
+        let rte = pg_sys::planner_rt_fetch(result_relation, root);
+        let rel = pg_sys::table_open((*rte).relid, pg_sys::NoLock as _);
+        let trigdesc = (*rel).trigdesc;
+        let has_row_triggers = !trigdesc.is_null()
+            && match modify_type {
+                DirectModifyType::Update => {
+                    (*trigdesc).trig_update_before_row || (*trigdesc).trig_update_after_row
+                }
+                DirectModifyType::Delete => {
+                    (*trigdesc).trig_delete_before_row || (*trigdesc).trig_delete_after_row
+                }
+            };
+        pg_sys::table_close(rel, pg_sys::NoLock as _);
+        if has_row_triggers {
+            return false;
+        }
+
+        // the subplan must be a plain scan on the target foreign table
+        let fscan = (*plan).plan.lefttree as *mut pg_sys::ForeignScan;
+        if fscan.is_null()
+            || !is_a(fscan as _, pg_sys::NodeTag_T_ForeignScan)
+            || (*fscan).scan.scanrelid != result_relation
+        {
+            return false;
+        }
+
+        // all the restrictions must be pushed down without query parameters
+        let baserel = pg_sys::find_base_rel(root, result_relation as _);
+        let conds = PgList::<pg_sys::RestrictInfo>::from_pg((*baserel).baserestrictinfo);
+        let mut state = FdwState::<W>::deserialize_from_list((*fscan).fdw_private as _);
+        if state.quals.len() != conds.len() || !(*fscan).fdw_exprs.is_null() {
+            return false;
+        }
+
+        // the new values must be constants
+        let mut sets = Row::new();
+        if modify_type == DirectModifyType::Update {
+            let mut processed_tlist = ptr::null_mut();
+            let mut update_colnos = ptr::null_mut();
+            pg_sys::get_translated_update_targetlist(
+                root,
+                result_relation,
+                &mut processed_tlist,
+                &mut update_colnos,
+            );
+            let tles = PgList::<pg_sys::TargetEntry>::from_pg(processed_tlist);
+            let colnos = PgList::<c_int>::from_pg(update_colnos);
+            for (tle, colno) in tles.iter_ptr().zip(colnos.iter_int()) {
+                let expr = unnest_clause((*tle).expr as _);
+                if !is_a(expr, pg_sys::NodeTag_T_Const) {
+                    return false;
+                }
+                let cst = expr as *mut pg_sys::Const;
+                if !is_cell_type((*cst).consttype) {
+                    return false;
+                }
+                let cell = Cell::from_polymorphic_datum(
+                    (*cst).constvalue,
+                    (*cst).constisnull,
+                    (*cst).consttype,
+                );
+                let field = pg_sys::get_attname((*rte).relid, colno as _, false);
+                sets.push(CStr::from_ptr(field).to_str().unwrap(), cell);
+            }
+        }
+
+        let modify = DirectModify {
+            modify_type,
+            sets,
+            quals: state.quals.clone(),
+            options: state.opts.clone(),
+        };
+        if !state.instance.can_direct_modify(&modify) {
+            return false;
+        }
+        state.direct_modify = Some(modify);
+
+        // turn the scan into a direct modify, the restrictions are no longer
+        // checked locally
+        (*fscan).operation = (*plan).operation;
+        (*fscan).resultRelation = result_relation;
+        (*fscan).scan.plan.qual = ptr::null_mut();
+
+        true
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn begin_direct_modify<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+    eflags: c_int,
+) {
+    debug2!("---> begin_direct_modify");
+    unsafe {
+        let plan = (*node).ss.ps.plan as *mut pg_sys::ForeignScan;
+        let mut state = FdwState::<W>::deserialize_from_list((*plan).fdw_private as _);
+
+        // execute on the first fetch if it is not EXPLAIN statement
+        state.scan_pending = eflags & pg_sys::EXEC_FLAG_EXPLAIN_ONLY as c_int <= 0;
+        if state.scan_pending {
+            state.activate();
+        }
+
+        (*node).fdw_state = state.into_pg() as _;
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn iterate_direct_modify<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+) -> *mut pg_sys::TupleTableSlot {
+    debug2!("---> iterate_direct_modify");
+    unsafe {
+        let mut state = PgBox::<FdwState<W>>::from_pg((*node).fdw_state as _);
+
+        if state.scan_pending {
+            state.scan_pending = false;
+
+            state.tmp_ctx.reset();
+            let mut old_ctx = state.tmp_ctx.set_as_current();
+
+            // no rows are returned, so count the affected rows here
+            let affected = state.direct_modify();
+            let estate = (*node).ss.ps.state;
+            (*estate).es_processed += affected.max(0) as u64;
+
+            old_ctx.set_as_current();
+        }
+
+        // an empty slot ends the modification
+        let slot = (*node).ss.ss_ScanTupleSlot;
+        polyfill::exec_clear_tuple(slot);
+        slot
+    }
+}
+
+#[cfg(any(feature = "pg14", feature = "pg15"))]
+#[pg_guard]
+pub(super) extern "C" fn end_direct_modify<W: ForeignDataWrapper>(
+    node: *mut pg_sys::ForeignScanState,
+) {
+    debug2!("---> end_direct_modify");
+    unsafe {
+        let fdw_state = (*node).fdw_state as *mut FdwState<W>;
+        if !fdw_state.is_null() {
+            let mut state = PgBox::<FdwState<W>>::from_rust(fdw_state);
+            state.clear();
+        }
+    }
+}
@@ -2,6 +2,18 @@ use crate::interface::Sort;
 use pgx::{is_a, pg_sys, PgList};
 use std::ffi::CStr;
 
+// name of a collation, e.g. `default` or `C`, None if no collation is used
+unsafe fn collation_name(coll_id: pg_sys::Oid) -> Option<String> {
+    if coll_id == pg_sys::InvalidOid {
+        return None;
+    }
+    let name = pg_sys::get_collation_name(coll_id);
+    if name.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(name).to_str().unwrap().to_owned())
+}
+
 pub(crate) unsafe fn create_sort(
     pathkey: *mut pg_sys::PathKey,
     var: *mut pg_sys::Var,
@@ -22,56 +34,56 @@ pub(crate) unsafe fn create_sort(
     None
 }
 
-// extract sorts
-pub(crate) unsafe fn extract_sorts(
-    root: *mut pg_sys::PlannerInfo,
+// extract the sort of a pathkey on the base relation
+unsafe fn extract_sort(
+    pathkey: *mut pg_sys::PathKey,
     baserel: *mut pg_sys::RelOptInfo,
     baserel_id: pg_sys::Oid,
-) -> Vec<Sort> {
-    let mut ret = Vec::new();
-    let pathkeys: PgList<pg_sys::PathKey> = PgList::from_pg((*root).query_pathkeys);
-
-    for pathkey in pathkeys.iter_ptr() {
-        let ec = (*pathkey).pk_eclass;
-
-        if (*ec).ec_has_volatile {
-            continue;
-        }
+) -> Option<Sort> {
+    let ec = (*pathkey).pk_eclass;
 
-        let ems: PgList<pg_sys::EquivalenceMember> = PgList::from_pg((*ec).ec_members);
-        ems.iter_ptr()
-            .find(|em| pg_sys::bms_equal((*(*em)).em_relids, (*baserel).relids))
-            .and_then(|em| {
-                let expr = (*em).em_expr as *mut pg_sys::Node;
+    if (*ec).ec_has_volatile {
+        return None;
+    }
 
-                if is_a(expr, pg_sys::NodeTag_T_Var) {
-                    let var = expr as *mut pg_sys::Var;
-                    let sort = create_sort(pathkey, var, baserel_id);
-                    if let Some(sort) = sort {
-                        ret.push(sort);
-                    }
-                } else if is_a(expr, pg_sys::NodeTag_T_RelabelType) {
-                    // ORDER BY clauses having a COLLATE option will be RelabelType
-                    let expr = expr as *mut pg_sys::RelabelType;
-                    let var = (*expr).arg as *mut pg_sys::Var;
-                    if is_a(var as *mut pg_sys::Node, pg_sys::NodeTag_T_Var) {
-                        let sort = create_sort(pathkey, var, baserel_id);
-                        if let Some(mut sort) = sort {
-                            let coll_id = (*expr).resultcollid;
-                            sort.collate = Some(
-                                CStr::from_ptr(pg_sys::get_collation_name(coll_id))
-                                    .to_str()
-                                    .unwrap()
-                                    .to_owned(),
-                            );
-                            ret.push(sort);
-                        }
-                    }
-                }
+    let ems: PgList<pg_sys::EquivalenceMember> = PgList::from_pg((*ec).ec_members);
+    let em = ems
+        .iter_ptr()
+        .find(|em| pg_sys::bms_equal((*(*em)).em_relids, (*baserel).relids))?;
+    let expr = (*em).em_expr as *mut pg_sys::Node;
 
-                None::<()>
-            });
+    if is_a(expr, pg_sys::NodeTag_T_Var) {
+        // sorts on a collatable column use its collation, which may be the
+        // database default collation
+        let var = expr as *mut pg_sys::Var;
+        let mut sort = create_sort(pathkey, var, baserel_id)?;
+        sort.collate = collation_name((*ec).ec_collation);
+        Some(sort)
+    } else if is_a(expr, pg_sys::NodeTag_T_RelabelType) {
+        // ORDER BY clauses having a COLLATE option will be RelabelType
+        let expr = expr as *mut pg_sys::RelabelType;
+        let var = (*expr).arg as *mut pg_sys::Var;
+        if !is_a(var as *mut pg_sys::Node, pg_sys::NodeTag_T_Var) {
+            return None;
+        }
+        let mut sort = create_sort(pathkey, var, baserel_id)?;
+        sort.collate = collation_name((*expr).resultcollid);
+        Some(sort)
+    } else {
+        None
     }
+}
 
-    ret
+// extract the longest leading part of query pathkeys which can be sorted on
+// the base relation, along with their sorts
+pub(crate) unsafe fn extract_sort_pathkeys(
+    root: *mut pg_sys::PlannerInfo,
+    baserel: *mut pg_sys::RelOptInfo,
+    baserel_id: pg_sys::Oid,
+) -> Vec<(*mut pg_sys::PathKey, Sort)> {
+    let pathkeys: PgList<pg_sys::PathKey> = PgList::from_pg((*root).query_pathkeys);
+    pathkeys
+        .iter_ptr()
+        .map_while(|pathkey| extract_sort(pathkey, baserel, baserel_id).map(|sort| (pathkey, sort)))
+        .collect()
 }
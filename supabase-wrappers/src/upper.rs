@@ -0,0 +1,101 @@
+use crate::coerce;
+use crate::interface::{Aggregate, AggregateKind};
+use crate::join::var_column_name;
+use crate::qual::unnest_clause;
+use pgx::{is_a, pg_sys, PgList};
+use std::ffi::CStr;
+use std::os::raw::c_char;
+
+// check if the aggregate result type can be returned as a Cell
+fn is_supported_type(typoid: pg_sys::Oid) -> bool {
+    [
+        pg_sys::BOOLOID,
+        pg_sys::INT2OID,
+        pg_sys::INT4OID,
+        pg_sys::INT8OID,
+        pg_sys::FLOAT4OID,
+        pg_sys::FLOAT8OID,
+        pg_sys::TEXTOID,
+        pg_sys::DATEOID,
+        pg_sys::TIMESTAMPOID,
+    ]
+    .contains(&typoid)
+}
+
+// get the column name of a plain column expression
+pub(crate) unsafe fn expr_column_name(
+    root: *mut pg_sys::PlannerInfo,
+    expr: *mut pg_sys::Node,
+) -> Option<String> {
+    let expr = unnest_clause(expr);
+    if !is_a(expr, pg_sys::NodeTag_T_Var) {
+        return None;
+    }
+    var_column_name(root, expr as _)
+}
+
+// extract GROUP BY columns, return None if any of them is not a plain column
+pub(crate) unsafe fn extract_group_by(
+    root: *mut pg_sys::PlannerInfo,
+    parse: *mut pg_sys::Query,
+) -> Option<Vec<String>> {
+    let mut ret = Vec::new();
+    let clauses: PgList<pg_sys::SortGroupClause> = PgList::from_pg((*parse).groupClause);
+    for sgc in clauses.iter_ptr() {
+        let expr = pg_sys::get_sortgroupclause_expr(sgc, (*parse).targetList);
+        ret.push(expr_column_name(root, expr)?);
+    }
+    Some(ret)
+}
+
+// extract a plain aggregate function call on a column, e.g. sum(col)
+pub(crate) unsafe fn extract_aggregate(
+    root: *mut pg_sys::PlannerInfo,
+    aggref: *mut pg_sys::Aggref,
+) -> Option<Aggregate> {
+    if (*aggref).aggkind != b'n' as c_char
+        || (*aggref).agglevelsup != 0
+        || (*aggref).aggsplit != pg_sys::AggSplit_AGGSPLIT_SIMPLE
+        || !(*aggref).aggorder.is_null()
+        || !(*aggref).aggfilter.is_null()
+        || (*aggref).aggvariadic
+        || !is_supported_type((*aggref).aggtype)
+    {
+        return None;
+    }
+
+    // only built-in aggregate functions
+    if pg_sys::get_func_namespace((*aggref).aggfnoid) != pg_sys::PG_CATALOG_NAMESPACE {
+        return None;
+    }
+    let name = pg_sys::get_func_name((*aggref).aggfnoid);
+    if name.is_null() {
+        return None;
+    }
+    let kind = match CStr::from_ptr(name).to_str().unwrap() {
+        "count" => AggregateKind::Count,
+        "sum" => AggregateKind::Sum,
+        "min" => AggregateKind::Min,
+        "max" => AggregateKind::Max,
+        "avg" => AggregateKind::Avg,
+        _ => return None,
+    };
+
+    let args: PgList<pg_sys::TargetEntry> = PgList::from_pg((*aggref).args);
+    let field = if (*aggref).aggstar {
+        None
+    } else {
+        if args.len() != 1 {
+            return None;
+        }
+        let tle = args.head().unwrap();
+        Some(expr_column_name(root, (*tle).expr as _)?)
+    };
+
+    Some(Aggregate {
+        kind,
+        field,
+        distinct: !(*aggref).aggdistinct.is_null(),
+        type_name: coerce::type_name((*aggref).aggtype),
+    })
+}
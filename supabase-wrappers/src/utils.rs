@@ -1,6 +1,9 @@
 //! Helper functions for working with Wrappers
 //!
 
+use crate::coerce;
+use crate::error::{FdwError, FdwResult, ReportResult};
+use crate::instance;
 use crate::interface::{Cell, Row};
 use pgx::prelude::PgBuiltInOids;
 use pgx::spi::Spi;
@@ -126,21 +129,20 @@ pub fn create_async_runtime() -> Runtime {
 
 /// Get required option value from the `options` map
 ///
-/// Get the required option's value from `options` map, return None and report
-/// error and stop current transaction if it does not exist.
+/// Get the required option's value from `options` map, return an error if it
+/// does not exist.
 ///
 /// For example,
 ///
 /// ```rust,no_run
-/// require_option("my_option", options);
+/// let value = require_option("my_option", options)?;
 /// ```
-pub fn require_option(opt_name: &str, options: &HashMap<String, String>) -> Option<String> {
-    options.get(opt_name).map(|t| t.to_owned()).or_else(|| {
-        report_error(
+pub fn require_option(opt_name: &str, options: &HashMap<String, String>) -> FdwResult<String> {
+    options.get(opt_name).map(|t| t.to_owned()).ok_or_else(|| {
+        FdwError::new(
             PgSqlErrorCode::ERRCODE_FDW_OPTION_NAME_NOT_FOUND,
-            &format!("required option \"{}\" is not specified", opt_name),
-        );
-        None
+            format!("required option \"{}\" is not specified", opt_name),
+        )
     })
 }
 
@@ -190,6 +192,30 @@ pub fn get_vault_secret(secret_id: &str) -> Option<String> {
     }
 }
 
+/// Drop the cached FDW instances of a foreign server
+///
+/// The FDW instances, along with their clients and connections, are cached in
+/// current session and reused by later queries. This function drops the
+/// instances of a foreign server, so they will be created again by the next
+/// query. The instances used by the current transaction are dropped when it
+/// ends.
+///
+/// Returns `true` if any instance of the server is found. An error is raised
+/// if the server does not exist.
+///
+/// For example,
+///
+/// ```rust,no_run
+/// disconnect_server("my_server");
+/// ```
+pub fn disconnect_server(server_name: &str) -> bool {
+    let server_name = PgMemoryContexts::CurrentMemoryContext.pstrdup(server_name);
+    unsafe {
+        let fserver = pg_sys::GetForeignServerByName(server_name, false);
+        instance::disconnect((*fserver).serverid)
+    }
+}
+
 // convert options definition to hashmap
 pub(super) unsafe fn options_to_hashmap(options: *mut pg_sys::List) -> HashMap<String, String> {
     let mut ret = HashMap::new();
@@ -223,6 +249,59 @@ pub(super) unsafe fn tuple_table_slot_to_row(slot: *mut pg_sys::TupleTableSlot)
     row
 }
 
+// set the values of the row columns into tuple values, matching the columns by
+// name, the columns not in the tuple are ignored
+pub(super) unsafe fn set_row_values(
+    row: Row,
+    tup_desc: pg_sys::TupleDesc,
+    values: &mut [pg_sys::Datum],
+    nulls: &mut [bool],
+) {
+    let attrs = PgTupleDesc::from_pg_copy(tup_desc);
+    for (col, cell) in row.cols.into_iter().zip(row.cells) {
+        let att_idx = attrs
+            .iter()
+            .position(|a| !a.attisdropped && pgx::name_data_to_str(&a.attname) == col);
+        if let Some(att_idx) = att_idx {
+            match cell {
+                Some(cell) => {
+                    let attr = attrs.get(att_idx).unwrap();
+                    values[att_idx] =
+                        coerce::cell_to_datum(cell, attr.atttypid, attr.atttypmod, &col)
+                            .report_err();
+                    nulls[att_idx] = false;
+                }
+                None => nulls[att_idx] = true,
+            }
+        }
+    }
+}
+
+// store the cells of a row into a slot, matched by column name. The columns not
+// in the row keep their current values, or null if the slot is empty.
+pub(super) unsafe fn store_row_in_slot(row: Row, slot: *mut pg_sys::TupleTableSlot) {
+    let tup_desc = (*slot).tts_tupleDescriptor;
+    let natts = (*tup_desc).natts as usize;
+    let mut values = vec![pg_sys::Datum::from(0); natts];
+    let mut nulls = vec![true; natts];
+
+    if (*slot).tts_flags & pg_sys::TTS_FLAG_EMPTY as u16 == 0 {
+        if ((*slot).tts_nvalid as usize) < natts {
+            pg_sys::slot_getsomeattrs_int(slot, natts as _);
+        }
+        values.copy_from_slice(std::slice::from_raw_parts((*slot).tts_values, natts));
+        nulls.copy_from_slice(std::slice::from_raw_parts((*slot).tts_isnull, natts));
+    }
+
+    set_row_values(row, tup_desc, &mut values, &mut nulls);
+
+    // form the tuple in the slot's memory context, so it can be freed by the slot
+    let mut old_ctx = PgMemoryContexts::For((*slot).tts_mcxt).set_as_current();
+    let tuple = pg_sys::heap_form_tuple(tup_desc, values.as_mut_ptr(), nulls.as_mut_ptr());
+    old_ctx.set_as_current();
+    pg_sys::ExecForceStoreHeapTuple(tuple, slot, true);
+}
+
 // extract target column name and attribute no list
 pub(super) unsafe fn extract_target_columns(
     root: *mut pg_sys::PlannerInfo,
@@ -260,7 +339,7 @@ pub(super) unsafe fn extract_target_columns(
     // get column names from var list
     let col_vars: PgList<pg_sys::Var> = PgList::from_pg(col_vars);
     for var in col_vars.iter_ptr() {
-        let rte = pg_sys::planner_rt_fetch((*var).varno as u32, root);
+        let rte = pg_sys::planner_rt_fetch((*var).varno as pg_sys::Index, root);
         let attno = (*var).varattno;
         let attname = pg_sys::get_attname((*rte).relid, attno, true);
         if !attname.is_null() {
@@ -272,8 +351,86 @@ pub(super) unsafe fn extract_target_columns(
     (col_names, col_attnos)
 }
 
+/// Check if the option list contains only the allowed options, used in [validator](crate::interface::ForeignDataWrapper::validator)
+///
+/// For example, to only allow credentials in user mappings,
+///
+/// ```rust,no_run
+/// # use supabase_wrappers::prelude::*;
+/// # let opt_list: Vec<Option<String>> = Vec::new();
+/// check_options_allowed(&opt_list, &["api_key", "api_key_id"]);
+/// ```
+pub fn check_options_allowed(opt_list: &[Option<String>], allowed: &[&str]) -> FdwResult<()> {
+    for opt in opt_list.iter().flatten() {
+        let name = opt.split('=').next().unwrap_or_default();
+        if !allowed.contains(&name) {
+            return Err(FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME,
+                format!("invalid option \"{}\"", name),
+            )
+            .with_hint(format!(
+                "Valid options in this context are: {}",
+                allowed.join(", ")
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Quote an identifier in the SQL statements returned by [import_foreign_schema](crate::interface::ForeignDataWrapper::import_foreign_schema)
+///
+/// For example, `my "table"` is quoted as `"my ""table"""`.
+pub fn quote_identifier(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Quote a string literal in the SQL statements returned by [import_foreign_schema](crate::interface::ForeignDataWrapper::import_foreign_schema)
+///
+/// For example, `it's` is quoted as `'it''s'`, and `a\b` as `E'a\\b'`.
+pub fn quote_literal(value: &str) -> String {
+    let quoted = value.replace('\'', "''");
+    if value.contains('\\') {
+        format!("E'{}'", quoted.replace('\\', "\\\\"))
+    } else {
+        format!("'{}'", quoted)
+    }
+}
+
+// parse a boolean option value, the same values as Postgres are accepted
+pub(super) fn parse_bool_option(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "on" | "yes" | "1" => Some(true),
+        "false" | "off" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Check if a boolean option in the option list has a valid value, used in [validator](crate::interface::ForeignDataWrapper::validator)
+///
+/// For example, to check the `async_capable` option of foreign servers and
+/// tables,
+///
+/// ```rust,no_run
+/// # use supabase_wrappers::prelude::*;
+/// # let opt_list: Vec<Option<String>> = Vec::new();
+/// check_options_bool(&opt_list, "async_capable");
+/// ```
+pub fn check_options_bool(opt_list: &[Option<String>], name: &str) -> FdwResult<()> {
+    for opt in opt_list.iter().flatten() {
+        if let Some((opt_name, value)) = opt.split_once('=') {
+            if opt_name == name && parse_bool_option(value).is_none() {
+                return Err(FdwError::new(
+                    PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                    format!("{} requires a Boolean value", name),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Check if the option list contains a specific option, used in [validator](crate::interface::ForeignDataWrapper::validator)
-pub fn check_options_contain(opt_list: &[Option<String>], tgt: &str) {
+pub fn check_options_contain(opt_list: &[Option<String>], tgt: &str) -> FdwResult<()> {
     let search_key = tgt.to_owned() + "=";
     if !opt_list.iter().any(|opt| {
         if let Some(s) = opt {
@@ -282,11 +439,12 @@ pub fn check_options_contain(opt_list: &[Option<String>], tgt: &str) {
             false
         }
     }) {
-        report_error(
+        return Err(FdwError::new(
             PgSqlErrorCode::ERRCODE_FDW_OPTION_NAME_NOT_FOUND,
-            &format!("required option \"{}\" is not specified", tgt),
-        );
+            format!("required option \"{}\" is not specified", tgt),
+        ));
     }
+    Ok(())
 }
 
 // trait for "serialize" and "deserialize" state, so that it is safe to be carried
@@ -0,0 +1,147 @@
+use pgx::{debug2, prelude::*};
+use std::cell::{Cell, RefCell};
+use std::os::raw::c_void;
+
+// transaction event to be notified to the participants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum XactEvent {
+    PreCommit,
+    PrePrepare,
+    Commit,
+    Abort,
+}
+
+// handler to notify a participant state of a transaction event
+pub(super) type XactHandler = unsafe fn(state: *mut c_void, event: XactEvent);
+
+// a fdw state taking part in the current transaction
+#[derive(Clone, Copy)]
+struct Participant {
+    // subtransaction in which the participant is registered
+    subid: pg_sys::SubTransactionId,
+    state: *mut c_void,
+    handler: XactHandler,
+}
+
+thread_local! {
+    static CALLBACKS_REGISTERED: Cell<bool> = const { Cell::new(false) };
+    static PARTICIPANTS: RefCell<Vec<Participant>> = const { RefCell::new(Vec::new()) };
+}
+
+// register a fdw state to be notified at the end of current transaction, the
+// state must live until then. A state is notified once by each handler.
+pub(super) unsafe fn register_participant(state: *mut c_void, handler: XactHandler) {
+    register(state, handler, false);
+}
+
+// register a fdw state like register_participant(), but it is also notified
+// of the abort of every subtransaction in which it is registered
+pub(super) unsafe fn register_subxact_participant(state: *mut c_void, handler: XactHandler) {
+    register(state, handler, true);
+}
+
+unsafe fn register(state: *mut c_void, handler: XactHandler, per_subxact: bool) {
+    // transaction callbacks are registered once per backend
+    if !CALLBACKS_REGISTERED.with(|r| r.replace(true)) {
+        pg_sys::RegisterXactCallback(Some(xact_callback), std::ptr::null_mut());
+        pg_sys::RegisterSubXactCallback(Some(subxact_callback), std::ptr::null_mut());
+    }
+
+    let subid = pg_sys::GetCurrentSubTransactionId();
+    PARTICIPANTS.with(|p| {
+        let mut participants = p.borrow_mut();
+        if !participants.iter().any(|pt| {
+            pt.state == state
+                && pt.handler as usize == handler as usize
+                && (!per_subxact || pt.subid == subid)
+        }) {
+            participants.push(Participant {
+                subid,
+                state,
+                handler,
+            });
+        }
+    });
+}
+
+// notify the participants without holding the list, as the handlers may raise
+// errors which trigger another notification
+unsafe fn notify(participants: &[Participant], event: XactEvent) {
+    for pt in participants {
+        (pt.handler)(pt.state, event);
+    }
+}
+
+#[pg_guard]
+extern "C" fn xact_callback(event: pg_sys::XactEvent, _arg: *mut c_void) {
+    debug2!("---> xact_callback");
+    unsafe {
+        match event {
+            pg_sys::XactEvent_XACT_EVENT_PRE_COMMIT
+            | pg_sys::XactEvent_XACT_EVENT_PARALLEL_PRE_COMMIT => {
+                let participants = PARTICIPANTS.with(|p| p.borrow().clone());
+                notify(&participants, XactEvent::PreCommit);
+            }
+            pg_sys::XactEvent_XACT_EVENT_PRE_PREPARE => {
+                let participants = PARTICIPANTS.with(|p| p.borrow().clone());
+                notify(&participants, XactEvent::PrePrepare);
+            }
+            // the prepared transaction is finished in current backend
+            pg_sys::XactEvent_XACT_EVENT_COMMIT
+            | pg_sys::XactEvent_XACT_EVENT_PARALLEL_COMMIT
+            | pg_sys::XactEvent_XACT_EVENT_PREPARE => {
+                let participants = PARTICIPANTS.with(|p| p.take());
+                notify(&participants, XactEvent::Commit);
+            }
+            pg_sys::XactEvent_XACT_EVENT_ABORT | pg_sys::XactEvent_XACT_EVENT_PARALLEL_ABORT => {
+                let participants = PARTICIPANTS.with(|p| p.take());
+                notify(&participants, XactEvent::Abort);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[pg_guard]
+extern "C" fn subxact_callback(
+    event: pg_sys::SubXactEvent,
+    my_subid: pg_sys::SubTransactionId,
+    parent_subid: pg_sys::SubTransactionId,
+    _arg: *mut c_void,
+) {
+    debug2!("---> subxact_callback");
+    unsafe {
+        match event {
+            // the participants are taken over by the parent transaction, and
+            // those registered in both of them are kept once
+            pg_sys::SubXactEvent_SUBXACT_EVENT_COMMIT_SUB => PARTICIPANTS.with(|p| {
+                let mut participants = p.borrow_mut();
+                for pt in participants.iter_mut().filter(|pt| pt.subid == my_subid) {
+                    pt.subid = parent_subid;
+                }
+                let mut kept: Vec<Participant> = Vec::new();
+                for pt in participants.drain(..) {
+                    if !kept.iter().any(|k| {
+                        k.state == pt.state
+                            && k.handler as usize == pt.handler as usize
+                            && k.subid == pt.subid
+                    }) {
+                        kept.push(pt);
+                    }
+                }
+                *participants = kept;
+            }),
+            pg_sys::SubXactEvent_SUBXACT_EVENT_ABORT_SUB => {
+                let participants = PARTICIPANTS.with(|p| {
+                    let mut participants = p.borrow_mut();
+                    let (aborted, kept): (Vec<_>, Vec<_>) =
+                        participants.drain(..).partition(|pt| pt.subid == my_subid);
+                    *participants = kept;
+                    aborted
+                });
+                notify(&participants, XactEvent::Abort);
+            }
+            _ => {}
+        }
+    }
+}
@@ -47,30 +47,38 @@ impl AirtableFdw {
     }
 
     // convert response body text to rows
-    fn parse_resp(&self, resp_body: &str, columns: &[String]) -> (Vec<Row>, Option<String>) {
-        let response: AirtableResponse = serde_json::from_str(resp_body).unwrap();
+    fn parse_resp(
+        &self,
+        resp_body: &str,
+        columns: &[String],
+    ) -> FdwResult<(Vec<Row>, Option<String>)> {
+        let response: AirtableResponse =
+            serde_json::from_str(resp_body).map_err(|err| format!("invalid response: {}", err))?;
         let mut result = Vec::new();
 
         for record in response.records.iter() {
             result.push(record.to_row(columns));
         }
 
-        (result, response.offset)
+        Ok((result, response.offset))
     }
 }
 
-macro_rules! report_fetch_error {
-    ($url:ident, $err:ident) => {
-        report_error(
-            PgSqlErrorCode::ERRCODE_FDW_ERROR,
-            &format!("fetch {} failed: {}", $url, $err),
-        )
-    };
+// convert a fetch error to FdwError, with the HTTP status if there is one
+fn fetch_error(url: &str, err: reqwest::Error) -> FdwError {
+    let fdw_err = FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_ERROR,
+        format!("fetch {} failed: {}", url, err),
+    );
+    match err.status() {
+        Some(status) => fdw_err.with_remote_status(status.as_u16()),
+        None => fdw_err,
+    }
 }
 
 // TODO Add support for INSERT, UPDATE, DELETE
 impl ForeignDataWrapper for AirtableFdw {
-    fn new(options: &HashMap<String, String>) -> Self {
+    fn new(options: &HashMap<String, String>) -> FdwResult<Self> {
         let base_url = options
             .get("api_url")
             .map(|t| t.to_owned())
@@ -78,28 +86,28 @@ impl ForeignDataWrapper for AirtableFdw {
             .trim_end_matches('/')
             .to_owned();
 
-        let client = require_option("api_key", options).map(|api_key| {
-            let mut headers = header::HeaderMap::new();
-            let value = format!("Bearer {}", api_key);
-            let mut auth_value = header::HeaderValue::from_str(&value).unwrap();
-            auth_value.set_sensitive(true);
-            headers.insert(header::AUTHORIZATION, auth_value);
-            let client = reqwest::Client::builder()
-                .default_headers(headers)
-                .build()
-                .unwrap();
-            let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-            ClientBuilder::new(client)
-                .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-                .build()
-        });
-
-        Self {
+        let api_key = require_option("api_key", options)?;
+        let mut headers = header::HeaderMap::new();
+        let value = format!("Bearer {}", api_key);
+        let mut auth_value = header::HeaderValue::from_str(&value)
+            .map_err(|err| format!("invalid api_key: {}", err))?;
+        auth_value.set_sensitive(true);
+        headers.insert(header::AUTHORIZATION, auth_value);
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|err| format!("create client failed: {}", err))?;
+        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
+        let client = ClientBuilder::new(client)
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            .build();
+
+        Ok(Self {
             rt: create_async_runtime(),
             base_url,
-            client,
+            client: Some(client),
             scan_result: None,
-        }
+        })
     }
 
     fn begin_scan(
@@ -109,15 +117,11 @@ impl ForeignDataWrapper for AirtableFdw {
         _sorts: &[Sort],        // TODO: Propagate sort
         _limit: &Option<Limit>, // TODO: maxRecords
         options: &HashMap<String, String>,
-    ) {
+    ) -> FdwResult<()> {
         // TODO: Support specifying other options (view)
-        let url = if let Some(url) = require_option("base_id", options).and_then(|base_id| {
-            require_option("table", options).map(|table| self.build_url(&base_id, &table))
-        }) {
-            url
-        } else {
-            return;
-        };
+        let base_id = require_option("base_id", options)?;
+        let table = require_option("table", options)?;
+        let url = self.build_url(&base_id, &table);
 
         let mut rows = Vec::new();
         if let Some(client) = &self.client {
@@ -127,69 +131,71 @@ impl ForeignDataWrapper for AirtableFdw {
                 // Fetch all of the rows upfront. Arguably, this could be done in batches (and invoked each
                 // time iter_scan() runs out of rows) to pipeline the I/O, but we'd have to manage more
                 // state so starting with the simpler solution.
-                let url = match self.set_limit_offset(&url, None, offset.as_deref()) {
-                    Ok(url) => url,
-                    Err(err) => {
-                        report_error(
-                            PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                            &format!("internal error: {}", err),
-                        );
-                        return;
-                    }
-                };
-
-                match self.rt.block_on(client.get(&url).send()) {
-                    Ok(resp) => match resp.error_for_status() {
-                        Ok(resp) => {
-                            let body = self.rt.block_on(resp.text()).unwrap();
-                            let (new_rows, new_offset) = self.parse_resp(&body, columns);
-                            rows.extend(new_rows.into_iter());
-
-                            if let Some(new_offset) = new_offset {
-                                offset = Some(new_offset);
-                            } else {
-                                break;
-                            }
-                        }
-                        Err(err) => report_fetch_error!(url, err),
-                    },
-                    Err(err) => report_fetch_error!(url, err),
+                let url = self
+                    .set_limit_offset(&url, None, offset.as_deref())
+                    .map_err(|err| format!("internal error: {}", err))?;
+
+                let resp = self
+                    .rt
+                    .block_on(client.get(&url).send())
+                    .map_err(|err| format!("fetch {} failed: {}", url, err))?
+                    .error_for_status()
+                    .map_err(|err| fetch_error(&url, err))?;
+                let body = self
+                    .rt
+                    .block_on(resp.text())
+                    .map_err(|err| fetch_error(&url, err))?;
+                let (new_rows, new_offset) = self.parse_resp(&body, columns)?;
+                rows.extend(new_rows.into_iter());
+
+                if let Some(new_offset) = new_offset {
+                    offset = Some(new_offset);
+                } else {
+                    break;
                 }
             }
         }
 
         self.scan_result = Some(rows);
+        Ok(())
     }
 
-    fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
+    fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>> {
         if let Some(ref mut result) = self.scan_result {
             if !result.is_empty() {
-                return result
+                return Ok(result
                     .drain(0..1)
                     .last()
-                    .map(|src_row| row.replace_with(src_row));
+                    .map(|src_row| row.replace_with(src_row)));
             }
         }
-        None
+        Ok(None)
     }
 
-    fn end_scan(&mut self) {
+    fn end_scan(&mut self) -> FdwResult<()> {
         self.scan_result.take();
+        Ok(())
     }
 
-    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) {
+    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) -> FdwResult<()> {
         if let Some(oid) = catalog {
             match oid {
                 FOREIGN_DATA_WRAPPER_RELATION_ID => {}
+                // the api key can be defined in either server or user mapping
                 FOREIGN_SERVER_RELATION_ID => {
-                    check_options_contain(&options, "api_key");
+                    check_options_bool(&options, "async_capable")?;
+                }
+                USER_MAPPING_RELATION_ID => {
+                    check_options_allowed(&options, &["api_key"])?;
                 }
                 FOREIGN_TABLE_RELATION_ID => {
-                    check_options_contain(&options, "base_id");
-                    check_options_contain(&options, "table");
+                    check_options_contain(&options, "base_id")?;
+                    check_options_contain(&options, "table")?;
+                    check_options_bool(&options, "async_capable")?;
                 }
                 _ => {}
             }
         }
+        Ok(())
     }
 }
@@ -1,3 +1,4 @@
+use pgx::JsonB;
 use serde::de::{MapAccess, Visitor};
 use serde::{Deserialize, Deserializer};
 use serde_json::Value;
@@ -92,8 +93,29 @@ impl AirtableRecord {
             String(v) => Some(Cell::String(v.clone())),
             // XXX Handle timestamps somehow...
 
-            // XXX Fix (probably map to JsonB)
-            _ => panic!("Unsupported: Array/Object"),
+            // multi-select and other lists of scalars are mapped to arrays
+            Array(arr) => AirtableRecord::array_to_cell(arr),
+            Object(_) => Some(Cell::Json(JsonB(value.clone()))),
+        }
+    }
+
+    // convert a list of same typed scalars to array cell, mixed lists and
+    // lists of objects are kept as json
+    fn array_to_cell(arr: &[Value]) -> Option<Cell> {
+        if arr.iter().all(|v| v.is_string() || v.is_null()) {
+            Some(Cell::StringArray(
+                arr.iter()
+                    .map(|v| v.as_str().map(|s| s.to_owned()))
+                    .collect(),
+            ))
+        } else if arr.iter().all(|v| v.is_boolean() || v.is_null()) {
+            Some(Cell::BoolArray(arr.iter().map(|v| v.as_bool()).collect()))
+        } else if arr.iter().all(|v| v.is_i64() || v.is_null()) {
+            Some(Cell::I64Array(arr.iter().map(|v| v.as_i64()).collect()))
+        } else if arr.iter().all(|v| v.is_number() || v.is_null()) {
+            Some(Cell::F64Array(arr.iter().map(|v| v.as_f64()).collect()))
+        } else {
+            Some(Cell::Json(JsonB(Value::Array(arr.to_vec()))))
         }
     }
 
@@ -3,84 +3,231 @@ use gcp_bigquery_client::{
     client_builder::ClientBuilder,
     model::{
         field_type::FieldType, query_request::QueryRequest, query_response::ResultSet,
-        table::Table, table_data_insert_all_request::TableDataInsertAllRequest,
+        table_data_insert_all_request::TableDataInsertAllRequest,
         table_field_schema::TableFieldSchema,
     },
+    table::ListOptions,
     Client,
 };
+use pgx::pg_sys;
 use pgx::prelude::PgSqlErrorCode;
-use pgx::prelude::{Date, Timestamp};
+use pgx::prelude::{Date, Time, Timestamp, TimestampWithTimeZone};
+use pgx::AnyNumeric;
 use serde_json::json;
 use std::collections::HashMap;
+use std::time::Instant;
 use time::{format_description::well_known::Iso8601, OffsetDateTime, PrimitiveDateTime};
 
 use supabase_wrappers::prelude::*;
 
-macro_rules! field_type_error {
-    ($field:ident, $err:ident) => {{
-        report_error(
-            PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
-            &format!("get field {} failed: {}", &$field.name, $err),
-        );
-        None
-    }};
+fn field_type_error<E: std::fmt::Display>(field: &TableFieldSchema, err: E) -> FdwError {
+    FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+        format!("get field {} failed: {}", &field.name, err),
+    )
+}
+
+// convert BigQuery repeated field to array Cell, the elements are returned by
+// BigQuery as a list of {"v": "value"} objects
+fn repeated_field_to_cell(rs: &ResultSet, field: &TableFieldSchema) -> FdwResult<Option<Cell>> {
+    let value = match rs
+        .get_json_value_by_name(&field.name)
+        .map_err(|err| field_type_error(field, err))?
+    {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let elems = value
+        .as_array()
+        .ok_or_else(|| field_type_error(field, "repeated value is not an array"))?
+        .iter()
+        .map(|elem| elem.get("v").and_then(|v| v.as_str()))
+        .collect::<Vec<Option<&str>>>();
+
+    fn parse_elems<T: std::str::FromStr>(
+        field: &TableFieldSchema,
+        elems: &[Option<&str>],
+    ) -> FdwResult<Vec<Option<T>>> {
+        elems
+            .iter()
+            .map(|elem| match elem {
+                Some(v) => v
+                    .parse::<T>()
+                    .map(Some)
+                    .map_err(|_| field_type_error(field, format!("invalid element '{}'", v))),
+                None => Ok(None),
+            })
+            .collect()
+    }
+
+    let cell = match field.r#type {
+        FieldType::Boolean | FieldType::Bool => Cell::BoolArray(parse_elems(field, &elems)?),
+        FieldType::Int64 | FieldType::Integer => Cell::I64Array(parse_elems(field, &elems)?),
+        FieldType::Float64 | FieldType::Float => Cell::F64Array(parse_elems(field, &elems)?),
+        FieldType::String => Cell::StringArray(
+            elems
+                .iter()
+                .map(|elem| elem.map(|v| v.to_owned()))
+                .collect(),
+        ),
+        _ => {
+            return Err(FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                format!("repeated field type {:?} not supported", field.r#type),
+            ));
+        }
+    };
+    Ok(Some(cell))
 }
 
 // convert BigQuery field to Cell
-fn field_to_cell(rs: &ResultSet, field: &TableFieldSchema) -> Option<Cell> {
-    match field.r#type {
+fn field_to_cell(rs: &ResultSet, field: &TableFieldSchema) -> FdwResult<Option<Cell>> {
+    if field.mode.as_deref() == Some("REPEATED") {
+        return repeated_field_to_cell(rs, field);
+    }
+
+    let cell = match field.r#type {
         FieldType::Boolean => rs
             .get_bool_by_name(&field.name)
-            .unwrap_or_else(|err| field_type_error!(field, err))
+            .map_err(|err| field_type_error(field, err))?
             .map(Cell::Bool),
         FieldType::Int64 | FieldType::Integer => rs
             .get_i64_by_name(&field.name)
-            .unwrap_or_else(|err| field_type_error!(field, err))
+            .map_err(|err| field_type_error(field, err))?
             .map(Cell::I64),
         FieldType::Float64 | FieldType::Float => rs
             .get_f64_by_name(&field.name)
-            .unwrap_or_else(|err| field_type_error!(field, err))
+            .map_err(|err| field_type_error(field, err))?
             .map(Cell::F64),
+        FieldType::Numeric | FieldType::Bignumeric => match rs
+            .get_string_by_name(&field.name)
+            .map_err(|err| field_type_error(field, err))?
+        {
+            Some(v) => {
+                let value =
+                    AnyNumeric::try_from(v.as_str()).map_err(|err| field_type_error(field, err))?;
+                Some(Cell::Numeric(value))
+            }
+            None => None,
+        },
         FieldType::String => rs
             .get_string_by_name(&field.name)
-            .unwrap_or_else(|err| field_type_error!(field, err))
+            .map_err(|err| field_type_error(field, err))?
             .map(Cell::String),
-        FieldType::Date => rs
+        FieldType::Date => match rs
             .get_string_by_name(&field.name)
-            .unwrap_or_else(|err| field_type_error!(field, err))
-            .map(|v| {
+            .map_err(|err| field_type_error(field, err))?
+        {
+            Some(v) => {
                 let pg_epoch = time::Date::parse("2000-01-01", &Iso8601::DEFAULT).unwrap();
-                let dt = time::Date::parse(&v, &Iso8601::DEFAULT).unwrap();
+                let dt = time::Date::parse(&v, &Iso8601::DEFAULT)
+                    .map_err(|err| field_type_error(field, err))?;
                 let days = (dt - pg_epoch).whole_days();
-                let dt = Date::from_pg_epoch_days(days.try_into().unwrap());
-                Cell::Date(dt)
-            }),
-        FieldType::Datetime => rs
+                let dt = Date::from_pg_epoch_days(
+                    days.try_into()
+                        .map_err(|err| field_type_error(field, err))?,
+                );
+                Some(Cell::Date(dt))
+            }
+            None => None,
+        },
+        FieldType::Time => match rs
+            .get_string_by_name(&field.name)
+            .map_err(|err| field_type_error(field, err))?
+        {
+            Some(v) => {
+                let tm = time::Time::parse(&v, &Iso8601::DEFAULT)
+                    .map_err(|err| field_type_error(field, err))?;
+                let tm = Time::try_from(tm)
+                    .map_err(|err| field_type_error(field, format!("{:?}", err)))?;
+                Some(Cell::Time(tm))
+            }
+            None => None,
+        },
+        FieldType::Datetime => match rs
             .get_string_by_name(&field.name)
-            .unwrap_or_else(|err| field_type_error!(field, err))
-            .map(|v| {
-                let dt = PrimitiveDateTime::parse(&v, &Iso8601::DEFAULT).unwrap();
-                let ts = Timestamp::try_from(dt).unwrap();
-                Cell::Timestamp(ts)
-            }),
-        FieldType::Timestamp => rs
+            .map_err(|err| field_type_error(field, err))?
+        {
+            Some(v) => {
+                let dt = PrimitiveDateTime::parse(&v, &Iso8601::DEFAULT)
+                    .map_err(|err| field_type_error(field, err))?;
+                let ts = Timestamp::try_from(dt)
+                    .map_err(|err| field_type_error(field, format!("{:?}", err)))?;
+                Some(Cell::Timestamp(ts))
+            }
+            None => None,
+        },
+        FieldType::Timestamp => match rs
             .get_f64_by_name(&field.name)
-            .unwrap_or_else(|err| field_type_error!(field, err))
-            .map(|v| {
-                let dt = OffsetDateTime::from_unix_timestamp_nanos((v * 1e9) as i128).unwrap();
-                let ts = Timestamp::try_from(dt).unwrap();
-                Cell::Timestamp(ts)
-            }),
+            .map_err(|err| field_type_error(field, err))?
+        {
+            Some(v) => {
+                // TIMESTAMP is an absolute point in time, unlike DATETIME
+                let dt = OffsetDateTime::from_unix_timestamp_nanos((v * 1e9) as i128)
+                    .map_err(|err| field_type_error(field, err))?;
+                let ts = TimestampWithTimeZone::try_from(dt)
+                    .map_err(|err| field_type_error(field, format!("{:?}", err)))?;
+                Some(Cell::Timestamptz(ts))
+            }
+            None => None,
+        },
         _ => {
-            report_error(
-                PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                &format!("field type {:?} not supported", field.r#type),
-            );
-            None
+            return Err(FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                format!("field type {:?} not supported", field.r#type),
+            ));
         }
+    };
+    Ok(cell)
+}
+
+// map BigQuery field type to Postgres column type, returns None if the type
+// is not supported
+fn field_to_pg_type(field: &TableFieldSchema) -> Option<&'static str> {
+    if field.mode.as_deref() == Some("REPEATED") {
+        return match field.r#type {
+            FieldType::Boolean | FieldType::Bool => Some("boolean[]"),
+            FieldType::Int64 | FieldType::Integer => Some("bigint[]"),
+            FieldType::Float64 | FieldType::Float => Some("double precision[]"),
+            FieldType::String => Some("text[]"),
+            _ => None,
+        };
+    }
+    match field.r#type {
+        FieldType::Boolean => Some("boolean"),
+        FieldType::Int64 | FieldType::Integer => Some("bigint"),
+        FieldType::Float64 | FieldType::Float => Some("double precision"),
+        FieldType::Numeric | FieldType::Bignumeric => Some("numeric"),
+        FieldType::String => Some("text"),
+        FieldType::Date => Some("date"),
+        FieldType::Time => Some("time"),
+        FieldType::Datetime => Some("timestamp"),
+        FieldType::Timestamp => Some("timestamptz"),
+        _ => None,
     }
 }
 
+const DIALECT: BigQueryDialect = BigQueryDialect;
+
+// format a cell as BigQuery literal
+fn literal(cell: &Cell) -> FdwResult<String> {
+    DIALECT.cell_literal(cell).ok_or_else(|| {
+        FdwError::new(
+            PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+            format!("value {:?} cannot be used in BigQuery query", cell),
+        )
+    })
+}
+
+// a write buffered until the local transaction commits, as the writes
+// cannot be rolled back on BigQuery
+enum PendingWrite {
+    // rows of an insert batch into a table
+    Insert(String, Vec<serde_json::Value>),
+    // update or delete statement
+    Dml(String),
+}
+
 #[wrappers_fdw(
     version = "0.1.0",
     author = "Supabase",
@@ -94,34 +241,204 @@ pub(crate) struct BigQueryFdw {
     table: String,
     rowid_col: String,
     tgt_cols: Vec<String>,
-    scan_result: Option<(Table, ResultSet)>,
+    qual_exprs: Vec<QualExpr>,
+    scan_sql: String,
+    scan_result: Option<(Vec<TableFieldSchema>, ResultSet)>,
     auth_mock: Option<GoogleAuthMock>,
+    stats: RemoteStats,
+    // buffered writes with the subtransactions in which they are made
+    pending_writes: Vec<(pg_sys::SubTransactionId, PendingWrite)>,
 }
 
 impl BigQueryFdw {
-    fn deparse(&self, quals: &[Qual], columns: &[String]) -> String {
+    fn buffer_write(&mut self, write: PendingWrite) {
+        let subid = unsafe { pg_sys::GetCurrentSubTransactionId() };
+        self.pending_writes.push((subid, write));
+    }
+
+    fn deparse(
+        &self,
+        quals: &[Qual],
+        exprs: &[QualExpr],
+        columns: &[String],
+        sorts: &[Sort],
+    ) -> String {
         let tgts = if columns.is_empty() {
             "*".to_string()
         } else {
-            columns.join(", ")
+            columns
+                .iter()
+                .map(|c| DIALECT.quote_identifier(c))
+                .collect::<Vec<String>>()
+                .join(", ")
         };
         let table = format!("`{}.{}.{}`", self.project_id, self.dataset_id, self.table,);
-        let sql = if quals.is_empty() {
-            format!("select {} from {}", tgts, table)
+        let mut sql = format!("select {} from {}", tgts, table);
+
+        // the quals which cannot be deparsed are checked by Postgres locally
+        let conds = quals
+            .iter()
+            .filter_map(|q| DIALECT.deparse_qual(q))
+            .chain(exprs.iter().filter_map(|e| DIALECT.deparse_qual_expr(e)))
+            .collect::<Vec<String>>();
+        if !conds.is_empty() {
+            sql.push_str(&format!(" where {}", conds.join(" and ")));
+        }
+        if !sorts.is_empty() {
+            let order_by = sorts
+                .iter()
+                .map(|s| DIALECT.deparse_sort(s))
+                .collect::<Vec<String>>()
+                .join(", ");
+            sql.push_str(&format!(" order by {}", order_by));
+        }
+        sql
+    }
+
+    // deparse a pushed down join, result columns are aliased by position as
+    // BigQuery column names cannot contain dots, the join conditions and quals
+    // have been checked by `can_join`, so they can all be deparsed
+    fn deparse_join(&self, join: &Join, columns: &[String]) -> String {
+        let tgts = if columns.is_empty() {
+            "1".to_string()
         } else {
-            let cond = quals
+            columns
                 .iter()
-                .map(|q| q.deparse())
+                .enumerate()
+                .map(|(i, c)| format!("{} as c{}", DIALECT.quote_qualified(c), i))
                 .collect::<Vec<String>>()
-                .join(" and ");
-            format!("select {} from {} where {}", tgts, table, cond)
+                .join(", ")
         };
+        let deparse_table = |tbl: &JoinTable| {
+            let table = format!(
+                "`{}.{}.{}`",
+                self.project_id,
+                self.dataset_id,
+                tbl.options
+                    .get("table")
+                    .map(|t| t.as_str())
+                    .unwrap_or_default()
+            );
+            if tbl.quals.is_empty() {
+                format!("{} as {}", table, tbl.alias)
+            } else {
+                let cond = DIALECT.deparse_quals(&tbl.quals).unwrap_or_default();
+                format!("(select * from {} where {}) as {}", table, cond, tbl.alias)
+            }
+        };
+        let join_type = match join.join_type {
+            JoinType::Inner => "inner join",
+            JoinType::Left => "left join",
+            JoinType::Right => "right join",
+            JoinType::Full => "full join",
+        };
+        let cond = join
+            .quals
+            .iter()
+            .filter_map(|q| DIALECT.deparse_join_qual(q))
+            .collect::<Vec<String>>()
+            .join(" and ");
+        format!(
+            "select {} from {} {} {} on {}",
+            tgts,
+            deparse_table(&join.outer),
+            join_type,
+            deparse_table(&join.inner),
+            cond
+        )
+    }
+
+    // deparse a pushed down aggregation, result columns are aliased by
+    // position as the deparsed aggregates are not valid column names, the
+    // quals have been checked by `can_aggregate`, so they can all be deparsed
+    fn deparse_aggregate(&self, aggregation: &Aggregation, columns: &[String]) -> String {
+        let tgts = if columns.is_empty() {
+            "1".to_string()
+        } else {
+            columns
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    let tgt = match aggregation.aggregates.iter().find(|a| &a.deparse() == c) {
+                        Some(aggregate) => DIALECT.deparse_aggregate(aggregate),
+                        None => DIALECT.quote_identifier(c),
+                    };
+                    format!("{} as c{}", tgt, i)
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let table = format!(
+            "`{}.{}.{}`",
+            self.project_id,
+            self.dataset_id,
+            aggregation
+                .options
+                .get("table")
+                .map(|t| t.as_str())
+                .unwrap_or_default()
+        );
+        let mut sql = format!("select {} from {}", tgts, table);
+        if !aggregation.quals.is_empty() {
+            let cond = DIALECT
+                .deparse_quals(&aggregation.quals)
+                .unwrap_or_default();
+            sql.push_str(&format!(" where {}", cond));
+        }
+        if !aggregation.group_by.is_empty() {
+            let group_by = aggregation
+                .group_by
+                .iter()
+                .map(|c| DIALECT.quote_identifier(c))
+                .collect::<Vec<String>>()
+                .join(", ");
+            sql.push_str(&format!(" group by {}", group_by));
+        }
         sql
     }
+
+    // get the schema fields of a table
+    fn get_table_fields(&self, client: &Client, table: &str) -> FdwResult<Vec<TableFieldSchema>> {
+        let tbl = self
+            .rt
+            .block_on(
+                client
+                    .table()
+                    .get(&self.project_id, &self.dataset_id, table, None),
+            )
+            .map_err(|err| format!("get table metadata failed: {}", err))?;
+        Ok(tbl.schema.fields.unwrap_or_default())
+    }
+
+    // execute query on BigQuery, and record it for EXPLAIN
+    fn execute_query(&mut self, sql: String, location: String) -> FdwResult<ResultSet> {
+        let client = match &self.client {
+            Some(client) => client,
+            None => return Err("BigQuery client is not initialized".into()),
+        };
+        let mut req = QueryRequest::new(sql.clone());
+        req.location = Some(location);
+
+        let start = Instant::now();
+        let rs = self
+            .rt
+            .block_on(client.job().query(&self.project_id, req))
+            .map_err(|err| format!("query failed: {}", err))?;
+        self.stats.requests += 1;
+        self.stats.latency += start.elapsed();
+        self.stats.bytes_processed += rs
+            .query_response()
+            .total_bytes_processed
+            .as_deref()
+            .and_then(|b| b.parse::<u64>().ok())
+            .unwrap_or_default();
+        self.scan_sql = sql;
+        Ok(rs)
+    }
 }
 
 impl ForeignDataWrapper for BigQueryFdw {
-    fn new(options: &HashMap<String, String>) -> Self {
+    fn new(options: &HashMap<String, String>) -> FdwResult<Self> {
         let mut ret = BigQueryFdw {
             rt: create_async_runtime(),
             client: None,
@@ -130,18 +447,16 @@ impl ForeignDataWrapper for BigQueryFdw {
             table: "".to_string(),
             rowid_col: "".to_string(),
             tgt_cols: Vec::new(),
+            qual_exprs: Vec::new(),
+            scan_sql: String::new(),
             scan_result: None,
             auth_mock: None,
+            stats: RemoteStats::default(),
+            pending_writes: Vec::new(),
         };
 
-        let project_id = require_option("project_id", options);
-        let dataset_id = require_option("dataset_id", options);
-
-        if project_id.is_none() || dataset_id.is_none() {
-            return ret;
-        }
-        ret.project_id = project_id.unwrap();
-        ret.dataset_id = dataset_id.unwrap();
+        ret.project_id = require_option("project_id", options)?;
+        ret.dataset_id = require_option("dataset_id", options)?;
 
         // Is authentication mocked
         let mock_auth: bool = options
@@ -163,7 +478,8 @@ impl ForeignDataWrapper for BigQueryFdw {
                 let auth_mock_uri = auth_mock.uri();
                 let dummy_auth_config = dummy_configuration(&auth_mock_uri);
                 ret.auth_mock = Some(auth_mock);
-                let sa_key_json = serde_json::to_string_pretty(&dummy_auth_config).unwrap();
+                let sa_key_json = serde_json::to_string_pretty(&dummy_auth_config)
+                    .map_err(|err| format!("create mock auth config failed: {}", err))?;
                 (auth_mock_uri, sa_key_json)
             }
             false => {
@@ -171,13 +487,10 @@ impl ForeignDataWrapper for BigQueryFdw {
                 match options.get("sa_key") {
                     Some(sa_key) => (uri, sa_key.to_owned()),
                     None => {
-                        let sa_key_id = match require_option("sa_key_id", options) {
-                            Some(sa_key_id) => sa_key_id,
-                            None => return ret,
-                        };
+                        let sa_key_id = require_option("sa_key_id", options)?;
                         let sa_key_json = match get_vault_secret(&sa_key_id) {
                             Some(sa_key) => sa_key,
-                            None => return ret,
+                            None => return Ok(ret),
                         };
                         (uri, sa_key_json)
                     }
@@ -185,61 +498,58 @@ impl ForeignDataWrapper for BigQueryFdw {
             }
         };
 
-        let sa_key = match yup_oauth2::parse_service_account_key(sa_key_json.as_bytes()) {
-            Ok(sa_key) => sa_key,
-            Err(err) => {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("parse service account key JSON failed: {}", err),
-                );
-                return ret;
-            }
-        };
+        let sa_key =
+            yup_oauth2::parse_service_account_key(sa_key_json.as_bytes()).map_err(|err| {
+                FdwError::new(
+                    PgSqlErrorCode::ERRCODE_FDW_INVALID_ATTRIBUTE_VALUE,
+                    format!("parse service account key JSON failed: {}", err),
+                )
+            })?;
 
-        ret.client = match ret.rt.block_on(
-            ClientBuilder::new()
-                .with_auth_base_url(auth_endpoint)
-                // Url of the BigQuery emulator docker image.
-                .with_v2_base_url(api_endpoint)
-                .build_from_service_account_key(sa_key, true),
-        ) {
-            Ok(client) => Some(client),
-            Err(err) => {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("create client failed: {}", err),
-                );
-                None
-            }
-        };
+        let client = ret
+            .rt
+            .block_on(
+                ClientBuilder::new()
+                    .with_auth_base_url(auth_endpoint)
+                    // Url of the BigQuery emulator docker image.
+                    .with_v2_base_url(api_endpoint)
+                    .build_from_service_account_key(sa_key, true),
+            )
+            .map_err(|err| {
+                FdwError::new(
+                    PgSqlErrorCode::ERRCODE_FDW_UNABLE_TO_ESTABLISH_CONNECTION,
+                    format!("create client failed: {}", err),
+                )
+            })?;
+        ret.client = Some(client);
 
-        ret
+        Ok(ret)
     }
 
     fn get_rel_size(
         &mut self,
-        _quals: &[Qual],
-        _columns: &[String],
-        _sorts: &[Sort],
+        quals: &[Qual],
+        columns: &[String],
+        sorts: &[Sort],
         _limit: &Option<Limit>,
-        _options: &HashMap<String, String>,
-    ) -> (i64, i32) {
-        (0, 0)
+        options: &HashMap<String, String>,
+    ) -> FdwResult<ScanEstimate> {
+        // the query is not sent until the scan begins, deparse it here so it
+        // can be shown by EXPLAIN
+        self.table = require_option("table", options)?;
+        self.scan_sql = self.deparse(quals, &self.qual_exprs, columns, sorts);
+        Ok(ScanEstimate::default())
     }
 
     fn begin_scan(
         &mut self,
         quals: &[Qual],
         columns: &[String],
-        _sorts: &[Sort],
+        sorts: &[Sort],
         _limit: &Option<Limit>,
         options: &HashMap<String, String>,
-    ) {
-        let table = require_option("table", options);
-        if table.is_none() {
-            return;
-        }
-        self.table = table.unwrap();
+    ) -> FdwResult<()> {
+        self.table = require_option("table", options)?;
         self.tgt_cols = columns.to_vec();
 
         let location = options
@@ -250,125 +560,274 @@ impl ForeignDataWrapper for BigQueryFdw {
         if let Some(client) = &self.client {
             // get table metadata
             let selected_fields = columns.iter().map(|c| c.as_str()).collect::<Vec<&str>>();
-            let tbl = match self.rt.block_on(client.table().get(
-                &self.project_id,
-                &self.dataset_id,
-                &self.table,
-                Some(selected_fields),
-            )) {
-                Ok(tbl) => tbl,
-                Err(err) => {
-                    report_error(
-                        PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                        &format!("get table metadata failed: {}", err),
-                    );
-                    return;
-                }
-            };
+            let tbl = self
+                .rt
+                .block_on(client.table().get(
+                    &self.project_id,
+                    &self.dataset_id,
+                    &self.table,
+                    Some(selected_fields),
+                ))
+                .map_err(|err| format!("get table metadata failed: {}", err))?;
 
-            let sql = self.deparse(quals, columns);
-            let mut req = QueryRequest::new(sql);
-            req.location = Some(location);
+            // result fields in target column order
+            let fields = tbl.schema.fields.unwrap_or_default();
+            let fields = columns
+                .iter()
+                .filter_map(|col| fields.iter().find(|f| &f.name == col).cloned())
+                .collect();
 
-            // execute query on BigQuery
-            match self.rt.block_on(client.job().query(&self.project_id, req)) {
-                Ok(rs) => {
-                    self.scan_result = Some((tbl, rs));
-                }
-                Err(err) => {
-                    self.scan_result = None;
-                    report_error(
-                        PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                        &format!("query failed: {}", err),
-                    );
+            let sql = self.deparse(quals, &self.qual_exprs, columns, sorts);
+            let rs = self.execute_query(sql, location)?;
+            self.scan_result = Some((fields, rs));
+        }
+        Ok(())
+    }
+
+    fn set_qual_exprs(&mut self, exprs: &[QualExpr]) {
+        self.qual_exprs = exprs.to_vec();
+    }
+
+    fn can_sort(&mut self, sorts: &[Sort]) -> Vec<Sort> {
+        // collations cannot be mapped to the remote side, which sorts text by
+        // bytes, so only the text sorts in C collation are pushed down
+        sorts
+            .iter()
+            .take_while(|s| matches!(s.collate.as_deref(), None | Some("C") | Some("POSIX")))
+            .cloned()
+            .collect()
+    }
+
+    fn can_join(&mut self, join: &Join) -> bool {
+        join.quals
+            .iter()
+            .all(|q| q.operator == "=" && DIALECT.deparse_join_qual(q).is_some())
+            && [&join.outer, &join.inner].iter().all(|tbl| {
+                tbl.options.contains_key("table") && DIALECT.deparse_quals(&tbl.quals).is_some()
+            })
+    }
+
+    fn begin_join_scan(&mut self, join: &Join, columns: &[String]) -> FdwResult<()> {
+        self.tgt_cols = columns.to_vec();
+
+        let location = join
+            .outer
+            .options
+            .get("location")
+            .map(|t| t.to_owned())
+            .unwrap_or_else(|| "US".to_string());
+
+        if let Some(client) = &self.client {
+            let mut tables = Vec::new();
+            for tbl in [&join.outer, &join.inner] {
+                let table = tbl.options.get("table").cloned().unwrap_or_default();
+                let fields = self.get_table_fields(client, &table)?;
+                tables.push((tbl, fields));
+            }
+
+            // result fields in target column order, renamed to the positional
+            // column aliases
+            let mut fields = Vec::new();
+            for (i, col) in columns.iter().enumerate() {
+                let field = col.split_once('.').and_then(|(alias, col_name)| {
+                    tables
+                        .iter()
+                        .find(|(tbl, _)| tbl.alias == alias)
+                        .and_then(|(_, fields)| fields.iter().find(|f| f.name == col_name))
+                });
+                match field {
+                    Some(field) => {
+                        let mut field = field.clone();
+                        field.name = format!("c{}", i);
+                        fields.push(field);
+                    }
+                    None => {
+                        return Err(FdwError::new(
+                            PgSqlErrorCode::ERRCODE_FDW_COLUMN_NAME_NOT_FOUND,
+                            format!("column {} not found", col),
+                        ));
+                    }
                 }
             }
+
+            let sql = self.deparse_join(join, columns);
+            let rs = self.execute_query(sql, location)?;
+            self.scan_result = Some((fields, rs));
         }
+        Ok(())
     }
 
-    fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
-        if let Some((ref tbl, ref mut rs)) = self.scan_result {
-            if rs.next_row() {
-                if let Some(fields) = &tbl.schema.fields {
-                    for tgt_col in &self.tgt_cols {
-                        if let Some(field) = fields.iter().find(|&f| &f.name == tgt_col) {
-                            let cell = field_to_cell(rs, field);
-                            row.push(&field.name, cell);
+    fn can_aggregate(&mut self, aggregation: &Aggregation) -> bool {
+        aggregation.options.contains_key("table")
+            && DIALECT.deparse_quals(&aggregation.quals).is_some()
+    }
+
+    fn begin_aggregate_scan(
+        &mut self,
+        aggregation: &Aggregation,
+        columns: &[String],
+    ) -> FdwResult<()> {
+        self.tgt_cols = columns.to_vec();
+
+        let location = aggregation
+            .options
+            .get("location")
+            .map(|t| t.to_owned())
+            .unwrap_or_else(|| "US".to_string());
+
+        if let Some(client) = &self.client {
+            let table = aggregation
+                .options
+                .get("table")
+                .cloned()
+                .unwrap_or_default();
+            let tbl_fields = self.get_table_fields(client, &table)?;
+            let find_field = |name: &str| tbl_fields.iter().find(|f| f.name == name);
+
+            // result fields in target column order, renamed to the positional
+            // column aliases
+            let mut fields = Vec::new();
+            for (i, col) in columns.iter().enumerate() {
+                let name = format!("c{}", i);
+                let field = match aggregation.aggregates.iter().find(|a| &a.deparse() == col) {
+                    Some(agg) => {
+                        let col_field = agg.field.as_deref().and_then(find_field);
+                        match agg.kind {
+                            AggregateKind::Count => Some(TableFieldSchema::integer(&name)),
+                            AggregateKind::Avg => Some(TableFieldSchema::float(&name)),
+                            AggregateKind::Sum => col_field.map(|f| match f.r#type {
+                                FieldType::Int64 | FieldType::Integer => {
+                                    TableFieldSchema::integer(&name)
+                                }
+                                _ => TableFieldSchema::float(&name),
+                            }),
+                            AggregateKind::Min | AggregateKind::Max => {
+                                col_field.map(|f| TableFieldSchema::new(&name, f.r#type.clone()))
+                            }
                         }
                     }
-                    return Some(());
+                    None => find_field(col).map(|f| TableFieldSchema::new(&name, f.r#type.clone())),
+                };
+                match field {
+                    Some(field) => fields.push(field),
+                    None => {
+                        return Err(FdwError::new(
+                            PgSqlErrorCode::ERRCODE_FDW_COLUMN_NAME_NOT_FOUND,
+                            format!("column {} not found", col),
+                        ));
+                    }
                 }
             }
+
+            let sql = self.deparse_aggregate(aggregation, columns);
+            let rs = self.execute_query(sql, location)?;
+            self.scan_result = Some((fields, rs));
         }
-        None
+        Ok(())
     }
 
-    fn end_scan(&mut self) {
+    fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>> {
+        if let Some((ref fields, ref mut rs)) = self.scan_result {
+            if rs.next_row() {
+                for (tgt_col, field) in self.tgt_cols.iter().zip(fields.iter()) {
+                    let cell = field_to_cell(rs, field)?;
+                    row.push(tgt_col, cell);
+                }
+                return Ok(Some(()));
+            }
+        }
+        Ok(None)
+    }
+
+    fn end_scan(&mut self) -> FdwResult<()> {
         self.scan_result.take();
+        Ok(())
     }
 
-    fn begin_modify(&mut self, options: &HashMap<String, String>) {
-        let table = require_option("table", options);
-        let rowid_col = require_option("rowid_column", options);
-        if table.is_none() || rowid_col.is_none() {
-            return;
+    fn explain(&self, verbose: bool) -> Vec<(String, String)> {
+        if verbose && !self.scan_sql.is_empty() {
+            vec![("Remote SQL".to_string(), self.scan_sql.clone())]
+        } else {
+            Vec::new()
         }
-        self.table = table.unwrap();
-        self.rowid_col = rowid_col.unwrap();
     }
 
-    fn insert(&mut self, src: &Row) {
-        if let Some(ref mut client) = self.client {
-            let mut insert_request = TableDataInsertAllRequest::new();
-            let mut row_json = json!({});
-
-            for (col_name, cell) in src.iter() {
-                if let Some(cell) = cell {
-                    match cell {
-                        Cell::Bool(v) => row_json[col_name] = json!(v),
-                        Cell::I8(v) => row_json[col_name] = json!(v),
-                        Cell::I16(v) => row_json[col_name] = json!(v),
-                        Cell::I32(v) => row_json[col_name] = json!(v),
-                        Cell::I64(v) => row_json[col_name] = json!(v),
-                        Cell::F32(v) => row_json[col_name] = json!(v),
-                        Cell::F64(v) => row_json[col_name] = json!(v),
-                        Cell::String(v) => row_json[col_name] = json!(v),
-                        Cell::Date(v) => row_json[col_name] = json!(v),
-                        Cell::Timestamp(v) => row_json[col_name] = json!(v),
-                        Cell::Json(v) => row_json[col_name] = json!(v),
+    fn remote_stats(&self) -> Option<RemoteStats> {
+        Some(self.stats.clone())
+    }
+
+    fn begin_modify(&mut self, options: &HashMap<String, String>) -> FdwResult<()> {
+        self.table = require_option("table", options)?;
+        self.rowid_col = require_option("rowid_column", options)?;
+        Ok(())
+    }
+
+    fn insert(&mut self, src: &Row) -> FdwResult<Option<Row>> {
+        self.insert_batch(std::slice::from_ref(src))?;
+        Ok(None)
+    }
+
+    fn insert_batch(&mut self, rows: &[Row]) -> FdwResult<()> {
+        if self.client.is_some() {
+            let mut batch = Vec::new();
+            for src in rows {
+                let mut row_json = json!({});
+                for (col_name, cell) in src.iter() {
+                    if let Some(cell) = cell {
+                        match cell {
+                            Cell::Bool(v) => row_json[col_name] = json!(v),
+                            Cell::I8(v) => row_json[col_name] = json!(v),
+                            Cell::I16(v) => row_json[col_name] = json!(v),
+                            Cell::I32(v) => row_json[col_name] = json!(v),
+                            Cell::I64(v) => row_json[col_name] = json!(v),
+                            Cell::F32(v) => row_json[col_name] = json!(v),
+                            Cell::F64(v) => row_json[col_name] = json!(v),
+                            // numeric is sent as string to keep its precision
+                            Cell::Numeric(v) => row_json[col_name] = json!(v.to_string()),
+                            Cell::String(v) => row_json[col_name] = json!(v),
+                            Cell::Date(v) => row_json[col_name] = json!(v),
+                            Cell::Time(v) => row_json[col_name] = json!(v),
+                            Cell::Timestamp(v) => row_json[col_name] = json!(v),
+                            Cell::Timestamptz(v) => row_json[col_name] = json!(v),
+                            Cell::Uuid(v) => row_json[col_name] = json!(v.to_string()),
+                            Cell::Json(v) => row_json[col_name] = json!(v),
+                            Cell::PlainJson(v) => row_json[col_name] = json!(v),
+                            Cell::BoolArray(v) => row_json[col_name] = json!(v),
+                            Cell::I16Array(v) => row_json[col_name] = json!(v),
+                            Cell::I32Array(v) => row_json[col_name] = json!(v),
+                            Cell::I64Array(v) => row_json[col_name] = json!(v),
+                            Cell::F32Array(v) => row_json[col_name] = json!(v),
+                            Cell::F64Array(v) => row_json[col_name] = json!(v),
+                            Cell::StringArray(v) => row_json[col_name] = json!(v),
+                            Cell::Interval(_) | Cell::Bytea(_) => {
+                                return Err(FdwError::new(
+                                    PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                                    format!("field type {:?} not supported", cell),
+                                ));
+                            }
+                        }
                     }
                 }
+                batch.push(row_json);
             }
 
-            insert_request.add_row(None, row_json).unwrap();
-
-            // execute insert job on BigQuery
-            if let Err(err) = self.rt.block_on(client.tabledata().insert_all(
-                &self.project_id,
-                &self.dataset_id,
-                &self.table,
-                insert_request,
-            )) {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("insert failed: {}", err),
-                );
-            }
+            self.buffer_write(PendingWrite::Insert(self.table.clone(), batch));
         }
+        Ok(())
     }
 
-    fn update(&mut self, rowid: &Cell, new_row: &Row) {
-        if let Some(ref mut client) = self.client {
+    fn update(&mut self, rowid: &Cell, new_row: &Row) -> FdwResult<Option<Row>> {
+        if self.client.is_some() {
             let mut sets = Vec::new();
             for (col, cell) in new_row.iter() {
                 if col == &self.rowid_col {
                     continue;
                 }
-                if let Some(cell) = cell {
-                    sets.push(format!("{} = {}", col, cell));
-                } else {
-                    sets.push(format!("{} = null", col));
-                }
+                let value = match cell {
+                    Some(cell) => literal(cell)?,
+                    None => "null".to_string(),
+                };
+                sets.push(format!("{} = {}", DIALECT.quote_identifier(col), value));
             }
             let sql = format!(
                 "update `{}.{}.{}` set {} where {} = {}",
@@ -376,46 +835,225 @@ impl ForeignDataWrapper for BigQueryFdw {
                 self.dataset_id,
                 self.table,
                 sets.join(", "),
-                self.rowid_col,
-                rowid
+                DIALECT.quote_identifier(&self.rowid_col),
+                literal(rowid)?
             );
-
-            let query_job = client
-                .job()
-                .query(&self.project_id, QueryRequest::new(&sql));
-
-            // execute update on BigQuery
-            if let Err(err) = self.rt.block_on(query_job) {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("update failed: {}", err),
-                );
-            }
+            self.buffer_write(PendingWrite::Dml(sql));
         }
+        Ok(None)
     }
 
-    fn delete(&mut self, rowid: &Cell) {
-        if let Some(ref mut client) = self.client {
+    fn delete(&mut self, rowid: &Cell) -> FdwResult<Option<Row>> {
+        if self.client.is_some() {
             let sql = format!(
                 "delete from `{}.{}.{}` where {} = {}",
-                self.project_id, self.dataset_id, self.table, self.rowid_col, rowid
+                self.project_id,
+                self.dataset_id,
+                self.table,
+                DIALECT.quote_identifier(&self.rowid_col),
+                literal(rowid)?
             );
+            self.buffer_write(PendingWrite::Dml(sql));
+        }
+        Ok(None)
+    }
+
+    fn on_pre_commit(&mut self) -> FdwResult<()> {
+        // the writes are flushed in the order they were made
+        if let Some(ref mut client) = self.client {
+            for (_, write) in self.pending_writes.drain(..) {
+                match write {
+                    PendingWrite::Insert(table, rows) => {
+                        let mut insert_request = TableDataInsertAllRequest::new();
+                        for row_json in rows {
+                            insert_request
+                                .add_row(None, row_json)
+                                .map_err(|err| format!("insert failed: {}", err))?;
+                        }
 
-            let query_job = client
-                .job()
-                .query(&self.project_id, QueryRequest::new(&sql));
+                        // execute insert job on BigQuery
+                        self.rt
+                            .block_on(client.tabledata().insert_all(
+                                &self.project_id,
+                                &self.dataset_id,
+                                &table,
+                                insert_request,
+                            ))
+                            .map_err(|err| format!("insert failed: {}", err))?;
+                    }
+                    PendingWrite::Dml(sql) => {
+                        let query_job = client
+                            .job()
+                            .query(&self.project_id, QueryRequest::new(&sql));
 
-            // execute delete on BigQuery
-            if let Err(err) = self.rt.block_on(query_job) {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("update failed: {}", err),
-                );
+                        // execute update or delete on BigQuery
+                        self.rt
+                            .block_on(query_job)
+                            .map_err(|err| format!("modify failed: {}", err))?;
+                    }
+                }
             }
         }
+        Ok(())
+    }
+
+    fn on_commit(&mut self) {
+        self.pending_writes.clear();
+    }
+
+    fn on_abort(&mut self) {
+        // discard the rows inserted in the aborted (sub)transaction
+        let subid = unsafe { pg_sys::GetCurrentSubTransactionId() };
+        self.pending_writes
+            .retain(|(write_subid, _)| *write_subid < subid);
     }
 
-    fn end_modify(&mut self) {}
+    fn sample_rows(
+        &mut self,
+        columns: &[String],
+        target_rows: usize,
+        options: &HashMap<String, String>,
+    ) -> FdwResult<RowSample> {
+        self.table = require_option("table", options)?;
+        self.tgt_cols = columns.to_vec();
+
+        let location = options
+            .get("location")
+            .map(|t| t.to_owned())
+            .unwrap_or_else(|| "US".to_string());
+
+        let mut total_rows = 0.0;
+        if let Some(client) = &self.client {
+            // the total rows come from table metadata, which costs no query
+            let tbl = self
+                .rt
+                .block_on(
+                    client
+                        .table()
+                        .get(&self.project_id, &self.dataset_id, &self.table, None),
+                )
+                .map_err(|err| format!("get table metadata failed: {}", err))?;
+            total_rows = tbl
+                .num_rows
+                .and_then(|n| n.parse::<f64>().ok())
+                .unwrap_or_default();
+
+            // result fields in target column order
+            let fields = tbl.schema.fields.unwrap_or_default();
+            let fields = columns
+                .iter()
+                .filter_map(|col| fields.iter().find(|f| &f.name == col).cloned())
+                .collect();
+
+            // sample the data blocks rather than sort the whole table, which
+            // would be billed for a full scan
+            let mut sql = self.deparse(&[], &[], columns, &[]);
+            if total_rows > target_rows as f64 {
+                let percent = target_rows as f64 * 100.0 / total_rows;
+                sql.push_str(&format!(" tablesample system ({} percent)", percent));
+            }
+            sql.push_str(&format!(" limit {}", target_rows));
+            let rs = self.execute_query(sql, location)?;
+            self.scan_result = Some((fields, rs));
+        }
+
+        let mut rows = Vec::new();
+        let mut row = Row::new();
+        while self.iter_scan(&mut row)?.is_some() {
+            rows.push(row.clone());
+            row.clear();
+        }
+        self.scan_result.take();
+
+        Ok(RowSample { rows, total_rows })
+    }
+
+    fn import_foreign_schema(&mut self, stmt: &ImportForeignSchemaStmt) -> FdwResult<Vec<String>> {
+        let mut ret = Vec::new();
+
+        // remote schema is the BigQuery dataset, which must be the one
+        // specified in server options
+        if stmt.remote_schema != self.dataset_id {
+            return Err(FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_SCHEMA_NOT_FOUND,
+                format!(
+                    "remote schema '{}' does not match server dataset '{}'",
+                    stmt.remote_schema, self.dataset_id
+                ),
+            ));
+        }
+
+        if let Some(client) = &self.client {
+            // list all tables in the dataset
+            let mut tables = Vec::new();
+            let mut page_token: Option<String> = None;
+            loop {
+                let mut opts = ListOptions::default();
+                if let Some(token) = page_token.take() {
+                    opts = opts.page_token(token);
+                }
+                let list = self
+                    .rt
+                    .block_on(
+                        client
+                            .table()
+                            .list(&self.project_id, &self.dataset_id, opts),
+                    )
+                    .map_err(|err| format!("list tables failed: {}", err))?;
+                if let Some(tbls) = list.tables {
+                    tables.extend(tbls.into_iter().map(|t| t.table_reference.table_id));
+                }
+                match list.next_page_token {
+                    Some(token) => page_token = Some(token),
+                    None => break,
+                }
+            }
+
+            for table in tables.iter().filter(|t| stmt.is_importable(t)) {
+                let fields = self.get_table_fields(client, table)?;
+
+                let cols = fields
+                    .iter()
+                    .filter_map(|field| {
+                        field_to_pg_type(field).map(|pg_type| {
+                            let not_null = if field.mode.as_deref() == Some("REQUIRED") {
+                                " not null"
+                            } else {
+                                ""
+                            };
+                            format!("{} {}{}", quote_identifier(&field.name), pg_type, not_null)
+                        })
+                    })
+                    .collect::<Vec<String>>();
+
+                ret.push(format!(
+                    "create foreign table if not exists {}.{} ({}) server {} options (table {})",
+                    quote_identifier(&stmt.local_schema),
+                    quote_identifier(table),
+                    cols.join(", "),
+                    quote_identifier(&stmt.server_name),
+                    quote_literal(table),
+                ));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) -> FdwResult<()> {
+        if let Some(oid) = catalog {
+            match oid {
+                FOREIGN_SERVER_RELATION_ID | FOREIGN_TABLE_RELATION_ID => {
+                    check_options_bool(&options, "async_capable")?;
+                }
+                USER_MAPPING_RELATION_ID => {
+                    check_options_allowed(&options, &["sa_key", "sa_key_id"])?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
 }
 
 use auth_mock::GoogleAuthMock;
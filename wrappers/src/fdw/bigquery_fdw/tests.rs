@@ -57,6 +57,59 @@ mod tests {
 
             assert_eq!(results, vec!["foo", "bar"]);
 
+            c.update("CREATE SCHEMA bq_imported", None, None);
+            c.update(
+                r#"IMPORT FOREIGN SCHEMA test_dataset
+                     FROM SERVER my_bigquery_server INTO bq_imported"#,
+                None,
+                None,
+            );
+            let results = c
+                .select(
+                    "SELECT name FROM bq_imported.test_table ORDER BY id",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("name").ok().and_then(|v| v.value::<&str>()))
+                .collect::<Vec<_>>();
+            assert_eq!(results, vec!["foo", "bar"]);
+
+            // REQUIRED columns are imported as not null
+            assert!(c
+                .select(
+                    r#"SELECT attnotnull FROM pg_attribute
+                       WHERE attrelid = 'bq_imported.test_table'::regclass AND attname = 'id'"#,
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<bool>()
+                .unwrap());
+
+            // BigQuery gives no row estimate, the scan is costed from the
+            // server options and, once analyzed, the table statistics
+            c.update(
+                r#"ALTER SERVER my_bigquery_server
+                     OPTIONS (ADD fdw_startup_cost '1000', ADD fdw_tuple_cost '2')"#,
+                None,
+                None,
+            );
+            let plan = c
+                .select("EXPLAIN SELECT * FROM test_table", None, None)
+                .first()
+                .get_one::<&str>()
+                .unwrap()
+                .to_string();
+            assert!(plan.contains("cost=1000.00..1002.01 rows=1"), "{}", plan);
+            c.update("ANALYZE test_table", None, None);
+            let plan = c
+                .select("EXPLAIN SELECT * FROM test_table", None, None)
+                .first()
+                .get_one::<&str>()
+                .unwrap()
+                .to_string();
+            assert!(plan.contains("cost=1000.00..1004.02 rows=2"), "{}", plan);
+
             // DISABLED: error: [FIXME]
             // insert failed: Request error (error: error decoding response body: missing field `status` at line 1 column 436)
 
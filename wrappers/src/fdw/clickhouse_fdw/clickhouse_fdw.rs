@@ -1,79 +1,197 @@
 use chrono::DateTime;
-use clickhouse_rs::{types, types::Block, types::SqlType, ClientHandle, Pool};
+use clickhouse_rs::{
+    errors::Error as ChError, types, types::Block, types::Decimal, types::SqlType, ClientHandle,
+    Pool,
+};
+use pgx::pg_sys;
 use pgx::prelude::{PgSqlErrorCode, Timestamp};
-use std::collections::HashMap;
+use pgx::{AnyNumeric, Uuid};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Instant;
 use time::OffsetDateTime;
 
 use supabase_wrappers::prelude::*;
 
-fn create_client(rt: &Runtime, conn_str: &str) -> Option<ClientHandle> {
+fn create_client(rt: &Runtime, conn_str: &str) -> FdwResult<ClientHandle> {
     let pool = Pool::new(conn_str);
-    rt.block_on(pool.get_handle()).map_or_else(
-        |err| {
-            report_error(
-                PgSqlErrorCode::ERRCODE_FDW_UNABLE_TO_ESTABLISH_CONNECTION,
-                &format!("connection failed: {}", err),
-            );
-            None
-        },
-        Some,
+    rt.block_on(pool.get_handle()).map_err(|err| {
+        FdwError::new(
+            PgSqlErrorCode::ERRCODE_FDW_UNABLE_TO_ESTABLISH_CONNECTION,
+            format!("connection failed: {}", err),
+        )
+    })
+}
+
+fn query_error(err: ChError) -> FdwError {
+    FdwError::from(format!("query failed: {}", err))
+}
+
+fn field_error(err: ChError) -> FdwError {
+    FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+        format!("get field failed: {}", err),
     )
 }
 
-fn field_to_cell(row: &types::Row<types::Complex>, i: usize) -> Option<Cell> {
-    let sql_type = row.sql_type(i).unwrap();
-    match sql_type {
+// get a field value, which is None if it is null in a Nullable column
+fn field_value<'a, T: types::FromSql<'a>>(
+    row: &'a types::Row<'a, types::Complex>,
+    i: usize,
+    nullable: bool,
+) -> FdwResult<Option<T>> {
+    if nullable {
+        row.get::<Option<T>, usize>(i).map_err(field_error)
+    } else {
+        row.get::<T, usize>(i).map(Some).map_err(field_error)
+    }
+}
+
+fn field_to_cell(row: &types::Row<types::Complex>, i: usize) -> FdwResult<Option<Cell>> {
+    let sql_type = row.sql_type(i).map_err(field_error)?;
+    let (value_type, nullable) = match sql_type {
+        SqlType::Nullable(t) => (t.clone(), true),
+        ref t => (t.clone(), false),
+    };
+    let cell = match value_type {
         SqlType::UInt8 => {
             // Bool is stored as UInt8 in ClickHouse, so we treat it as bool here
-            let value = row.get::<u8, usize>(i).unwrap();
-            Some(Cell::Bool(value != 0))
-        }
-        SqlType::Int16 => {
-            let value = row.get::<i16, usize>(i).unwrap();
-            Some(Cell::I16(value))
-        }
-        SqlType::Int32 => {
-            let value = row.get::<i32, usize>(i).unwrap();
-            Some(Cell::I32(value))
-        }
-        SqlType::UInt32 => {
-            let value = row.get::<u32, usize>(i).unwrap();
-            Some(Cell::I64(value as i64))
-        }
-        SqlType::Float32 => {
-            let value = row.get::<f32, usize>(i).unwrap();
-            Some(Cell::F32(value))
-        }
-        SqlType::Float64 => {
-            let value = row.get::<f64, usize>(i).unwrap();
-            Some(Cell::F64(value))
-        }
-        SqlType::UInt64 => {
-            let value = row.get::<u64, usize>(i).unwrap();
-            Some(Cell::I64(value as i64))
+            field_value::<u8>(row, i, nullable)?.map(|v| Cell::Bool(v != 0))
         }
-        SqlType::Int64 => {
-            let value = row.get::<i64, usize>(i).unwrap();
-            Some(Cell::I64(value))
-        }
-        SqlType::String => {
-            let value = row.get::<String, usize>(i).unwrap();
-            Some(Cell::String(value))
-        }
-        SqlType::DateTime(_) => {
-            let value = row.get::<DateTime<_>, usize>(i).unwrap();
-            let dt = OffsetDateTime::from_unix_timestamp_nanos((value.timestamp_nanos()) as i128)
-                .unwrap();
-            let ts = Timestamp::try_from(dt).unwrap();
-            Some(Cell::Timestamp(ts))
+        SqlType::Bool => field_value::<bool>(row, i, nullable)?.map(Cell::Bool),
+        SqlType::Int16 => field_value::<i16>(row, i, nullable)?.map(Cell::I16),
+        SqlType::Int32 => field_value::<i32>(row, i, nullable)?.map(Cell::I32),
+        SqlType::UInt32 => field_value::<u32>(row, i, nullable)?.map(|v| Cell::I64(v as i64)),
+        SqlType::Float32 => field_value::<f32>(row, i, nullable)?.map(Cell::F32),
+        SqlType::Float64 => field_value::<f64>(row, i, nullable)?.map(Cell::F64),
+        SqlType::UInt64 => field_value::<u64>(row, i, nullable)?.map(|v| Cell::I64(v as i64)),
+        SqlType::Int64 => field_value::<i64>(row, i, nullable)?.map(Cell::I64),
+        SqlType::Decimal(_, _) => field_value::<Decimal>(row, i, nullable)?
+            .map(|v| {
+                AnyNumeric::try_from(v.to_string().as_str())
+                    .map(Cell::Numeric)
+                    .map_err(|err| format!("invalid decimal {}: {}", v, err))
+            })
+            .transpose()?,
+        SqlType::String | SqlType::FixedString(_) => {
+            field_value::<String>(row, i, nullable)?.map(Cell::String)
         }
+        SqlType::Uuid => field_value::<uuid::Uuid>(row, i, nullable)?
+            .map(|v| Cell::Uuid(Uuid::from_bytes(*v.as_bytes()))),
+        SqlType::DateTime(_) => field_value::<DateTime<_>>(row, i, nullable)?
+            .map(|v| {
+                OffsetDateTime::from_unix_timestamp_nanos((v.timestamp_nanos()) as i128)
+                    .ok()
+                    .and_then(|dt| Timestamp::try_from(dt).ok())
+                    .map(Cell::Timestamp)
+                    .ok_or_else(|| format!("invalid timestamp: {}", v))
+            })
+            .transpose()?,
+        SqlType::Array(SqlType::UInt8) => field_value::<Vec<u8>>(row, i, nullable)?
+            .map(|v| Cell::BoolArray(v.into_iter().map(|v| Some(v != 0)).collect())),
+        SqlType::Array(SqlType::Bool) => field_value::<Vec<bool>>(row, i, nullable)?
+            .map(|v| Cell::BoolArray(v.into_iter().map(Some).collect())),
+        SqlType::Array(SqlType::Int16) => field_value::<Vec<i16>>(row, i, nullable)?
+            .map(|v| Cell::I16Array(v.into_iter().map(Some).collect())),
+        SqlType::Array(SqlType::Int32) => field_value::<Vec<i32>>(row, i, nullable)?
+            .map(|v| Cell::I32Array(v.into_iter().map(Some).collect())),
+        SqlType::Array(SqlType::UInt32) => field_value::<Vec<u32>>(row, i, nullable)?
+            .map(|v| Cell::I64Array(v.into_iter().map(|v| Some(v as i64)).collect())),
+        SqlType::Array(SqlType::Int64) => field_value::<Vec<i64>>(row, i, nullable)?
+            .map(|v| Cell::I64Array(v.into_iter().map(Some).collect())),
+        SqlType::Array(SqlType::Float32) => field_value::<Vec<f32>>(row, i, nullable)?
+            .map(|v| Cell::F32Array(v.into_iter().map(Some).collect())),
+        SqlType::Array(SqlType::Float64) => field_value::<Vec<f64>>(row, i, nullable)?
+            .map(|v| Cell::F64Array(v.into_iter().map(Some).collect())),
+        SqlType::Array(SqlType::String) => field_value::<Vec<String>>(row, i, nullable)?
+            .map(|v| Cell::StringArray(v.into_iter().map(Some).collect())),
         _ => {
-            report_error(
+            return Err(FdwError::new(
                 PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
-                &format!("data type {} is not supported", sql_type.to_string()),
-            );
-            None
+                format!("data type {} is not supported", sql_type.to_string()),
+            ));
         }
+    };
+    Ok(cell)
+}
+
+// convert a numeric to ClickHouse decimal value, which is a 64-bit integer
+// scaled by at most 18 decimal digits
+fn numeric_to_value(v: &AnyNumeric) -> FdwResult<types::Value> {
+    let s = v.to_string();
+    let scale = s.split_once('.').map(|(_, frac)| frac.len()).unwrap_or(0);
+    let underlying = s
+        .replace('.', "")
+        .parse::<i64>()
+        .ok()
+        .filter(|_| scale <= 18)
+        .ok_or_else(|| {
+            FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                format!("numeric {} is out of range of ClickHouse decimal", s),
+            )
+        })?;
+    Ok(types::Value::Decimal(Decimal::new(underlying, scale as u8)))
+}
+
+// convert array cell elements to ClickHouse array value, null elements are not
+// supported by ClickHouse arrays of non-nullable type
+fn array_to_value<T: Clone + Into<types::Value>>(
+    arr: &[Option<T>],
+    elem_type: SqlType,
+) -> FdwResult<types::Value> {
+    arr.iter()
+        .map(|v| v.clone().map(Into::into))
+        .collect::<Option<Vec<types::Value>>>()
+        .map(|v| types::Value::Array(elem_type.into(), Arc::new(v)))
+        .ok_or_else(|| {
+            FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                "null array element is not supported",
+            )
+        })
+}
+
+// map ClickHouse column type to Postgres column type, returns None if the
+// type is not supported
+fn ch_to_pg_type(ch_type: &str) -> Option<&'static str> {
+    // arrays of nullable elements are not supported
+    if let Some(elem_type) = ch_type
+        .strip_prefix("Array(")
+        .and_then(|t| t.strip_suffix(')'))
+    {
+        if elem_type.starts_with("Nullable(") {
+            return None;
+        }
+        return match elem_type {
+            "UInt8" | "Bool" => Some("boolean[]"),
+            "Int16" => Some("smallint[]"),
+            "Int32" => Some("integer[]"),
+            "UInt32" | "Int64" => Some("bigint[]"),
+            "Float32" => Some("real[]"),
+            "Float64" => Some("double precision[]"),
+            "String" => Some("text[]"),
+            _ => None,
+        };
+    }
+
+    let ch_type = ch_type
+        .strip_prefix("Nullable(")
+        .and_then(|t| t.strip_suffix(')'))
+        .unwrap_or(ch_type);
+    match ch_type {
+        "UInt8" | "Bool" => Some("boolean"),
+        "Int16" => Some("smallint"),
+        "Int32" => Some("integer"),
+        "UInt32" | "Int64" | "UInt64" => Some("bigint"),
+        "Float32" => Some("real"),
+        "Float64" => Some("double precision"),
+        "String" => Some("text"),
+        "UUID" => Some("uuid"),
+        t if t.starts_with("FixedString(") => Some("text"),
+        t if t.starts_with("Decimal") => Some("numeric"),
+        t if t.starts_with("DateTime") && !t.starts_with("DateTime64") => Some("timestamp"),
+        _ => None,
     }
 }
 
@@ -88,217 +206,810 @@ pub(crate) struct ClickHouseFdw {
     table: String,
     rowid_col: String,
     tgt_cols: Vec<String>,
+    qual_exprs: Vec<QualExpr>,
+    scan_sql: String,
     scan_blk: Option<Block<types::Complex>>,
     row_idx: usize,
+    stats: RemoteStats,
+    // rows inserted in current transaction, as (subtransaction, table, rowid
+    // column, rowid literal), to be removed if the insert is rolled back
+    inserted: Vec<(pg_sys::SubTransactionId, String, String, String)>,
+    // subtransactions in which rows are updated or deleted
+    mutated: Vec<pg_sys::SubTransactionId>,
 }
 
+const DIALECT: ClickHouseDialect = ClickHouseDialect;
+
 impl ClickHouseFdw {
-    fn deparse(&self, quals: &[Qual], columns: &[String]) -> String {
+    // fetch the whole result of a scan query to local, for simplicity purpose,
+    // may need optimization in the future.
+    fn fetch_scan(&mut self, sql: String) -> FdwResult<()> {
+        if let Some(ref mut client) = self.client {
+            let start = Instant::now();
+            let block = self
+                .rt
+                .block_on(client.query(&sql).fetch_all())
+                .map_err(query_error)?;
+            self.stats.requests += 1;
+            self.stats.latency += start.elapsed();
+            self.scan_blk = Some(block);
+        }
+        self.scan_sql = sql;
+        Ok(())
+    }
+
+    // `partition` is the condition selecting the rows of a partition
+    fn deparse(
+        &self,
+        quals: &[Qual],
+        exprs: &[QualExpr],
+        columns: &[String],
+        sorts: &[Sort],
+        partition: Option<&str>,
+    ) -> String {
         let tgts = if columns.is_empty() {
             "*".to_string()
         } else {
-            columns.join(", ")
+            columns
+                .iter()
+                .map(|c| DIALECT.quote_identifier(c))
+                .collect::<Vec<String>>()
+                .join(", ")
         };
-        let sql = if quals.is_empty() {
-            format!("select {} from {}", tgts, &self.table)
+        let mut sql = format!("select {} from {}", tgts, &self.table);
+
+        // the quals which cannot be deparsed are checked by Postgres locally
+        let conds = quals
+            .iter()
+            .filter_map(|q| DIALECT.deparse_qual(q))
+            .chain(exprs.iter().filter_map(|e| DIALECT.deparse_qual_expr(e)))
+            .chain(partition.map(|p| p.to_string()))
+            .collect::<Vec<String>>();
+        if !conds.is_empty() {
+            sql.push_str(&format!(" where {}", conds.join(" and ")));
+        }
+        if !sorts.is_empty() {
+            let order_by = sorts
+                .iter()
+                .map(|s| DIALECT.deparse_sort(s))
+                .collect::<Vec<String>>()
+                .join(", ");
+            sql.push_str(&format!(" order by {}", order_by));
+        }
+        sql
+    }
+
+    // the join conditions and quals have been checked by `can_join`, so they
+    // can all be deparsed
+    fn deparse_join(&self, join: &Join, columns: &[String]) -> String {
+        let tgts = if columns.is_empty() {
+            "1".to_string()
+        } else {
+            columns
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{} as {}",
+                        DIALECT.quote_qualified(c),
+                        DIALECT.quote_identifier(c)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let deparse_table = |tbl: &JoinTable| {
+            let table = tbl
+                .options
+                .get("table")
+                .map(|t| t.as_str())
+                .unwrap_or_default();
+            if tbl.quals.is_empty() {
+                format!("{} as {}", table, tbl.alias)
+            } else {
+                let cond = DIALECT.deparse_quals(&tbl.quals).unwrap_or_default();
+                format!("(select * from {} where {}) as {}", table, cond, tbl.alias)
+            }
+        };
+        let cond = join
+            .quals
+            .iter()
+            .filter_map(|q| DIALECT.deparse_join_qual(q))
+            .collect::<Vec<String>>()
+            .join(" and ");
+        format!(
+            "select {} from {} inner join {} on {}",
+            tgts,
+            deparse_table(&join.outer),
+            deparse_table(&join.inner),
+            cond
+        )
+    }
+
+    // the quals have been checked by `can_aggregate`, so they can all be
+    // deparsed
+    fn deparse_aggregate(&self, aggregation: &Aggregation, columns: &[String]) -> String {
+        let tgts = if columns.is_empty() {
+            "1".to_string()
         } else {
-            let cond = quals
+            columns
                 .iter()
-                .map(|q| q.deparse())
+                .map(|c| {
+                    let tgt = match aggregation.aggregates.iter().find(|a| &a.deparse() == c) {
+                        Some(aggregate) => DIALECT.deparse_aggregate(aggregate),
+                        None => DIALECT.quote_identifier(c),
+                    };
+                    format!("{} as {}", tgt, DIALECT.quote_identifier(c))
+                })
                 .collect::<Vec<String>>()
-                .join(" and ");
-            format!("select {} from {} where {}", tgts, &self.table, cond)
+                .join(", ")
         };
+        let table = aggregation
+            .options
+            .get("table")
+            .map(|t| t.as_str())
+            .unwrap_or_default();
+        let mut sql = format!("select {} from {}", tgts, table);
+        if !aggregation.quals.is_empty() {
+            let cond = DIALECT
+                .deparse_quals(&aggregation.quals)
+                .unwrap_or_default();
+            sql.push_str(&format!(" where {}", cond));
+        }
+        if !aggregation.group_by.is_empty() {
+            let group_by = aggregation
+                .group_by
+                .iter()
+                .map(|c| DIALECT.quote_identifier(c))
+                .collect::<Vec<String>>()
+                .join(", ");
+            sql.push_str(&format!(" group by {}", group_by));
+        }
         sql
     }
 }
 
+fn is_param(value: &Value) -> bool {
+    matches!(value, Value::Param(_))
+}
+
+// deparse `SET` clause of an update, rowid column is excluded
+fn deparse_sets(row: &Row, rowid_col: Option<&String>) -> FdwResult<String> {
+    let mut sets = Vec::new();
+    for (col, cell) in row.iter() {
+        if Some(col) == rowid_col {
+            continue;
+        }
+        let value = match cell {
+            Some(cell) => literal(cell)?,
+            None => "null".to_string(),
+        };
+        sets.push(format!("{} = {}", DIALECT.quote_identifier(col), value));
+    }
+    Ok(sets.join(", "))
+}
+
+// format a cell as ClickHouse literal
+fn literal(cell: &Cell) -> FdwResult<String> {
+    DIALECT.cell_literal(cell).ok_or_else(|| {
+        FdwError::new(
+            PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+            format!("value {:?} cannot be used in ClickHouse query", cell),
+        )
+    })
+}
+
 impl ForeignDataWrapper for ClickHouseFdw {
-    fn new(options: &HashMap<String, String>) -> Self {
+    fn new(options: &HashMap<String, String>) -> FdwResult<Self> {
         let rt = create_async_runtime();
         let client = match options.get("conn_string") {
-            Some(conn_str) => create_client(&rt, conn_str),
-            None => require_option("conn_string_id", options)
-                .and_then(|conn_str_id| get_vault_secret(&conn_str_id))
-                .and_then(|conn_str| create_client(&rt, &conn_str)),
+            Some(conn_str) => Some(create_client(&rt, conn_str)?),
+            None => {
+                let conn_str_id = require_option("conn_string_id", options)?;
+                match get_vault_secret(&conn_str_id) {
+                    Some(conn_str) => Some(create_client(&rt, &conn_str)?),
+                    None => None,
+                }
+            }
         };
 
-        Self {
+        Ok(Self {
             rt,
             client,
             table: "".to_string(),
             rowid_col: "".to_string(),
             tgt_cols: Vec::new(),
+            qual_exprs: Vec::new(),
+            scan_sql: String::new(),
             scan_blk: None,
             row_idx: 0,
-        }
+            stats: RemoteStats::default(),
+            inserted: Vec::new(),
+            mutated: Vec::new(),
+        })
     }
 
     fn get_rel_size(
         &mut self,
         quals: &[Qual],
         columns: &[String],
-        _sorts: &[Sort],
+        sorts: &[Sort],
         _limit: &Option<Limit>,
         options: &HashMap<String, String>,
-    ) -> (i64, i32) {
-        let table = require_option("table", options);
-        let rowid_col = require_option("rowid_column", options);
-        if table.is_none() || rowid_col.is_none() {
-            return (0, 0);
-        }
-        self.table = table.unwrap();
-        self.rowid_col = rowid_col.unwrap();
+    ) -> FdwResult<ScanEstimate> {
+        self.table = require_option("table", options)?;
         self.tgt_cols = columns.to_vec();
 
-        let sql = self.deparse(quals, columns);
+        // parameter values are unknown during planning, they will be applied
+        // when the scan begins
+        let quals: Vec<Qual> = quals
+            .iter()
+            .filter(|q| !is_param(&q.value))
+            .cloned()
+            .collect();
+        let exprs: Vec<QualExpr> = self
+            .qual_exprs
+            .iter()
+            .filter(|e| !e.values().into_iter().any(is_param))
+            .cloned()
+            .collect();
+        let sql = self.deparse(&quals, &exprs, columns, sorts, None);
 
-        if let Some(ref mut client) = self.client {
-            // for simplicity purpose, we fetch whole query result to local,
-            // may need optimization in the future.
-            match self.rt.block_on(client.query(&sql).fetch_all()) {
-                Ok(block) => {
-                    let rows = block.row_count();
-                    let width = block.column_count() * 8;
-                    self.scan_blk = Some(block);
-                    return (rows as i64, width as i32);
-                }
-                Err(err) => report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("query failed: {}", err),
-                ),
-            }
-        }
-        (0, 0)
+        self.fetch_scan(sql)?;
+
+        Ok(match &self.scan_blk {
+            Some(block) => ScanEstimate {
+                rows: block.row_count() as f64,
+                width: (block.column_count() * 8) as i32,
+                is_remote: true,
+                ..Default::default()
+            },
+            None => ScanEstimate::default(),
+        })
     }
 
     fn begin_scan(
         &mut self,
-        _quals: &[Qual],
-        _columns: &[String],
-        _sorts: &[Sort],
+        quals: &[Qual],
+        columns: &[String],
+        sorts: &[Sort],
         _limit: &Option<Limit>,
+        options: &HashMap<String, String>,
+    ) -> FdwResult<()> {
+        self.table = require_option("table", options)?;
+        self.tgt_cols = columns.to_vec();
+        self.row_idx = 0;
+
+        // the instance is shared by the scans planned in the same query, so
+        // the result fetched by get_rel_size is only reused if it is of the
+        // same query, otherwise query again, e.g. with the evaluated parameters
+        // of a parameterized scan
+        let sql = self.deparse(quals, &self.qual_exprs, columns, sorts, None);
+        if self.scan_blk.is_some() && self.scan_sql == sql {
+            return Ok(());
+        }
+
+        self.fetch_scan(sql)
+    }
+
+    fn set_qual_exprs(&mut self, exprs: &[QualExpr]) {
+        self.qual_exprs = exprs.to_vec();
+    }
+
+    fn can_sort(&mut self, sorts: &[Sort]) -> Vec<Sort> {
+        // collations cannot be mapped to the remote side, which sorts text by
+        // bytes, so only the text sorts in C collation are pushed down
+        sorts
+            .iter()
+            .take_while(|s| matches!(s.collate.as_deref(), None | Some("C") | Some("POSIX")))
+            .cloned()
+            .collect()
+    }
+
+    fn can_join(&mut self, join: &Join) -> bool {
+        // outer joins are not supported, because ClickHouse fills the
+        // non-matching rows with default values rather than nulls
+        join.join_type == JoinType::Inner
+            && join
+                .quals
+                .iter()
+                .all(|q| q.operator == "=" && DIALECT.deparse_join_qual(q).is_some())
+            && [&join.outer, &join.inner].iter().all(|tbl| {
+                tbl.options.contains_key("table") && DIALECT.deparse_quals(&tbl.quals).is_some()
+            })
+    }
+
+    fn begin_join_scan(&mut self, join: &Join, columns: &[String]) -> FdwResult<()> {
+        self.tgt_cols = columns.to_vec();
+        self.row_idx = 0;
+
+        let sql = self.deparse_join(join, columns);
+
+        self.fetch_scan(sql)
+    }
+
+    fn partition_scan(
+        &mut self,
+        quals: &[Qual],
+        columns: &[String],
+        options: &HashMap<String, String>,
+    ) -> FdwResult<Vec<String>> {
+        // the rows are split by the hash of rowid column, each partition is
+        // scanned by its own query
+        let partitions = match options.get("parallel_partitions") {
+            Some(n) => n
+                .parse::<u64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("invalid option parallel_partitions: {}", n))?,
+            None => return Ok(Vec::new()),
+        };
+        let rowid_col = DIALECT.quote_identifier(&require_option("rowid_column", options)?);
+        Ok((0..partitions)
+            .map(|i| {
+                // the hash of null is null, so null rowids go to the first
+                // partition
+                let mut cond = format!("cityHash64({}) % {} = {}", rowid_col, partitions, i);
+                if i == 0 {
+                    cond = format!("({} or isNull({}))", cond, rowid_col);
+                }
+                self.deparse(quals, &self.qual_exprs, columns, &[], Some(&cond))
+            })
+            .collect())
+    }
+
+    fn begin_partition_scan(
+        &mut self,
+        partition: &str,
+        columns: &[String],
         _options: &HashMap<String, String>,
-    ) {
+    ) -> FdwResult<()> {
+        self.tgt_cols = columns.to_vec();
+        self.row_idx = 0;
+        self.fetch_scan(partition.to_string())
+    }
+
+    fn can_aggregate(&mut self, aggregation: &Aggregation) -> bool {
+        aggregation.options.contains_key("table")
+            && DIALECT.deparse_quals(&aggregation.quals).is_some()
+    }
+
+    fn begin_aggregate_scan(
+        &mut self,
+        aggregation: &Aggregation,
+        columns: &[String],
+    ) -> FdwResult<()> {
+        self.tgt_cols = columns.to_vec();
         self.row_idx = 0;
+
+        let sql = self.deparse_aggregate(aggregation, columns);
+
+        self.fetch_scan(sql)
     }
 
-    fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
+    fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>> {
         if let Some(block) = &self.scan_blk {
             let mut rows = block.rows();
 
             if let Some(src_row) = rows.nth(self.row_idx) {
                 for tgt_col in &self.tgt_cols {
-                    let (i, _) = block
+                    let i = block
                         .columns()
                         .iter()
-                        .enumerate()
-                        .find(|(_, c)| c.name() == tgt_col)
-                        .unwrap();
-                    let col_name = src_row.name(i).unwrap();
-                    let cell = field_to_cell(&src_row, i);
-                    cell.as_ref()?;
-                    row.push(col_name, cell);
+                        .position(|c| c.name() == tgt_col)
+                        .ok_or_else(|| format!("column {} not found in result", tgt_col))?;
+                    let cell = field_to_cell(&src_row, i)?;
+                    row.push(tgt_col, cell);
                 }
                 self.row_idx += 1;
-                return Some(());
+                return Ok(Some(()));
             }
         }
-        None
+        Ok(None)
     }
 
-    fn end_scan(&mut self) {
+    fn re_scan(&mut self) -> FdwResult<()> {
+        self.row_idx = 0;
+        Ok(())
+    }
+
+    fn end_scan(&mut self) -> FdwResult<()> {
         self.scan_blk.take();
+        Ok(())
     }
 
-    fn begin_modify(&mut self, options: &HashMap<String, String>) {
-        let table = require_option("table", options);
-        let rowid_col = require_option("rowid_column", options);
-        if table.is_none() || rowid_col.is_none() {
-            return;
+    fn explain(&self, verbose: bool) -> Vec<(String, String)> {
+        if verbose && !self.scan_sql.is_empty() {
+            vec![("Remote SQL".to_string(), self.scan_sql.clone())]
+        } else {
+            Vec::new()
         }
-        self.table = table.unwrap();
-        self.rowid_col = rowid_col.unwrap();
     }
 
-    fn insert(&mut self, src: &Row) {
+    fn remote_stats(&self) -> Option<RemoteStats> {
+        Some(self.stats.clone())
+    }
+
+    fn begin_modify(&mut self, options: &HashMap<String, String>) -> FdwResult<()> {
+        self.table = require_option("table", options)?;
+        self.rowid_col = require_option("rowid_column", options)?;
+        Ok(())
+    }
+
+    fn insert(&mut self, src: &Row) -> FdwResult<Option<Row>> {
+        self.insert_batch(std::slice::from_ref(src))?;
+        Ok(None)
+    }
+
+    fn insert_batch(&mut self, rows: &[Row]) -> FdwResult<()> {
         if let Some(ref mut client) = self.client {
-            let mut row = Vec::new();
-            for (col_name, cell) in src.iter() {
-                let col_name = col_name.to_owned();
-                if let Some(cell) = cell {
-                    match cell {
-                        Cell::Bool(v) => row.push((col_name, types::Value::from(*v))),
-                        Cell::F64(v) => row.push((col_name, types::Value::from(*v))),
-                        Cell::I64(v) => row.push((col_name, types::Value::from(*v))),
-                        Cell::String(v) => row.push((col_name, types::Value::from(v.as_str()))),
-                        _ => report_error(
-                            PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
-                            &format!("field type {:?} not supported", cell),
-                        ),
+            // null cells are skipped, and rows with different columns cannot
+            // be in the same block
+            let mut blocks = Vec::new();
+            let mut block = Block::new();
+            let mut block_cols = Vec::new();
+            for src in rows {
+                let mut row = Vec::new();
+                for (col_name, cell) in src.iter() {
+                    let col_name = col_name.to_owned();
+                    if let Some(cell) = cell {
+                        match cell {
+                            Cell::Bool(v) => row.push((col_name, types::Value::from(*v))),
+                            Cell::F64(v) => row.push((col_name, types::Value::from(*v))),
+                            Cell::I64(v) => row.push((col_name, types::Value::from(*v))),
+                            Cell::String(v) => row.push((col_name, types::Value::from(v.as_str()))),
+                            Cell::Numeric(v) => row.push((col_name, numeric_to_value(v)?)),
+                            Cell::Uuid(v) => row.push((
+                                col_name,
+                                types::Value::from(uuid::Uuid::from_bytes(*v.as_bytes())),
+                            )),
+                            Cell::BoolArray(v) => {
+                                row.push((col_name, array_to_value(v, SqlType::Bool)?))
+                            }
+                            Cell::I16Array(v) => {
+                                row.push((col_name, array_to_value(v, SqlType::Int16)?))
+                            }
+                            Cell::I32Array(v) => {
+                                row.push((col_name, array_to_value(v, SqlType::Int32)?))
+                            }
+                            Cell::I64Array(v) => {
+                                row.push((col_name, array_to_value(v, SqlType::Int64)?))
+                            }
+                            Cell::F32Array(v) => {
+                                row.push((col_name, array_to_value(v, SqlType::Float32)?))
+                            }
+                            Cell::F64Array(v) => {
+                                row.push((col_name, array_to_value(v, SqlType::Float64)?))
+                            }
+                            Cell::StringArray(v) => {
+                                row.push((col_name, array_to_value(v, SqlType::String)?))
+                            }
+                            _ => {
+                                return Err(FdwError::new(
+                                    PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                                    format!("field type {:?} not supported", cell),
+                                ))
+                            }
+                        }
                     }
                 }
+                let row_cols: Vec<String> = row.iter().map(|(col, _)| col.clone()).collect();
+                if block.row_count() > 0 && row_cols != block_cols {
+                    blocks.push(block);
+                    block = Block::new();
+                }
+                block_cols = row_cols;
+                block
+                    .push(row)
+                    .map_err(|err| format!("insert failed: {}", err))?;
+            }
+            blocks.push(block);
+
+            // remember the rowids before inserting, so the rows of a partially
+            // failed insert can be removed as well
+            let subid = unsafe { pg_sys::GetCurrentSubTransactionId() };
+            for src in rows {
+                let rowid = src
+                    .iter()
+                    .find(|(col, _)| *col == &self.rowid_col)
+                    .and_then(|(_, cell)| cell.as_ref());
+                if let Some(rowid) = rowid {
+                    self.inserted.push((
+                        subid,
+                        self.table.clone(),
+                        self.rowid_col.clone(),
+                        literal(rowid)?,
+                    ));
+                }
             }
-            let mut block = Block::new();
-            block.push(row).unwrap();
 
             // execute query on ClickHouse
-            if let Err(err) = self.rt.block_on(client.insert(&self.table, block)) {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("insert failed: {}", err),
-                );
+            for block in blocks {
+                self.rt
+                    .block_on(client.insert(&self.table, block))
+                    .map_err(|err| format!("insert failed: {}", err))?;
             }
         }
+        Ok(())
     }
 
-    fn update(&mut self, rowid: &Cell, new_row: &Row) {
+    fn update(&mut self, rowid: &Cell, new_row: &Row) -> FdwResult<Option<Row>> {
         if let Some(ref mut client) = self.client {
-            let mut sets = Vec::new();
-            for (col, cell) in new_row.iter() {
-                if col == &self.rowid_col {
-                    continue;
-                }
-                if let Some(cell) = cell {
-                    sets.push(format!("{} = {}", col, cell));
-                } else {
-                    sets.push(format!("{} = null", col));
-                }
-            }
             let sql = format!(
                 "alter table {} update {} where {} = {}",
                 self.table,
-                sets.join(", "),
-                self.rowid_col,
-                rowid
+                deparse_sets(new_row, Some(&self.rowid_col))?,
+                DIALECT.quote_identifier(&self.rowid_col),
+                literal(rowid)?
             );
 
             // execute query on ClickHouse
-            if let Err(err) = self.rt.block_on(client.execute(&sql)) {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("update failed: {}", err),
-                );
-            }
+            self.rt
+                .block_on(client.execute(&sql))
+                .map_err(|err| format!("update failed: {}", err))?;
+            self.mutated
+                .push(unsafe { pg_sys::GetCurrentSubTransactionId() });
         }
+        Ok(None)
     }
 
-    fn delete(&mut self, rowid: &Cell) {
+    fn delete(&mut self, rowid: &Cell) -> FdwResult<Option<Row>> {
         if let Some(ref mut client) = self.client {
             let sql = format!(
                 "alter table {} delete where {} = {}",
-                self.table, self.rowid_col, rowid
+                self.table,
+                DIALECT.quote_identifier(&self.rowid_col),
+                literal(rowid)?
             );
 
             // execute query on ClickHouse
-            if let Err(err) = self.rt.block_on(client.execute(&sql)) {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                    &format!("delete failed: {}", err),
+            self.rt
+                .block_on(client.execute(&sql))
+                .map_err(|err| format!("delete failed: {}", err))?;
+            self.mutated
+                .push(unsafe { pg_sys::GetCurrentSubTransactionId() });
+        }
+        Ok(None)
+    }
+
+    fn on_commit(&mut self) {
+        self.inserted.clear();
+        self.mutated.clear();
+    }
+
+    // ClickHouse has no transactions, the inserted rows are removed by their
+    // rowids on a best-effort basis, and the updates and deletes cannot be
+    // undone at all
+    fn on_abort(&mut self) {
+        let subid = unsafe { pg_sys::GetCurrentSubTransactionId() };
+        if self.mutated.iter().any(|s| *s >= subid) {
+            report_warning("updates and deletes on ClickHouse cannot be rolled back");
+        }
+        self.mutated.retain(|s| *s < subid);
+
+        let mut aborted: BTreeMap<(String, String), Vec<String>> = BTreeMap::new();
+        self.inserted.retain(|(s, table, rowid_col, rowid)| {
+            if *s < subid {
+                return true;
+            }
+            aborted
+                .entry((table.clone(), rowid_col.clone()))
+                .or_default()
+                .push(rowid.clone());
+            false
+        });
+
+        if let Some(ref mut client) = self.client {
+            for ((table, rowid_col), rowids) in aborted {
+                let sql = format!(
+                    "alter table {} delete where {} in ({})",
+                    table,
+                    DIALECT.quote_identifier(&rowid_col),
+                    rowids.join(", ")
                 );
+                if let Err(err) = self.rt.block_on(client.execute(&sql)) {
+                    report_warning(&format!("remove inserted rows failed: {}", err));
+                }
             }
         }
     }
 
-    fn end_modify(&mut self) {}
+    fn can_direct_modify(&mut self, modify: &DirectModify) -> bool {
+        // key columns cannot be updated by ClickHouse mutations
+        let rowid_col = modify.options.get("rowid_column");
+        modify.options.contains_key("table")
+            && DIALECT.deparse_quals(&modify.quals).is_some()
+            && !modify.sets.cols.iter().any(|c| Some(c) == rowid_col)
+            && modify
+                .sets
+                .cells
+                .iter()
+                .flatten()
+                .all(|cell| DIALECT.cell_literal(cell).is_some())
+    }
+
+    fn direct_modify(&mut self, modify: &DirectModify) -> FdwResult<i64> {
+        let table = modify
+            .options
+            .get("table")
+            .map(|t| t.as_str())
+            .unwrap_or_default();
+        let cond = if modify.quals.is_empty() {
+            "1".to_string()
+        } else {
+            DIALECT.deparse_quals(&modify.quals).unwrap_or_default()
+        };
+
+        if let Some(ref mut client) = self.client {
+            // mutations don't report the affected rows, so count them first
+            let sql = format!("select count() from {} where {}", table, cond);
+            let block = self
+                .rt
+                .block_on(client.query(&sql).fetch_all())
+                .map_err(query_error)?;
+            let affected = match block.rows().next() {
+                Some(row) => row.get::<u64, _>(0).map_err(field_error)?,
+                None => 0,
+            };
+
+            let sql = match modify.modify_type {
+                DirectModifyType::Update => {
+                    let sets = deparse_sets(&modify.sets, None)?;
+                    format!("alter table {} update {} where {}", table, sets, cond)
+                }
+                DirectModifyType::Delete => format!("alter table {} delete where {}", table, cond),
+            };
+
+            // execute query on ClickHouse
+            self.rt
+                .block_on(client.execute(&sql))
+                .map_err(|err| format!("modify failed: {}", err))?;
+
+            return Ok(affected as i64);
+        }
+        Ok(0)
+    }
+
+    fn sample_rows(
+        &mut self,
+        columns: &[String],
+        target_rows: usize,
+        options: &HashMap<String, String>,
+    ) -> FdwResult<RowSample> {
+        self.table = require_option("table", options)?;
+        self.tgt_cols = columns.to_vec();
+        self.row_idx = 0;
+
+        let count_sql = format!("select count() from {}", self.table);
+
+        let mut total_rows = 0u64;
+        if let Some(ref mut client) = self.client {
+            let block = self
+                .rt
+                .block_on(client.query(&count_sql).fetch_all())
+                .map_err(query_error)?;
+            if let Some(row) = block.rows().next() {
+                total_rows = row.get(0).map_err(field_error)?;
+            }
+        }
+
+        // rows are picked randomly by the ratio of target rows, which
+        // needs no sort of the whole table as `order by rand()` does
+        let sample_cond = (total_rows > target_rows as u64).then(|| {
+            let ratio = target_rows as f64 / total_rows as f64;
+            format!("rand() <= {}", (ratio * u32::MAX as f64) as u64)
+        });
+        let sql = format!(
+            "{} limit {}",
+            self.deparse(&[], &[], columns, &[], sample_cond.as_deref()),
+            target_rows
+        );
+
+        if let Some(ref mut client) = self.client {
+            let block = self
+                .rt
+                .block_on(client.query(&sql).fetch_all())
+                .map_err(query_error)?;
+            self.scan_blk = Some(block);
+        }
+
+        let mut rows = Vec::new();
+        let mut row = Row::new();
+        while self.iter_scan(&mut row)?.is_some() {
+            rows.push(row.clone());
+            row.clear();
+        }
+        self.scan_blk.take();
+
+        Ok(RowSample {
+            rows,
+            total_rows: total_rows as f64,
+        })
+    }
+
+    fn import_foreign_schema(&mut self, stmt: &ImportForeignSchemaStmt) -> FdwResult<Vec<String>> {
+        let mut ret = Vec::new();
+
+        if let Some(ref mut client) = self.client {
+            let sql = format!(
+                "select table, name, type, is_in_primary_key from system.columns \
+                 where database = '{}' order by table, position",
+                stmt.remote_schema.replace('\'', "\\'")
+            );
+            let block = self
+                .rt
+                .block_on(client.query(&sql).fetch_all())
+                .map_err(query_error)?;
+
+            // group columns by table, in (column name, column type, is primary key) form
+            let mut tables = BTreeMap::new();
+            for row in block.rows() {
+                let table: String = row.get("table").map_err(field_error)?;
+                let col: String = row.get("name").map_err(field_error)?;
+                let col_type: String = row.get("type").map_err(field_error)?;
+                let is_pk: u8 = row.get("is_in_primary_key").map_err(field_error)?;
+                tables
+                    .entry(table)
+                    .or_insert_with(Vec::new)
+                    .push((col, col_type, is_pk != 0));
+            }
+
+            for (table, cols) in tables.iter().filter(|(t, _)| stmt.is_importable(t)) {
+                let cols = cols
+                    .iter()
+                    .filter_map(|(col, col_type, is_pk)| {
+                        ch_to_pg_type(col_type).map(|pg_type| (col, pg_type, is_pk))
+                    })
+                    .collect::<Vec<_>>();
+                if cols.is_empty() {
+                    continue;
+                }
+
+                // use the first primary key column as rowid column, or the
+                // first column if there is no primary key
+                let rowid_col = cols
+                    .iter()
+                    .find(|(_, _, is_pk)| **is_pk)
+                    .unwrap_or(&cols[0])
+                    .0;
+
+                let col_defs = cols
+                    .iter()
+                    .map(|(col, pg_type, _)| format!("{} {}", quote_identifier(col), pg_type))
+                    .collect::<Vec<String>>();
+
+                // the remote table name is used as is in the remote queries
+                let remote_table = format!(
+                    "{}.{}",
+                    DIALECT.quote_identifier(&stmt.remote_schema),
+                    DIALECT.quote_identifier(table)
+                );
+                ret.push(format!(
+                    "create foreign table if not exists {}.{} ({}) server {} options (table {}, rowid_column {})",
+                    quote_identifier(&stmt.local_schema),
+                    quote_identifier(table),
+                    col_defs.join(", "),
+                    quote_identifier(&stmt.server_name),
+                    quote_literal(&remote_table),
+                    quote_literal(rowid_col),
+                ));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) -> FdwResult<()> {
+        if let Some(oid) = catalog {
+            match oid {
+                FOREIGN_SERVER_RELATION_ID | FOREIGN_TABLE_RELATION_ID => {
+                    check_options_bool(&options, "async_capable")?;
+                }
+                USER_MAPPING_RELATION_ID => {
+                    check_options_allowed(&options, &["conn_string", "conn_string_id"])?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
 }
@@ -3,9 +3,19 @@
 mod tests {
     use clickhouse_rs as ch;
     use pgx::prelude::*;
+    use pgx::spi::SpiClient;
     use pgx::{pg_test, IntoDatum};
     use supabase_wrappers::prelude::create_async_runtime;
 
+    // the remote query executed for a statement, as shown by EXPLAIN ANALYZE
+    fn remote_sql(c: &SpiClient, sql: &str) -> String {
+        c.select(&format!("EXPLAIN (VERBOSE, ANALYZE) {}", sql), None, None)
+            .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+            .find(|l| l.trim_start().starts_with("Remote SQL:"))
+            .unwrap_or_default()
+            .to_string()
+    }
+
     #[pg_test]
     fn clickhouse_smoketest() {
         Spi::execute(|c| {
@@ -87,6 +97,849 @@ mod tests {
                 })
                 .expect("value");
             assert_eq!(remote_value, "test");
+
+            assert_eq!(
+                c.select(
+                    "SELECT b.name FROM test_table a JOIN test_table b ON a.id = b.id",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<&str>()
+                .unwrap(),
+                "test"
+            );
+
+            c.update("CREATE TABLE local_names (name text)", None, None);
+            c.update("INSERT INTO local_names VALUES ('test')", None, None);
+            assert_eq!(
+                c.select(
+                    "SELECT f.name FROM local_names l JOIN test_table f ON f.name = l.name",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<&str>()
+                .unwrap(),
+                "test"
+            );
+
+            // the limit is only pushed down if the table is the only relation
+            // in the query
+            let plan: Vec<&str> = c
+                .select(
+                    "EXPLAIN VERBOSE SELECT name FROM test_table LIMIT 1",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan.iter().any(|l| l.contains("limit = Some(")));
+            let plan: Vec<&str> = c
+                .select(
+                    r#"EXPLAIN VERBOSE SELECT f.name FROM local_names l
+                       JOIN test_table f ON f.name = l.name LIMIT 1"#,
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan.iter().any(|l| l.contains("limit = None")));
+            assert!(!plan.iter().any(|l| l.contains("limit = Some(")));
+
+            // nor if the query ordering cannot be sorted remotely
+            let plan: Vec<&str> = c
+                .select(
+                    "EXPLAIN VERBOSE SELECT name FROM test_table ORDER BY name LIMIT 1",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan.iter().any(|l| l.contains("limit = None")));
+            let plan: Vec<&str> = c
+                .select(
+                    r#"EXPLAIN VERBOSE SELECT name FROM test_table ORDER BY name COLLATE "C" LIMIT 1"#,
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan.iter().any(|l| l.contains("limit = Some(")));
+
+            // string literals are escaped when pushed down
+            assert_eq!(
+                c.select(
+                    "SELECT count(*) FROM test_table WHERE name IN ('it''s', 'test')",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                1
+            );
+            assert_eq!(
+                c.select(
+                    "SELECT count(*) FROM test_table WHERE name = 'te''st\\'",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                0
+            );
+
+            // boolean expressions are pushed down
+            assert_eq!(
+                c.select(
+                    "SELECT name FROM test_table WHERE id = 42 OR NOT (name <> 'test')",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<&str>()
+                .unwrap(),
+                "test"
+            );
+            assert!(remote_sql(
+                &c,
+                "SELECT name FROM test_table WHERE id = 42 OR NOT (name <> 'test')"
+            )
+            .contains("where (`id` = 42 or `name` = 'test')"));
+
+            // function calls, pattern matching and distinct tests are pushed down
+            assert_eq!(
+                c.select(
+                    "SELECT count(*) FROM test_table WHERE upper(name) LIKE 'TE%' AND name IS DISTINCT FROM 'foo'",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                1
+            );
+            let sql = remote_sql(
+                &c,
+                "SELECT name FROM test_table WHERE upper(name) LIKE 'TE%' AND name IS DISTINCT FROM 'foo'",
+            );
+            assert!(sql.contains("upperUTF8(`name`) like 'TE%'"), "{}", sql);
+            assert!(
+                sql.contains(
+                    "not (coalesce(`name` = 'foo', false) or (`name` is null and 'foo' is null))"
+                ),
+                "{}",
+                sql
+            );
+            assert_eq!(
+                c.select(
+                    "SELECT count(*) FROM test_table WHERE name IS NOT DISTINCT FROM 'foo' OR id::text = 'foo'",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                0
+            );
+            let sql = remote_sql(
+                &c,
+                "SELECT name FROM test_table WHERE name IS NOT DISTINCT FROM 'foo' OR id::text = 'foo'",
+            );
+            assert!(
+                sql.contains(
+                    "where ((coalesce(`name` = 'foo', false) or (`name` is null and 'foo' is null)) or toString(`id`) = 'foo')"
+                ),
+                "{}",
+                sql
+            );
+
+            // the remote query and request stats are shown by EXPLAIN
+            let plan: Vec<&str> = c
+                .select(
+                    "EXPLAIN (VERBOSE, ANALYZE) SELECT name FROM test_table WHERE name = 'test'",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan
+                .iter()
+                .any(|l| l.trim_start().starts_with("Remote SQL:") && l.contains("test_table")));
+            assert!(plan
+                .iter()
+                .any(|l| l.trim_start().starts_with("Remote Requests:")));
+
+            // text sorts are only pushed down in C collation
+            assert!(
+                !remote_sql(&c, "SELECT name FROM test_table ORDER BY name").contains("order by")
+            );
+            assert!(remote_sql(
+                &c,
+                r#"SELECT name FROM test_table ORDER BY name COLLATE "C""#
+            )
+            .contains("order by `name` nulls last"));
+
+            assert_eq!(
+                c.select("SELECT name FROM test_table ORDER BY id DESC", None, None)
+                    .first()
+                    .get_one::<&str>()
+                    .unwrap(),
+                "test"
+            );
+
+            assert_eq!(
+                c.select("SELECT count(*) FROM test_table", None, None)
+                    .first()
+                    .get_one::<i64>()
+                    .unwrap(),
+                1
+            );
+
+            // aggregates over no rows return null as in Postgres
+            assert_eq!(
+                c.select(
+                    "SELECT max(id) FROM test_table WHERE name = 'nothing'",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>(),
+                None
+            );
+            assert!(remote_sql(
+                &c,
+                "SELECT max(id), min(name) FROM test_table WHERE name = 'nothing'"
+            )
+            .contains("CAST(maxOrNull(`id`) AS Nullable(Int64))"));
+
+            c.update("CREATE SCHEMA imported", None, None);
+            c.update(
+                r#"IMPORT FOREIGN SCHEMA supa LIMIT TO (test_table)
+                     FROM SERVER my_clickhouse_server INTO imported"#,
+                None,
+                None,
+            );
+            assert_eq!(
+                c.select("SELECT name FROM imported.test_table", None, None)
+                    .first()
+                    .get_one::<&str>()
+                    .unwrap(),
+                "test"
+            );
+
+            // names are quoted in the imported table definitions, and the
+            // Nullable columns can be selected
+            rt.block_on(async {
+                handle
+                    .execute("DROP TABLE IF EXISTS supa.`it's`")
+                    .await?;
+                handle
+                    .execute("CREATE TABLE supa.`it's` (id Int64, `no\"te` Nullable(String)) engine = Memory")
+                    .await?;
+                handle
+                    .execute("INSERT INTO supa.`it's` VALUES (1, NULL), (2, 'x')")
+                    .await
+            })
+            .expect("it's in ClickHouse");
+            c.update(
+                r#"IMPORT FOREIGN SCHEMA supa LIMIT TO ("it's")
+                     FROM SERVER my_clickhouse_server INTO imported"#,
+                None,
+                None,
+            );
+            assert_eq!(
+                c.select(
+                    r#"SELECT id FROM imported."it's" WHERE "no""te" IS NULL"#,
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                1
+            );
+            assert_eq!(
+                c.select(
+                    r#"SELECT "no""te" FROM imported."it's" WHERE id = 2"#,
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<&str>()
+                .unwrap(),
+                "x"
+            );
+
+            c.update(
+                "ALTER FOREIGN TABLE test_table OPTIONS (ADD batch_size '2')",
+                None,
+                None,
+            );
+            c.update(
+                "INSERT INTO test_table (name) VALUES ('a'), ('b'), ('c')",
+                None,
+                None,
+            );
+            assert_eq!(
+                c.select("SELECT count(*) FROM test_table", None, None)
+                    .first()
+                    .get_one::<i64>()
+                    .unwrap(),
+                4
+            );
+
+            c.update("ANALYZE test_table", None, None);
+            assert_eq!(
+                c.select(
+                    "SELECT reltuples::bigint FROM pg_class WHERE oid = 'test_table'::regclass",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                4
+            );
+
+            // the server cost options are used for the costs which are not
+            // estimated by ClickHouse, the table option takes precedence
+            c.update(
+                r#"ALTER SERVER my_clickhouse_server
+                     OPTIONS (ADD fdw_startup_cost '1000', ADD fdw_tuple_cost '2')"#,
+                None,
+                None,
+            );
+            let plan = c
+                .select("EXPLAIN SELECT * FROM test_table", None, None)
+                .first()
+                .get_one::<&str>()
+                .unwrap()
+                .to_string();
+            assert!(plan.contains("cost=1000.00..1008.04 rows=4"), "{}", plan);
+            c.update(
+                "ALTER FOREIGN TABLE test_table OPTIONS (ADD startup_cost '10')",
+                None,
+                None,
+            );
+            let plan = c
+                .select("EXPLAIN SELECT * FROM test_table", None, None)
+                .first()
+                .get_one::<&str>()
+                .unwrap()
+                .to_string();
+            assert!(plan.contains("cost=10.00..18.04 rows=4"), "{}", plan);
+            c.update(
+                "ALTER FOREIGN TABLE test_table OPTIONS (DROP startup_cost)",
+                None,
+                None,
+            );
+            c.update(
+                r#"ALTER SERVER my_clickhouse_server
+                     OPTIONS (DROP fdw_startup_cost, DROP fdw_tuple_cost)"#,
+                None,
+                None,
+            );
+
+            // larger tables are sampled randomly
+            rt.block_on(async {
+                handle
+                    .execute("DROP TABLE IF EXISTS supa.sample_table")
+                    .await?;
+                handle
+                    .execute("CREATE TABLE supa.sample_table engine = Memory AS SELECT nullIf(number, 0) AS id FROM numbers(1000)")
+                    .await
+            })
+            .expect("sample_table in ClickHouse");
+            c.update(
+                r#"CREATE FOREIGN TABLE sample_table (id bigint)
+                     SERVER my_clickhouse_server OPTIONS (table 'sample_table')"#,
+                None,
+                None,
+            );
+            c.update(
+                "ALTER FOREIGN TABLE sample_table ALTER COLUMN id SET STATISTICS 1",
+                None,
+                None,
+            );
+            c.update("ANALYZE sample_table", None, None);
+            assert_eq!(
+                c.select(
+                    "SELECT reltuples::bigint FROM pg_class WHERE oid = 'sample_table'::regclass",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                1000
+            );
+
+            // the scans of different tables in one query each get their own
+            // rows and remote query
+            assert_eq!(
+                c.select(
+                    r#"SELECT count(*) FILTER (WHERE src = 't'), count(*) FILTER (WHERE src = 's')
+                       FROM (SELECT 't' AS src, id FROM test_table
+                             UNION ALL SELECT 's', id FROM sample_table) u"#,
+                    None,
+                    None
+                )
+                .first()
+                .get_two::<i64, i64>(),
+                (Some(4), Some(1000))
+            );
+            let remote_sqls: Vec<String> = c
+                .select(
+                    r#"EXPLAIN VERBOSE SELECT id FROM test_table
+                       UNION ALL SELECT id FROM sample_table"#,
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .filter(|l| l.trim_start().starts_with("Remote SQL:"))
+                .map(|l| l.to_string())
+                .collect();
+            assert_eq!(remote_sqls.len(), 2);
+            assert!(remote_sqls[0].contains("from test_table"));
+            assert!(remote_sqls[1].contains("from sample_table"));
+
+            // the scan is split into partitions for the parallel workers, and
+            // the null rowids are in one of them
+            c.update(
+                r#"ALTER FOREIGN TABLE sample_table
+                     OPTIONS (ADD rowid_column 'id', ADD parallel_partitions '2')"#,
+                None,
+                None,
+            );
+            c.update("SET parallel_setup_cost = 0", None, None);
+            c.update("SET parallel_tuple_cost = 0", None, None);
+            c.update("SET min_parallel_table_scan_size = 0", None, None);
+            let plan: Vec<&str> = c
+                .select("EXPLAIN SELECT id FROM sample_table", None, None)
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan.iter().any(|l| l.contains("Gather")));
+            assert!(plan
+                .iter()
+                .any(|l| l.contains("Parallel Foreign Scan on sample_table")));
+            let ids = c
+                .select("SELECT id FROM sample_table", None, None)
+                .map(|r| r.by_name("id").ok().and_then(|v| v.value::<i64>()))
+                .collect::<Vec<_>>();
+            assert_eq!(ids.len(), 1000);
+            assert_eq!(ids.iter().filter(|id| id.is_none()).count(), 1);
+            let mut ids = ids.into_iter().flatten().collect::<Vec<_>>();
+            ids.sort_unstable();
+            assert_eq!(ids, (1..1000).collect::<Vec<i64>>());
+            c.update("RESET parallel_setup_cost", None, None);
+            c.update("RESET parallel_tuple_cost", None, None);
+            c.update("RESET min_parallel_table_scan_size", None, None);
+
+            // the cached instances are dropped and created again by next query
+            assert!(c
+                .select(
+                    "SELECT wrappers_disconnect('my_clickhouse_server')",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<bool>()
+                .unwrap());
+            assert_eq!(
+                c.select("SELECT count(*) FROM test_table", None, None)
+                    .first()
+                    .get_one::<i64>()
+                    .unwrap(),
+                4
+            );
+
+            // a foreign table queried through a view uses the user mapping of
+            // the view owner, not the one of the current user
+            c.update("CREATE ROLE wrappers_view_owner", None, None);
+            c.update(
+                r#"DO $$ BEGIN
+                     EXECUTE format('GRANT USAGE ON SCHEMA %I TO wrappers_view_owner', current_schema());
+                   END $$"#,
+                None,
+                None,
+            );
+            c.update(
+                "GRANT SELECT ON test_table TO wrappers_view_owner",
+                None,
+                None,
+            );
+            c.update(
+                r#"CREATE USER MAPPING FOR wrappers_view_owner
+                     SERVER my_clickhouse_server
+                     OPTIONS (conn_string 'tcp://default:@localhost:9000/supa')"#,
+                None,
+                None,
+            );
+            c.update(
+                r#"CREATE USER MAPPING FOR CURRENT_USER
+                     SERVER my_clickhouse_server
+                     OPTIONS (conn_string 'tcp://default:@localhost:1/supa')"#,
+                None,
+                None,
+            );
+            c.update(
+                "CREATE VIEW test_view AS SELECT * FROM test_table",
+                None,
+                None,
+            );
+            c.update(
+                "ALTER VIEW test_view OWNER TO wrappers_view_owner",
+                None,
+                None,
+            );
+            assert_eq!(
+                c.select("SELECT count(*) FROM test_view", None, None)
+                    .first()
+                    .get_one::<i64>()
+                    .unwrap(),
+                4
+            );
+            c.update(
+                "DROP USER MAPPING FOR CURRENT_USER SERVER my_clickhouse_server",
+                None,
+                None,
+            );
+
+            rt.block_on(async {
+                handle
+                    .execute("DROP TABLE IF EXISTS supa.typed_table")
+                    .await?;
+                handle
+                    .execute(
+                        "CREATE TABLE supa.typed_table (id UUID, amount Decimal(10, 2), tags Array(String)) engine = Memory",
+                    )
+                    .await?;
+                handle
+                    .execute(
+                        "INSERT INTO supa.typed_table VALUES ('a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11', 12.34, ['a', 'b'])",
+                    )
+                    .await
+            })
+            .expect("typed_table in ClickHouse");
+            c.update(
+                r#"
+                  CREATE FOREIGN TABLE typed_table (
+                    id uuid,
+                    amount numeric,
+                    tags text[]
+                  )
+                  SERVER my_clickhouse_server
+                  OPTIONS (
+                    table 'typed_table',
+                    rowid_column 'id'
+                  )
+             "#,
+                None,
+                None,
+            );
+            assert_eq!(
+                c.select(
+                    "SELECT amount::text FROM typed_table WHERE id = 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::uuid",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<&str>()
+                .unwrap(),
+                "12.34"
+            );
+            assert_eq!(
+                c.select("SELECT tags[2] FROM typed_table", None, None)
+                    .first()
+                    .get_one::<&str>()
+                    .unwrap(),
+                "b"
+            );
+            c.update(
+                "INSERT INTO typed_table VALUES ('b0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11', 5.5, ARRAY['c'])",
+                None,
+                None,
+            );
+            assert_eq!(
+                c.select(
+                    "SELECT amount::text FROM typed_table WHERE id = 'b0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::uuid",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<&str>()
+                .unwrap(),
+                "5.50"
+            );
+
+            // booleans and boolean arrays are read and inserted
+            rt.block_on(async {
+                handle
+                    .execute("DROP TABLE IF EXISTS supa.bool_table")
+                    .await?;
+                handle
+                    .execute(
+                        "CREATE TABLE supa.bool_table (id Int64, flag Bool, flags Array(Bool)) engine = Memory",
+                    )
+                    .await
+            })
+            .expect("bool_table in ClickHouse");
+            c.update(
+                r#"
+                  CREATE FOREIGN TABLE bool_table (
+                    id bigint,
+                    flag boolean,
+                    flags boolean[]
+                  )
+                  SERVER my_clickhouse_server
+                  OPTIONS (
+                    table 'bool_table',
+                    rowid_column 'id'
+                  )
+             "#,
+                None,
+                None,
+            );
+            c.update(
+                "INSERT INTO bool_table VALUES (1, true, ARRAY[true, false])",
+                None,
+                None,
+            );
+            assert!(c
+                .select("SELECT flag FROM bool_table WHERE id = 1", None, None)
+                .first()
+                .get_one::<bool>()
+                .unwrap());
+            assert!(!c
+                .select("SELECT flags[2] FROM bool_table WHERE id = 1", None, None)
+                .first()
+                .get_one::<bool>()
+                .unwrap());
+
+            // the update is sent as one mutation if its quals can be pushed
+            // down, otherwise the rows are updated one by one
+            let plan: Vec<&str> = c
+                .select(
+                    "EXPLAIN UPDATE bool_table SET flag = false WHERE id = 1",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan
+                .iter()
+                .any(|l| l.contains("Foreign Update on bool_table")));
+            c.update(
+                "UPDATE bool_table SET flag = false WHERE id = 1",
+                None,
+                None,
+            );
+            assert!(!c
+                .select("SELECT flag FROM bool_table WHERE id = 1", None, None)
+                .first()
+                .get_one::<bool>()
+                .unwrap());
+
+            let plan: Vec<&str> = c
+                .select(
+                    "EXPLAIN UPDATE bool_table SET flag = true WHERE md5(id::text) = md5('1')",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(!plan.iter().any(|l| l.contains("Foreign Update")));
+            assert!(plan
+                .iter()
+                .any(|l| l.contains("Foreign Scan on bool_table")));
+            c.update(
+                "UPDATE bool_table SET flag = true WHERE md5(id::text) = md5('1')",
+                None,
+                None,
+            );
+            assert!(c
+                .select("SELECT flag FROM bool_table WHERE id = 1", None, None)
+                .first()
+                .get_one::<bool>()
+                .unwrap());
+
+            // the rows are also updated one by one if there are row level
+            // triggers, so the triggers are fired
+            c.update("CREATE TABLE bool_table_log (id bigint)", None, None);
+            c.update(
+                r#"CREATE FUNCTION log_bool_table() RETURNS trigger LANGUAGE plpgsql AS $$
+                     BEGIN
+                       INSERT INTO bool_table_log VALUES (NEW.id);
+                       RETURN NEW;
+                     END
+                   $$"#,
+                None,
+                None,
+            );
+            c.update(
+                r#"CREATE TRIGGER bool_table_update AFTER UPDATE ON bool_table
+                     FOR EACH ROW EXECUTE FUNCTION log_bool_table()"#,
+                None,
+                None,
+            );
+            let plan: Vec<&str> = c
+                .select(
+                    "EXPLAIN UPDATE bool_table SET flag = true WHERE id = 1",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(!plan.iter().any(|l| l.contains("Foreign Update")));
+            c.update("UPDATE bool_table SET flag = true WHERE id = 1", None, None);
+            assert_eq!(
+                c.select("SELECT id FROM bool_table_log", None, None)
+                    .first()
+                    .get_one::<i64>(),
+                Some(1)
+            );
+            c.update("DROP TRIGGER bool_table_update ON bool_table", None, None);
+
+            let plan: Vec<&str> = c
+                .select("EXPLAIN DELETE FROM bool_table WHERE id = 1", None, None)
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan
+                .iter()
+                .any(|l| l.contains("Foreign Delete on bool_table")));
+            c.update("DELETE FROM bool_table WHERE id = 1", None, None);
+            assert_eq!(
+                c.select("SELECT count(*) FROM bool_table", None, None)
+                    .first()
+                    .get_one::<i64>()
+                    .unwrap(),
+                0
+            );
+
+            // the modify in an aborted subtransaction ends with it, the row
+            // inserted in it is removed on ClickHouse, and the table can still
+            // be modified afterwards
+            c.update(
+                r#"DO $$ BEGIN
+                     BEGIN
+                       INSERT INTO bool_table VALUES (2, true, ARRAY[true]);
+                       RAISE EXCEPTION 'abort subtransaction';
+                     EXCEPTION WHEN raise_exception THEN NULL;
+                     END;
+                   END $$"#,
+                None,
+                None,
+            );
+            let remote_count: u64 = rt
+                .block_on(async {
+                    handle
+                        .query("SELECT count() FROM supa.bool_table WHERE id = 2")
+                        .fetch_all()
+                        .await?
+                        .rows()
+                        .next()
+                        .unwrap()
+                        .get(0)
+                })
+                .expect("count");
+            assert_eq!(remote_count, 0);
+            c.update(
+                "INSERT INTO bool_table VALUES (3, true, ARRAY[false])",
+                None,
+                None,
+            );
+            assert!(!c
+                .select("SELECT flags[1] FROM bool_table WHERE id = 3", None, None)
+                .first()
+                .get_one::<bool>()
+                .unwrap());
+
+            // cells are coerced to the declared column types
+            c.update(
+                r#"
+                  CREATE FOREIGN TABLE typed_text_table (
+                    id text,
+                    amount text
+                  )
+                  SERVER my_clickhouse_server
+                  OPTIONS (
+                    table 'typed_table'
+                  )
+             "#,
+                None,
+                None,
+            );
+            assert_eq!(
+                c.select("SELECT id FROM typed_text_table", None, None)
+                    .first()
+                    .get_one::<&str>()
+                    .unwrap(),
+                "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"
+            );
+            assert_eq!(
+                c.select("SELECT amount FROM typed_text_table", None, None)
+                    .first()
+                    .get_one::<&str>()
+                    .unwrap(),
+                "12.34"
+            );
+
+            // timestamp literals are pushed down as is, regardless of the
+            // session time zone
+            rt.block_on(async {
+                handle.execute("DROP TABLE IF EXISTS supa.ts_table").await?;
+                handle
+                    .execute(
+                        "CREATE TABLE supa.ts_table (id Int64, ts DateTime('UTC')) engine = Memory",
+                    )
+                    .await?;
+                handle
+                    .execute("INSERT INTO supa.ts_table VALUES (1, '2023-01-02 03:04:05')")
+                    .await
+            })
+            .expect("ts_table in ClickHouse");
+            c.update(
+                r#"
+                  CREATE FOREIGN TABLE ts_table (
+                    id bigint,
+                    ts timestamp
+                  )
+                  SERVER my_clickhouse_server
+                  OPTIONS (
+                    table 'ts_table'
+                  )
+             "#,
+                None,
+                None,
+            );
+            c.update("SET timezone = 'America/New_York'", None, None);
+            assert_eq!(
+                c.select(
+                    "SELECT id FROM ts_table WHERE ts = '2023-01-02 03:04:05'::timestamp",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                1
+            );
+            let plan: Vec<&str> = c
+                .select(
+                    "EXPLAIN VERBOSE SELECT id FROM ts_table WHERE ts = '2023-01-02 03:04:05'::timestamp",
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("QUERY PLAN").ok().and_then(|v| v.value::<&str>()))
+                .collect();
+            assert!(plan
+                .iter()
+                .any(|l| l.trim_start().starts_with("Remote SQL:")
+                    && l.contains("2023-01-02T03:04:05")));
+            c.update("RESET timezone", None, None);
         });
     }
 }
@@ -13,36 +13,24 @@ use yup_oauth2::ServiceAccountAuthenticator;
 
 use supabase_wrappers::prelude::*;
 
-macro_rules! report_request_error {
-    ($url:ident, $err:ident) => {
-        report_error(
-            PgSqlErrorCode::ERRCODE_FDW_ERROR,
-            &format!("fetch {} failed: {}", $url, $err),
-        )
-    };
+// convert a request error to FdwError, with the HTTP status if there is one
+fn request_error(url: &str, err: reqwest::Error) -> FdwError {
+    let fdw_err = FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_ERROR,
+        format!("fetch {} failed: {}", url, err),
+    );
+    match err.status() {
+        Some(status) => fdw_err.with_remote_status(status.as_u16()),
+        None => fdw_err,
+    }
 }
 
-fn get_oauth2_token(sa_key: &str, rt: &Runtime) -> Option<AccessToken> {
-    let creds = match yup_oauth2::parse_service_account_key(sa_key.as_bytes()) {
-        Ok(creds) => creds,
-        Err(err) => {
-            report_error(
-                PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                &format!("parse service account key JSON failed: {}", err),
-            );
-            return None;
-        }
-    };
-    let sa = match rt.block_on(ServiceAccountAuthenticator::builder(creds).build()) {
-        Ok(sa) => sa,
-        Err(err) => {
-            report_error(
-                PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                &format!("invalid service account key: {}", err),
-            );
-            return None;
-        }
-    };
+fn get_oauth2_token(sa_key: &str, rt: &Runtime) -> FdwResult<AccessToken> {
+    let creds = yup_oauth2::parse_service_account_key(sa_key.as_bytes())
+        .map_err(|err| format!("parse service account key JSON failed: {}", err))?;
+    let sa = rt
+        .block_on(ServiceAccountAuthenticator::builder(creds).build())
+        .map_err(|err| format!("invalid service account key: {}", err))?;
     let scopes = &[
         "https://www.googleapis.com/auth/cloud-platform",
         "https://www.googleapis.com/auth/firebase.database",
@@ -50,16 +38,67 @@ fn get_oauth2_token(sa_key: &str, rt: &Runtime) -> Option<AccessToken> {
         "https://www.googleapis.com/auth/identitytoolkit",
         "https://www.googleapis.com/auth/userinfo.email",
     ];
-    match rt.block_on(sa.token(scopes)) {
-        Ok(token) => Some(token),
-        Err(err) => {
-            report_error(
-                PgSqlErrorCode::ERRCODE_FDW_ERROR,
-                &format!("get token failed: {}", err),
-            );
-            None
-        }
-    }
+    let token = rt
+        .block_on(sa.token(scopes))
+        .map_err(|err| format!("get token failed: {}", err))?;
+    Ok(token)
+}
+
+fn create_client(token: &str) -> FdwResult<ClientWithMiddleware> {
+    let mut headers = header::HeaderMap::new();
+    let value = format!("Bearer {}", token);
+    let mut auth_value = header::HeaderValue::from_str(&value)
+        .map_err(|err| format!("invalid access token: {}", err))?;
+    auth_value.set_sensitive(true);
+    headers.insert(header::AUTHORIZATION, auth_value);
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .map_err(|err| format!("create client failed: {}", err))?;
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
+    Ok(ClientBuilder::new(client)
+        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+        .build())
+}
+
+// parse a timestamp property, which is either in milliseconds since Unix epoch
+// or in ISO 8601 format
+fn parse_timestamp(s: &str, col_type: &str) -> Result<Timestamp, String> {
+    let ts = if col_type == "timestamp" {
+        let millis = s.parse::<i64>().map_err(|err| err.to_string())?;
+        let dt =
+            OffsetDateTime::from_unix_timestamp(millis / 1000).map_err(|err| err.to_string())?;
+        Timestamp::try_from(dt)
+    } else {
+        let dt = PrimitiveDateTime::parse(s, &Iso8601::DEFAULT).map_err(|err| err.to_string())?;
+        Timestamp::try_from(dt)
+    };
+    ts.map_err(|err| err.to_string())
+}
+
+// convert a JSON property to cell of the column type, returns None if the
+// property is not of that type
+fn json_to_cell(v: &JsonValue, col_name: &str, col_type: &str) -> FdwResult<Option<Cell>> {
+    let cell = match col_type {
+        "bool" => v.as_bool().map(Cell::Bool),
+        "i64" => v.as_i64().map(Cell::I64),
+        "string" => v.as_str().map(|a| Cell::String(a.to_owned())),
+        "timestamp" | "timestamp_iso" => match v.as_str() {
+            Some(a) => {
+                let ts = parse_timestamp(a, col_type).map_err(|err| {
+                    format!(
+                        "invalid timestamp '{}' in column '{}': {}",
+                        a, col_name, err
+                    )
+                })?;
+                Some(Cell::Timestamp(ts))
+            }
+            None => None,
+        },
+        "json" => Some(Cell::Json(JsonB(v.clone()))),
+        _ => None,
+    };
+    Ok(cell)
 }
 
 fn body_to_rows(
@@ -67,7 +106,7 @@ fn body_to_rows(
     obj_key: &str,
     normal_cols: Vec<(&str, &str, &str)>,
     tgt_cols: &[String],
-) -> Vec<Row> {
+) -> FdwResult<Vec<Row>> {
     let mut result = Vec::new();
 
     let objs = match resp
@@ -76,7 +115,7 @@ fn body_to_rows(
         .and_then(|v| v.as_array())
     {
         Some(objs) => objs,
-        None => return result,
+        None => return Ok(result),
     };
 
     for obj in objs {
@@ -87,46 +126,28 @@ fn body_to_rows(
             if let Some((src_name, col_name, col_type)) =
                 normal_cols.iter().find(|(_, c, _)| c == tgt_col)
             {
-                let cell = obj
-                    .as_object()
-                    .and_then(|v| v.get(*src_name))
-                    .and_then(|v| match *col_type {
-                        "bool" => v.as_bool().map(Cell::Bool),
-                        "i64" => v.as_i64().map(Cell::I64),
-                        "string" => v.as_str().map(|a| Cell::String(a.to_owned())),
-                        "timestamp" => v.as_str().map(|a| {
-                            let secs = a.parse::<i64>().unwrap() / 1000;
-                            let dt = OffsetDateTime::from_unix_timestamp(secs).unwrap();
-                            let ts = Timestamp::try_from(dt).unwrap();
-                            Cell::Timestamp(ts)
-                        }),
-                        "timestamp_iso" => v.as_str().map(|a| {
-                            let dt = PrimitiveDateTime::parse(a, &Iso8601::DEFAULT).unwrap();
-                            let ts = Timestamp::try_from(dt).unwrap();
-                            Cell::Timestamp(ts)
-                        }),
-                        "json" => Some(Cell::Json(JsonB(v.clone()))),
-                        _ => None,
-                    });
+                let cell = match obj.as_object().and_then(|v| v.get(*src_name)) {
+                    Some(v) => json_to_cell(v, col_name, col_type)?,
+                    None => None,
+                };
                 row.push(col_name, cell);
             }
         }
 
         // put all properties into 'attrs' JSON column
         if tgt_cols.iter().any(|c| c == "attrs") {
-            let attrs = serde_json::from_str(&obj.to_string()).unwrap();
-            row.push("attrs", Some(Cell::Json(JsonB(attrs))));
+            row.push("attrs", Some(Cell::Json(JsonB(obj.clone()))));
         }
 
         result.push(row);
     }
 
-    result
+    Ok(result)
 }
 
 // convert response body text to rows
-fn resp_to_rows(obj: &str, resp: &JsonValue, tgt_cols: &[String]) -> Vec<Row> {
-    let mut result = Vec::new();
+fn resp_to_rows(obj: &str, resp: &JsonValue, tgt_cols: &[String]) -> FdwResult<Vec<Row>> {
+    let result;
 
     match obj {
         "auth/users" => {
@@ -139,7 +160,7 @@ fn resp_to_rows(obj: &str, resp: &JsonValue, tgt_cols: &[String]) -> Vec<Row> {
                     ("createdAt", "created_at", "timestamp"),
                 ],
                 tgt_cols,
-            );
+            )?;
         }
         _ => {
             // match firestore documents
@@ -154,17 +175,17 @@ fn resp_to_rows(obj: &str, resp: &JsonValue, tgt_cols: &[String]) -> Vec<Row> {
                         ("updateTime", "updated_at", "timestamp_iso"),
                     ],
                     tgt_cols,
-                );
+                )?;
             } else {
-                report_error(
+                return Err(FdwError::new(
                     PgSqlErrorCode::ERRCODE_FDW_TABLE_NOT_FOUND,
-                    &format!("'{}' object is not implemented", obj),
-                );
+                    format!("'{}' object is not implemented", obj),
+                ));
             }
         }
     }
 
-    result
+    Ok(result)
 }
 
 #[wrappers_fdw(
@@ -177,9 +198,30 @@ pub(crate) struct FirebaseFdw {
     project_id: String,
     client: Option<ClientWithMiddleware>,
     scan_result: Option<Vec<Row>>,
+
+    // service account key and the access token got by it, which needs to be
+    // refreshed when it is expired
+    sa_key: Option<String>,
+    access_token: Option<AccessToken>,
 }
 
 impl FirebaseFdw {
+    // the instance is cached across queries, so its access token may have
+    // expired since it was created
+    fn refresh_client(&mut self) -> FdwResult<()> {
+        if let (Some(sa_key), Some(access_token)) = (&self.sa_key, &self.access_token) {
+            if access_token.is_expired() {
+                let access_token = get_oauth2_token(sa_key, &self.rt)?;
+                let token = access_token
+                    .token()
+                    .ok_or("get token failed: empty access token")?;
+                self.client = Some(create_client(token)?);
+                self.access_token = Some(access_token);
+            }
+        }
+        Ok(())
+    }
+
     const DEFAULT_AUTH_BASE_URL: &'static str =
         "https://identitytoolkit.googleapis.com/v1/projects";
     const DEFAULT_FIRESTORE_BASE_URL: &'static str =
@@ -220,18 +262,18 @@ impl FirebaseFdw {
                 // match for firestore documents
                 // ref: https://firebase.google.com/docs/firestore/reference/rest/v1beta1/projects.databases.documents/listDocuments
                 let re = Regex::new(r"^firestore/(?P<collection>[^/]+)").unwrap();
-                if let Some(caps) = re.captures(obj) {
+                if let Some(collection) = re.captures(obj).and_then(|caps| caps.name("collection"))
+                {
                     let base_url = require_option_or(
                         "base_url",
                         options,
                         Self::DEFAULT_FIRESTORE_BASE_URL.to_owned(),
                     );
-                    let collection = caps.name("collection").unwrap().as_str();
                     let mut ret = format!(
                         "{}/{}/databases/(default)/documents/{}?pageSize={}",
                         base_url,
                         self.project_id,
-                        collection,
+                        collection.as_str(),
                         Self::PAGE_SIZE,
                     );
                     if let Some(next_page_token) = next_page {
@@ -247,18 +289,17 @@ impl FirebaseFdw {
 }
 
 impl ForeignDataWrapper for FirebaseFdw {
-    fn new(options: &HashMap<String, String>) -> Self {
+    fn new(options: &HashMap<String, String>) -> FdwResult<Self> {
         let mut ret = Self {
             rt: create_async_runtime(),
             project_id: "".to_string(),
             client: None,
             scan_result: None,
+            sa_key: None,
+            access_token: None,
         };
 
-        ret.project_id = match require_option("project_id", options) {
-            Some(project_id) => project_id,
-            None => return ret,
-        };
+        ret.project_id = require_option("project_id", options)?;
 
         // get oauth2 access token if it is directly defined in options
         let token = if let Some(access_token) = options.get("access_token") {
@@ -268,40 +309,26 @@ impl ForeignDataWrapper for FirebaseFdw {
             let sa_key = match options.get("sa_key") {
                 Some(sa_key) => sa_key.to_owned(),
                 None => {
-                    let sa_key_id = match require_option("sa_key_id", options) {
-                        Some(sa_key_id) => sa_key_id,
-                        None => return ret,
-                    };
+                    let sa_key_id = require_option("sa_key_id", options)?;
                     match get_vault_secret(&sa_key_id) {
                         Some(sa_key) => sa_key,
-                        None => return ret,
+                        None => return Ok(ret),
                     }
                 }
             };
-            if let Some(access_token) = get_oauth2_token(&sa_key, &ret.rt) {
-                access_token.token().map(|t| t.to_owned()).unwrap()
-            } else {
-                return ret;
-            }
+            let access_token = get_oauth2_token(&sa_key, &ret.rt)?;
+            let token = access_token
+                .token()
+                .map(|t| t.to_owned())
+                .ok_or("get token failed: empty access token")?;
+            ret.sa_key = Some(sa_key);
+            ret.access_token = Some(access_token);
+            token
         };
 
-        // create client
-        let mut headers = header::HeaderMap::new();
-        let value = format!("Bearer {}", token);
-        let mut auth_value = header::HeaderValue::from_str(&value).unwrap();
-        auth_value.set_sensitive(true);
-        headers.insert(header::AUTHORIZATION, auth_value);
-        let client = reqwest::Client::builder()
-            .default_headers(headers)
-            .build()
-            .unwrap();
-        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-        let client = ClientBuilder::new(client)
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build();
-        ret.client = Some(client);
-
-        ret
+        ret.client = Some(create_client(&token)?);
+
+        Ok(ret)
     }
 
     fn begin_scan(
@@ -311,15 +338,16 @@ impl ForeignDataWrapper for FirebaseFdw {
         _sorts: &[Sort],
         _limit: &Option<Limit>,
         options: &HashMap<String, String>,
-    ) {
-        let obj = match require_option("object", options) {
-            Some(obj) => obj,
-            None => return,
+    ) -> FdwResult<()> {
+        self.refresh_client()?;
+
+        let obj = require_option("object", options)?;
+        let row_cnt_limit = match options.get("limit") {
+            Some(n) => n
+                .parse::<usize>()
+                .map_err(|_| format!("invalid option limit: {}", n))?,
+            None => Self::DEFAULT_ROWS_LIMIT,
         };
-        let row_cnt_limit = options
-            .get("limit")
-            .map(|n| n.parse::<usize>().unwrap())
-            .unwrap_or(Self::DEFAULT_ROWS_LIMIT);
 
         self.scan_result = None;
 
@@ -330,63 +358,72 @@ impl ForeignDataWrapper for FirebaseFdw {
             loop {
                 let url = self.build_url(&obj, &next_page, options);
 
-                match self.rt.block_on(client.get(&url).send()) {
-                    Ok(resp) => match resp.error_for_status() {
-                        Ok(resp) => {
-                            let body = self.rt.block_on(resp.text()).unwrap();
-                            let json: JsonValue = serde_json::from_str(&body).unwrap();
-                            let mut rows = resp_to_rows(&obj, &json, columns);
-                            result.append(&mut rows);
-                            if result.len() >= row_cnt_limit {
-                                break;
-                            }
-
-                            // get next page token, stop fetching if no more pages
-                            next_page = json
-                                .get("nextPageToken")
-                                .and_then(|v| v.as_str())
-                                .map(|v| v.to_owned());
-                            if next_page.is_none() {
-                                break;
-                            }
-                        }
-                        Err(err) => {
-                            report_request_error!(url, err);
-                            break;
-                        }
-                    },
-                    Err(err) => {
-                        report_request_error!(url, err);
-                        break;
-                    }
+                let resp = self
+                    .rt
+                    .block_on(client.get(&url).send())
+                    .map_err(|err| format!("fetch {} failed: {}", url, err))?
+                    .error_for_status()
+                    .map_err(|err| request_error(&url, err))?;
+                let body = self
+                    .rt
+                    .block_on(resp.text())
+                    .map_err(|err| request_error(&url, err))?;
+                let json: JsonValue = serde_json::from_str(&body)
+                    .map_err(|err| format!("invalid response: {}", err))?;
+                let mut rows = resp_to_rows(&obj, &json, columns)?;
+                result.append(&mut rows);
+                if result.len() >= row_cnt_limit {
+                    break;
+                }
+
+                // get next page token, stop fetching if no more pages
+                next_page = json
+                    .get("nextPageToken")
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_owned());
+                if next_page.is_none() {
+                    break;
                 }
             }
 
             self.scan_result = Some(result);
         }
+        Ok(())
     }
 
-    fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
+    fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>> {
         if let Some(ref mut result) = self.scan_result {
             if !result.is_empty() {
-                return result
+                return Ok(result
                     .drain(0..1)
                     .last()
-                    .map(|src_row| row.replace_with(src_row));
+                    .map(|src_row| row.replace_with(src_row)));
             }
         }
-        None
+        Ok(None)
     }
 
-    fn end_scan(&mut self) {
+    fn end_scan(&mut self) -> FdwResult<()> {
         self.scan_result.take();
+        Ok(())
     }
 
-    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) {
+    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) -> FdwResult<()> {
         if let Some(oid) = catalog {
-            if oid == FOREIGN_TABLE_RELATION_ID {
-                check_options_contain(&options, "object");
+            match oid {
+                FOREIGN_SERVER_RELATION_ID => {
+                    check_options_bool(&options, "async_capable")?;
+                }
+                FOREIGN_TABLE_RELATION_ID => {
+                    check_options_contain(&options, "object")?;
+                    check_options_bool(&options, "async_capable")?;
+                }
+                USER_MAPPING_RELATION_ID => {
+                    check_options_allowed(&options, &["sa_key", "sa_key_id", "access_token"])?;
+                }
+                _ => {}
             }
         }
+        Ok(())
     }
 }
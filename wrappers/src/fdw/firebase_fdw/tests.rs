@@ -57,6 +57,44 @@ mod tests {
 
             assert_eq!(results, vec!["bo@supabase.io", "copple@supabase.io"]);
 
+            // the attrs column is returned after the other columns, the cells
+            // are matched to the target columns by name
+            c.update(
+                r#"
+                  CREATE FOREIGN TABLE firebase_users_attrs (
+                    attrs jsonb,
+                    email text
+                  )
+                 SERVER my_firebase_server
+                 OPTIONS (
+                   object 'auth/users',
+                   base_url 'http://localhost:9099/identitytoolkit.googleapis.com/v1/projects'
+                )
+             "#,
+                None,
+                None,
+            );
+            let results = c
+                .select(
+                    "SELECT attrs->>'email' AS attrs_email, email FROM firebase_users_attrs",
+                    None,
+                    None,
+                )
+                .filter_map(|r| {
+                    r.by_name("attrs_email")
+                        .ok()
+                        .and_then(|v| v.value::<&str>())
+                        .zip(r.by_name("email").ok().and_then(|v| v.value::<&str>()))
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                results,
+                vec![
+                    ("bo@supabase.io", "bo@supabase.io"),
+                    ("copple@supabase.io", "copple@supabase.io")
+                ]
+            );
+
             c.update(
                 r#"
                 CREATE FOREIGN TABLE firebase_docs (
@@ -93,4 +131,44 @@ mod tests {
                  serde_json::json!({"id": {"integerValue": "1"}, "name": {"stringValue": "hello"}}))]);
         });
     }
+
+    #[pg_test(error = "target column \"foo\" is missing in returned row")]
+    fn firebase_missing_column_test() {
+        Spi::execute(|c| {
+            c.update(
+                r#"CREATE FOREIGN DATA WRAPPER firebase_wrapper
+                         HANDLER firebase_fdw_handler VALIDATOR firebase_fdw_validator"#,
+                None,
+                None,
+            );
+            c.update(
+                r#"CREATE SERVER my_firebase_server
+                         FOREIGN DATA WRAPPER firebase_wrapper
+                         OPTIONS (
+                          project_id 'supa',
+                          access_token 'owner'
+                         )"#,
+                None,
+                None,
+            );
+            c.update(
+                r#"
+                  CREATE FOREIGN TABLE firebase_users (
+                    email text,
+                    foo text
+                  )
+                 SERVER my_firebase_server
+                 OPTIONS (
+                   object 'auth/users',
+                   base_url 'http://localhost:9099/identitytoolkit.googleapis.com/v1/projects'
+                )
+             "#,
+                None,
+                None,
+            );
+
+            // the wrapper doesn't return unknown columns
+            c.select("SELECT email, foo FROM firebase_users", None, None);
+        });
+    }
 }
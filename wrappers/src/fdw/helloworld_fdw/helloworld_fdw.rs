@@ -29,11 +29,11 @@ impl ForeignDataWrapper for HelloWorldFdw {
     // You can do any initalization in this new() function, like saving connection
     // info or API url in an variable, but don't do any heavy works like making a
     // database connection or API call.
-    fn new(_options: &HashMap<String, String>) -> Self {
-        Self {
+    fn new(_options: &HashMap<String, String>) -> FdwResult<Self> {
+        Ok(Self {
             row_cnt: 0,
             tgt_cols: Vec::new(),
-        }
+        })
     }
 
     fn begin_scan(
@@ -43,15 +43,16 @@ impl ForeignDataWrapper for HelloWorldFdw {
         _sorts: &[Sort],
         _limit: &Option<Limit>,
         _options: &HashMap<String, String>,
-    ) {
+    ) -> FdwResult<()> {
         // reset row counter
         self.row_cnt = 0;
 
         // save a copy of target columns
         self.tgt_cols = columns.to_vec();
+        Ok(())
     }
 
-    fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
+    fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>> {
         // this is called on each row and we only return one row here
         if self.row_cnt < 1 {
             // add values to row if they are in target column list
@@ -66,14 +67,15 @@ impl ForeignDataWrapper for HelloWorldFdw {
             self.row_cnt += 1;
 
             // return Some(()) to Postgres and continue data scan
-            return Some(());
+            return Ok(Some(()));
         }
 
         // return 'None' to stop data scan
-        None
+        Ok(None)
     }
 
-    fn end_scan(&mut self) {
+    fn end_scan(&mut self) -> FdwResult<()> {
         // we do nothing here, but you can do things like resource cleanup and etc.
+        Ok(())
     }
 }
@@ -6,33 +6,56 @@ use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde_json::{Map as JsonMap, Number, Value as JsonValue};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
 use time::OffsetDateTime;
 
 use supabase_wrappers::prelude::*;
 
-fn create_client(api_key: &str) -> ClientWithMiddleware {
+fn create_client(api_key: &str) -> FdwResult<ClientWithMiddleware> {
     let mut headers = header::HeaderMap::new();
     let value = format!("Bearer {}", api_key);
-    let mut auth_value = header::HeaderValue::from_str(&value).unwrap();
+    let mut auth_value = header::HeaderValue::from_str(&value).map_err(|err| {
+        FdwError::new(
+            PgSqlErrorCode::ERRCODE_FDW_INVALID_ATTRIBUTE_VALUE,
+            format!("invalid api key: {}", err),
+        )
+    })?;
     auth_value.set_sensitive(true);
     headers.insert(header::AUTHORIZATION, auth_value);
     let client = reqwest::Client::builder()
         .default_headers(headers)
         .build()
-        .unwrap();
+        .map_err(|err| format!("create client failed: {}", err))?;
     let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-    ClientBuilder::new(client)
+    Ok(ClientBuilder::new(client)
         .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-        .build()
+        .build())
+}
+
+// convert a request error to FdwError, with the HTTP status if any
+fn request_error<E: std::fmt::Display>(err: E, status: Option<reqwest::StatusCode>) -> FdwError {
+    let err = FdwError::from(format!("request failed: {}", err));
+    match status {
+        Some(status) => err.with_remote_status(status.as_u16()),
+        None => err,
+    }
+}
+
+fn parse_body(resp_body: &str) -> FdwResult<JsonValue> {
+    serde_json::from_str(resp_body)
+        .map_err(|err| format!("parse response body failed: {}", err).into())
 }
 
 fn body_to_rows(
     resp_body: &str,
     normal_cols: Vec<(&str, &str)>,
     tgt_cols: &[String],
-) -> (Vec<Row>, Option<String>, Option<bool>) {
+) -> FdwResult<Vec<Row>> {
     let mut result = Vec::new();
-    let value: JsonValue = serde_json::from_str(resp_body).unwrap();
+    let value = parse_body(resp_body)?;
     let is_list = value
         .as_object()
         .and_then(|v| v.get("object"))
@@ -57,8 +80,10 @@ fn body_to_rows(
                 .as_object()
                 .and_then(|v| v.get(*bal_type))
                 .and_then(|v| v.as_array())
-                .map(|v| v[0].as_object().unwrap().clone())
-                .unwrap();
+                .and_then(|v| v.first())
+                .and_then(|v| v.as_object())
+                .cloned()
+                .ok_or_else(|| format!("invalid balance object: missing {}", bal_type))?;
             obj.insert(
                 "balance_type".to_string(),
                 JsonValue::String(bal_type.to_string()),
@@ -71,18 +96,17 @@ fn body_to_rows(
         value
             .as_object()
             .map(|v| vec![JsonValue::Object(v.clone())])
-            .unwrap()
+            .ok_or("response body is not an object")?
     };
     let objs = if is_list {
         value
             .as_object()
             .and_then(|v| v.get("data"))
             .and_then(|v| v.as_array())
-            .unwrap()
+            .ok_or("list response has no data")?
     } else {
         &single_wrapped
     };
-    let mut cursor: Option<String> = None;
 
     for obj in objs {
         let mut row = Row::new();
@@ -97,43 +121,329 @@ fn body_to_rows(
                         "bool" => v.as_bool().map(Cell::Bool),
                         "i64" => v.as_i64().map(Cell::I64),
                         "string" => v.as_str().map(|a| Cell::String(a.to_owned())),
-                        "timestamp" => v.as_i64().map(|a| {
-                            let dt = OffsetDateTime::from_unix_timestamp(a).unwrap();
-                            let ts = Timestamp::try_from(dt).unwrap();
-                            Cell::Timestamp(ts)
-                        }),
+                        "timestamp" => v
+                            .as_i64()
+                            .and_then(|a| OffsetDateTime::from_unix_timestamp(a).ok())
+                            .and_then(|dt| Timestamp::try_from(dt).ok())
+                            .map(Cell::Timestamp),
                         _ => None,
                     });
                 row.push(col_name, cell);
             } else if tgt_col == "attrs" {
                 // put all properties into 'attrs' JSON column
-                let attrs = serde_json::from_str(&obj.to_string()).unwrap();
-                row.push("attrs", Some(Cell::Json(JsonB(attrs))));
+                row.push("attrs", Some(Cell::Json(JsonB(obj.clone()))));
             }
         }
 
         result.push(row);
     }
 
-    // get last object's id as cursor
-    if let Some(last_obj) = objs.last() {
-        cursor = last_obj
-            .as_object()
-            .and_then(|v| v.get("id"))
-            .and_then(|v| v.as_str())
-            .map(|v| v.to_owned());
-    }
+    Ok(result)
+}
 
-    // get 'has_more' attribute
+// get the pagination cursor of a response body, which is the last object's
+// id of a list, and whether the list has more objects
+fn page_cursor(resp_body: &str) -> FdwResult<(Option<String>, bool)> {
+    let value = parse_body(resp_body)?;
+    let cursor = value
+        .as_object()
+        .and_then(|v| v.get("data"))
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.last())
+        .and_then(|v| v.get("id"))
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_owned());
     let has_more = value
         .as_object()
         .and_then(|v| v.get("has_more"))
-        .and_then(|v| v.as_bool());
+        .and_then(|v| v.as_bool())
+        .unwrap_or_default();
+    Ok((cursor, has_more))
+}
+
+// send a request and return the response body text
+fn send_request(rt: &Runtime, req: reqwest_middleware::RequestBuilder) -> FdwResult<String> {
+    let resp = rt
+        .block_on(req.send())
+        .map_err(|err| request_error(err, None))?;
+    let status = resp.status();
+    let resp = resp
+        .error_for_status()
+        .map_err(|err| request_error(err, Some(status)))?;
+    rt.block_on(resp.text())
+        .map_err(|err| request_error(err, Some(status)))
+}
+
+// fetch the response bodies of a scan, starting from the first page `url` and
+// following the pagination cursor until no more objects or `page_cnt` pages
+// are fetched, or the scan is cancelled
+//
+// this doesn't call any Postgres functions, so it can run in a background thread
+fn fetch_pages(
+    rt: &Runtime,
+    client: &ClientWithMiddleware,
+    url: &Url,
+    page_cnt: i64,
+    cancelled: &AtomicBool,
+    stats: &mut RemoteStats,
+) -> FdwResult<Vec<String>> {
+    let mut bodies = Vec::new();
+    let mut cursor: Option<String> = None;
 
-    (result, cursor, has_more)
+    while (bodies.len() as i64) < page_cnt && !cancelled.load(Ordering::Relaxed) {
+        let mut page_url = url.clone();
+        if let Some(ref cursor) = cursor {
+            page_url
+                .query_pairs_mut()
+                .append_pair("starting_after", cursor);
+        }
+
+        // make api call
+        let start = Instant::now();
+        let body = send_request(rt, client.get(page_url))?;
+        stats.requests += 1;
+        stats.bytes += body.len() as u64;
+        stats.latency += start.elapsed();
+
+        let (next_cursor, has_more) = page_cursor(&body)?;
+        bodies.push(body);
+        match next_cursor {
+            Some(next_cursor) if has_more => cursor = Some(next_cursor),
+            _ => break,
+        }
+    }
+
+    Ok(bodies)
 }
 
-fn row_to_body(row: &Row) -> JsonValue {
+// get number of pages to fetch for a scan
+fn page_count(limit: &Option<Limit>) -> i64 {
+    match limit {
+        Some(limit) if limit.count == 0 => 0,
+        Some(limit) => (limit.offset + limit.count) / PAGE_SIZE + 1,
+        // if no limit specified, fetch all records
+        None => i64::MAX,
+    }
+}
+
+// get source columns of a Stripe object, returns None if the object is not supported
+fn obj_columns(obj: &str) -> Option<Vec<(&'static str, &'static str)>> {
+    let cols = match obj {
+        "accounts" => vec![
+            ("id", "string"),
+            ("business_type", "string"),
+            ("country", "string"),
+            ("email", "string"),
+            ("type", "string"),
+            ("created", "timestamp"),
+        ],
+        "balance" => vec![
+            ("balance_type", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+        ],
+        "balance_transactions" => vec![
+            ("id", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("description", "string"),
+            ("fee", "i64"),
+            ("net", "i64"),
+            ("status", "string"),
+            ("type", "string"),
+            ("created", "timestamp"),
+        ],
+        "charges" => vec![
+            ("id", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("customer", "string"),
+            ("description", "string"),
+            ("invoice", "string"),
+            ("payment_intent", "string"),
+            ("status", "string"),
+            ("created", "timestamp"),
+        ],
+        "customers" => vec![
+            ("id", "string"),
+            ("email", "string"),
+            ("name", "string"),
+            ("description", "string"),
+            ("created", "timestamp"),
+        ],
+        "disputes" => vec![
+            ("id", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("charge", "string"),
+            ("payment_intent", "string"),
+            ("reason", "string"),
+            ("status", "string"),
+            ("created", "timestamp"),
+        ],
+        "events" => vec![
+            ("id", "string"),
+            ("type", "string"),
+            ("api_version", "string"),
+            ("created", "timestamp"),
+        ],
+        "files" => vec![
+            ("id", "string"),
+            ("filename", "string"),
+            ("purpose", "string"),
+            ("title", "string"),
+            ("size", "i64"),
+            ("type", "string"),
+            ("url", "string"),
+            ("created", "timestamp"),
+            ("expires_at", "timestamp"),
+        ],
+        "file_links" => vec![
+            ("id", "string"),
+            ("file", "string"),
+            ("url", "string"),
+            ("created", "timestamp"),
+            ("expired", "bool"),
+            ("expires_at", "timestamp"),
+        ],
+        "invoices" => vec![
+            ("id", "string"),
+            ("customer", "string"),
+            ("subscription", "string"),
+            ("status", "string"),
+            ("total", "i64"),
+            ("currency", "string"),
+            ("period_start", "timestamp"),
+            ("period_end", "timestamp"),
+        ],
+        "mandates" => vec![
+            ("id", "string"),
+            ("payment_method", "string"),
+            ("status", "string"),
+            ("type", "string"),
+        ],
+        "payment_intents" => vec![
+            ("id", "string"),
+            ("customer", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("payment_method", "string"),
+            ("created", "timestamp"),
+        ],
+        "payouts" => vec![
+            ("id", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("arrival_date", "timestamp"),
+            ("description", "string"),
+            ("statement_descriptor", "string"),
+            ("status", "string"),
+            ("created", "timestamp"),
+        ],
+        "products" => vec![
+            ("id", "string"),
+            ("name", "string"),
+            ("active", "bool"),
+            ("default_price", "string"),
+            ("description", "string"),
+            ("created", "timestamp"),
+            ("updated", "timestamp"),
+        ],
+        "refunds" => vec![
+            ("id", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("charge", "string"),
+            ("payment_intent", "string"),
+            ("reason", "string"),
+            ("status", "string"),
+            ("created", "timestamp"),
+        ],
+        "setup_attempts" => vec![
+            ("id", "string"),
+            ("application", "string"),
+            ("customer", "string"),
+            ("on_behalf_of", "string"),
+            ("payment_method", "string"),
+            ("setup_intent", "string"),
+            ("status", "string"),
+            ("usage", "string"),
+            ("created", "timestamp"),
+        ],
+        "setup_intents" => vec![
+            ("id", "string"),
+            ("client_secret", "string"),
+            ("customer", "string"),
+            ("description", "string"),
+            ("payment_method", "string"),
+            ("status", "string"),
+            ("usage", "string"),
+            ("created", "timestamp"),
+        ],
+        "subscriptions" => vec![
+            ("id", "string"),
+            ("customer", "string"),
+            ("currency", "string"),
+            ("current_period_start", "timestamp"),
+            ("current_period_end", "timestamp"),
+        ],
+        "tokens" => vec![
+            ("id", "string"),
+            ("type", "string"),
+            ("client_ip", "string"),
+            ("used", "bool"),
+            ("created", "timestamp"),
+        ],
+        "topups" => vec![
+            ("id", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("description", "string"),
+            ("status", "string"),
+            ("created", "timestamp"),
+        ],
+        "transfers" => vec![
+            ("id", "string"),
+            ("amount", "i64"),
+            ("currency", "string"),
+            ("description", "string"),
+            ("destination", "string"),
+            ("created", "timestamp"),
+        ],
+        _ => return None,
+    };
+    Some(cols)
+}
+
+// all the Stripe objects supported by this FDW
+const SUPPORTED_OBJECTS: &[&str] = &[
+    "accounts",
+    "balance",
+    "balance_transactions",
+    "charges",
+    "customers",
+    "disputes",
+    "events",
+    "files",
+    "file_links",
+    "invoices",
+    "mandates",
+    "payment_intents",
+    "payouts",
+    "products",
+    "refunds",
+    "setup_attempts",
+    "setup_intents",
+    "subscriptions",
+    "tokens",
+    "topups",
+    "transfers",
+];
+
+// Stripe objects which can be created, updated and deleted
+const MODIFIABLE_OBJECTS: &[&str] = &["customers", "products", "subscriptions"];
+
+fn row_to_body(row: &Row) -> FdwResult<JsonValue> {
     let mut map = JsonMap::new();
 
     for (col_name, cell) in row.iter() {
@@ -158,56 +468,94 @@ fn row_to_body(row: &Row) -> JsonValue {
                     }
                 }
                 _ => {
-                    report_error(
+                    return Err(FdwError::new(
                         PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
-                        &format!("field type {:?} not supported", cell),
-                    );
-                    return JsonValue::Null;
+                        format!("field type {:?} not supported", cell),
+                    ));
                 }
             }
         }
     }
 
-    JsonValue::Object(map)
+    Ok(JsonValue::Object(map))
+}
+
+// fields which can be filtered by the list API of an object, besides id
+// ref: https://stripe.com/docs/api/[object]/list
+fn pushdown_fields(obj: &str) -> Option<Vec<&'static str>> {
+    match obj {
+        "accounts" => Some(vec![]),
+        "balance" => Some(vec![]),
+        "balance_transactions" => Some(vec!["type"]),
+        "charges" => Some(vec!["customer"]),
+        "customers" => Some(vec!["email"]),
+        "disputes" => Some(vec!["charge", "payment_intent"]),
+        "events" => Some(vec!["type"]),
+        "files" => Some(vec!["purpose"]),
+        "file_links" => Some(vec![]),
+        "invoices" => Some(vec!["customer", "status", "subscription"]),
+        "mandates" => Some(vec![]),
+        "payment_intents" => Some(vec!["customer"]),
+        "payouts" => Some(vec!["status"]),
+        "products" => Some(vec!["active"]),
+        "refunds" => Some(vec!["charge", "payment_intent"]),
+        "setup_attempts" => Some(vec!["setup_intent"]),
+        "setup_intents" => Some(vec!["customer", "payment_method"]),
+        "subscriptions" => Some(vec!["customer", "price", "status"]),
+        "tokens" => Some(vec![]),
+        "topups" => Some(vec!["status"]),
+        "transfers" => Some(vec!["destination"]),
+        _ => None,
+    }
+}
+
+// check if a qual can be pushed down as a list API query parameter
+fn is_pushdown_qual(qual: &Qual, fields: &[&str]) -> bool {
+    fields.contains(&qual.field.as_str()) && qual.operator == "=" && !qual.use_or
+}
+
+// get the id of a single object lookup, i.e. `id = 'xxx'`
+fn object_id(qual: &Qual) -> Option<&String> {
+    if qual.field == "id" && qual.operator == "=" && !qual.use_or {
+        if let Value::Cell(Cell::String(id))
+        | Value::Param(Param {
+            value: Some(Cell::String(id)),
+            ..
+        }) = &qual.value
+        {
+            return Some(id);
+        }
+    }
+    None
 }
 
-fn pushdown_quals(
-    url: &mut Url,
-    obj: &str,
-    quals: &[Qual],
-    fields: Vec<&str>,
-    page_size: i64,
-    cursor: &Option<String>,
-) {
+fn pushdown_quals(url: &mut Url, obj: &str, quals: &[Qual], fields: Vec<&str>, page_size: i64) {
     // for scan with a single id query param, optimized to single object GET request
     if quals.len() == 1 {
-        let qual = &quals[0];
-        if qual.field == "id" && qual.operator == "=" && !qual.use_or {
-            if let Value::Cell(Cell::String(id)) = &qual.value {
-                let new_path = format!("{}/{}", url.path(), id);
-                url.set_path(&new_path);
-                url.set_query(None);
-                return;
-            }
+        if let Some(id) = object_id(&quals[0]) {
+            let new_path = format!("{}/{}", url.path(), id);
+            url.set_path(&new_path);
+            url.set_query(None);
+            return;
         }
     }
 
     // pushdown quals
-    for qual in quals {
-        for field in &fields {
-            if qual.field == *field && qual.operator == "=" && !qual.use_or {
-                if let Value::Cell(cell) = &qual.value {
-                    match cell {
-                        Cell::Bool(b) => {
-                            url.query_pairs_mut()
-                                .append_pair(field, b.to_string().as_str());
-                        }
-                        Cell::String(s) => {
-                            url.query_pairs_mut().append_pair(field, s);
-                        }
-                        _ => {}
-                    }
+    for qual in quals.iter().filter(|q| is_pushdown_qual(q, &fields)) {
+        if let Value::Cell(cell)
+        | Value::Param(Param {
+            value: Some(cell), ..
+        }) = &qual.value
+        {
+            match cell {
+                Cell::Bool(b) => {
+                    url.query_pairs_mut()
+                        .append_pair(&qual.field, b.to_string().as_str());
                 }
+                Cell::String(s) => {
+                    url.query_pairs_mut().append_pair(&qual.field, s);
+                }
+                _ => {}
             }
         }
     }
@@ -216,20 +564,29 @@ fn pushdown_quals(
     if obj != "balance" {
         url.query_pairs_mut()
             .append_pair("limit", &format!("{}", page_size));
-        if let Some(ref cursor) = cursor {
-            url.query_pairs_mut().append_pair("starting_after", cursor);
-        }
     }
 }
 
-macro_rules! report_request_error {
-    ($err:ident) => {{
-        report_error(
-            PgSqlErrorCode::ERRCODE_FDW_ERROR,
-            &format!("request failed: {}", $err),
-        );
-        return;
-    }};
+// maximum page size limit for Stripe API
+const PAGE_SIZE: i64 = 100;
+
+// a scan running in a background thread, its response bodies are converted
+// to rows in the main thread when it is finished
+struct AsyncScan {
+    obj: String,
+    columns: Vec<String>,
+    handle: JoinHandle<FdwResult<(Vec<String>, RemoteStats)>>,
+
+    // set to stop the thread fetching more pages
+    cancelled: Arc<AtomicBool>,
+}
+
+impl AsyncScan {
+    // stop the thread at the next page, e.g. the query has ended before the
+    // scan is finished
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
 }
 
 #[wrappers_fdw(
@@ -240,345 +597,88 @@ macro_rules! report_request_error {
 pub(crate) struct StripeFdw {
     rt: Runtime,
     base_url: Url,
+    api_key: Option<String>,
     client: Option<ClientWithMiddleware>,
+    async_scan: Option<AsyncScan>,
+    scan_url: Option<Url>,
     scan_result: Option<Vec<Row>>,
     obj: String,
     rowid_col: String,
+    stats: RemoteStats,
 }
 
 impl StripeFdw {
-    fn build_url(
-        &self,
-        obj: &str,
-        quals: &[Qual],
-        page_size: i64,
-        cursor: &Option<String>,
-    ) -> Option<Url> {
-        let mut url = self.base_url.join(obj).unwrap();
-
-        // pushdown quals other than id
-        // ref: https://stripe.com/docs/api/[object]/list
-        let fields = match obj {
-            "accounts" => vec![],
-            "balance" => vec![],
-            "balance_transactions" => vec!["type"],
-            "charges" => vec!["customer"],
-            "customers" => vec!["email"],
-            "disputes" => vec!["charge", "payment_intent"],
-            "events" => vec!["type"],
-            "files" => vec!["purpose"],
-            "file_links" => vec![],
-            "invoices" => vec!["customer", "status", "subscription"],
-            "mandates" => vec![],
-            "payment_intents" => vec!["customer"],
-            "payouts" => vec!["status"],
-            "products" => vec!["active"],
-            "refunds" => vec!["charge", "payment_intent"],
-            "setup_attempts" => vec!["setup_intent"],
-            "setup_intents" => vec!["customer", "payment_method"],
-            "subscriptions" => vec!["customer", "price", "status"],
-            "tokens" => vec![],
-            "topups" => vec!["status"],
-            "transfers" => vec!["destination"],
-            _ => {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_TABLE_NOT_FOUND,
-                    &format!("'{}' object is not implemented", obj),
-                );
-                return None;
-            }
-        };
-        pushdown_quals(&mut url, obj, quals, fields, page_size, cursor);
+    fn build_url(&self, obj: &str, quals: &[Qual], page_size: i64) -> FdwResult<Url> {
+        let mut url = self
+            .base_url
+            .join(obj)
+            .map_err(|err| format!("invalid url: {}", err))?;
+
+        let fields = pushdown_fields(obj).ok_or_else(|| object_not_found(obj))?;
+        pushdown_quals(&mut url, obj, quals, fields, page_size);
+
+        Ok(url)
+    }
+
+    // build url of a single object
+    fn object_url(&self, rowid: &Cell) -> FdwResult<Url> {
+        match rowid {
+            Cell::String(rowid) => self
+                .base_url
+                .join(&format!("{}/", self.obj))
+                .and_then(|url| url.join(rowid))
+                .map_err(|err| format!("invalid url: {}", err).into()),
+            _ => Err(FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                format!("rowid column must be text, got {:?}", rowid),
+            )),
+        }
+    }
+
+    // report the modified object id and convert the response body to a row
+    fn modify_resp_to_row(&self, action: &str, resp_body: &str) -> FdwResult<Option<Row>> {
+        let json = parse_body(resp_body)?;
+        if let Some(id) = json.get("id").and_then(|v| v.as_str()) {
+            report_info(&format!("{} {} {}", action, self.obj, id));
+        }
+        self.resp_to_row(resp_body)
+    }
 
-        Some(url)
+    // convert the object returned by a modify request to a row with all the
+    // object columns
+    fn resp_to_row(&self, resp_body: &str) -> FdwResult<Option<Row>> {
+        let normal_cols = obj_columns(&self.obj).ok_or_else(|| object_not_found(&self.obj))?;
+        let mut tgt_cols: Vec<String> = normal_cols.iter().map(|(c, _)| c.to_string()).collect();
+        tgt_cols.push("attrs".to_string());
+        let mut rows = body_to_rows(resp_body, normal_cols, &tgt_cols)?;
+        Ok(rows.pop())
     }
 
-    // convert response body text to rows
-    fn resp_to_rows(
+    // convert response bodies of a scan to rows
+    fn bodies_to_rows(
         &self,
         obj: &str,
-        resp_body: &str,
+        bodies: &[String],
         tgt_cols: &[String],
-    ) -> (Vec<Row>, Option<String>, Option<bool>) {
-        match obj {
-            "accounts" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("business_type", "string"),
-                    ("country", "string"),
-                    ("email", "string"),
-                    ("type", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "balance" => body_to_rows(
-                resp_body,
-                vec![
-                    ("balance_type", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                ],
-                tgt_cols,
-            ),
-            "balance_transactions" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("description", "string"),
-                    ("fee", "i64"),
-                    ("net", "i64"),
-                    ("status", "string"),
-                    ("type", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "charges" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("customer", "string"),
-                    ("description", "string"),
-                    ("invoice", "string"),
-                    ("payment_intent", "string"),
-                    ("status", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "customers" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("email", "string"),
-                    ("name", "string"),
-                    ("description", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "disputes" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("charge", "string"),
-                    ("payment_intent", "string"),
-                    ("reason", "string"),
-                    ("status", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "events" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("type", "string"),
-                    ("api_version", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "files" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("filename", "string"),
-                    ("purpose", "string"),
-                    ("title", "string"),
-                    ("size", "i64"),
-                    ("type", "string"),
-                    ("url", "string"),
-                    ("created", "timestamp"),
-                    ("expires_at", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "file_links" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("file", "string"),
-                    ("url", "string"),
-                    ("created", "timestamp"),
-                    ("expired", "bool"),
-                    ("expires_at", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "invoices" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("customer", "string"),
-                    ("subscription", "string"),
-                    ("status", "string"),
-                    ("total", "i64"),
-                    ("currency", "string"),
-                    ("period_start", "timestamp"),
-                    ("period_end", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "mandates" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("payment_method", "string"),
-                    ("status", "string"),
-                    ("type", "string"),
-                ],
-                tgt_cols,
-            ),
-            "payment_intents" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("customer", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("payment_method", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "payouts" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("arrival_date", "timestamp"),
-                    ("description", "string"),
-                    ("statement_descriptor", "string"),
-                    ("status", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "products" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("name", "string"),
-                    ("active", "bool"),
-                    ("default_price", "string"),
-                    ("description", "string"),
-                    ("created", "timestamp"),
-                    ("updated", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "refunds" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("charge", "string"),
-                    ("payment_intent", "string"),
-                    ("reason", "string"),
-                    ("status", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "setup_attempts" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("application", "string"),
-                    ("customer", "string"),
-                    ("on_behalf_of", "string"),
-                    ("payment_method", "string"),
-                    ("setup_intent", "string"),
-                    ("status", "string"),
-                    ("usage", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "setup_intents" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("client_secret", "string"),
-                    ("customer", "string"),
-                    ("description", "string"),
-                    ("payment_method", "string"),
-                    ("status", "string"),
-                    ("usage", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "subscriptions" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("customer", "string"),
-                    ("currency", "string"),
-                    ("current_period_start", "timestamp"),
-                    ("current_period_end", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "tokens" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("type", "string"),
-                    ("client_ip", "string"),
-                    ("used", "bool"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "topups" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("description", "string"),
-                    ("status", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            "transfers" => body_to_rows(
-                resp_body,
-                vec![
-                    ("id", "string"),
-                    ("amount", "i64"),
-                    ("currency", "string"),
-                    ("description", "string"),
-                    ("destination", "string"),
-                    ("created", "timestamp"),
-                ],
-                tgt_cols,
-            ),
-            _ => {
-                report_error(
-                    PgSqlErrorCode::ERRCODE_FDW_TABLE_NOT_FOUND,
-                    &format!("'{}' object is not implemented", obj),
-                );
-                (Vec::new(), None, None)
-            }
+    ) -> FdwResult<Vec<Row>> {
+        let normal_cols = obj_columns(obj).ok_or_else(|| object_not_found(obj))?;
+        let mut result = Vec::new();
+        for body in bodies {
+            result.extend(body_to_rows(body, normal_cols.clone(), tgt_cols)?);
         }
+        Ok(result)
     }
 }
 
+fn object_not_found(obj: &str) -> FdwError {
+    FdwError::new(
+        PgSqlErrorCode::ERRCODE_FDW_TABLE_NOT_FOUND,
+        format!("'{}' object is not implemented", obj),
+    )
+}
+
 impl ForeignDataWrapper for StripeFdw {
-    fn new(options: &HashMap<String, String>) -> Self {
+    fn new(options: &HashMap<String, String>) -> FdwResult<Self> {
         let base_url = options
             .get("api_url")
             .map(|t| t.to_owned())
@@ -592,21 +692,87 @@ impl ForeignDataWrapper for StripeFdw {
                 }
             })
             .unwrap_or_else(|| "https://api.stripe.com/v1/".to_string());
-        let client = match options.get("api_key") {
-            Some(api_key) => Some(create_client(api_key)),
-            None => require_option("api_key_id", options)
-                .and_then(|key_id| get_vault_secret(&key_id))
-                .map(|api_key| create_client(&api_key)),
+        let api_key = match options.get("api_key") {
+            Some(api_key) => Some(api_key.to_owned()),
+            None => {
+                let key_id = require_option("api_key_id", options)?;
+                get_vault_secret(&key_id)
+            }
         };
-
-        StripeFdw {
+        let client = match &api_key {
+            Some(api_key) => Some(create_client(api_key)?),
+            None => None,
+        };
+        let base_url = Url::parse(&base_url).map_err(|err| {
+            FdwError::new(
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_ATTRIBUTE_VALUE,
+                format!("invalid api_url: {}", err),
+            )
+        })?;
+
+        Ok(StripeFdw {
             rt: create_async_runtime(),
-            base_url: Url::parse(&base_url).unwrap(),
+            base_url,
+            api_key,
             client,
+            async_scan: None,
+            scan_url: None,
             scan_result: None,
             obj: String::default(),
             rowid_col: String::default(),
-        }
+            stats: RemoteStats::default(),
+        })
+    }
+
+    fn classify_quals(
+        &mut self,
+        quals: &[Qual],
+        options: &HashMap<String, String>,
+    ) -> Vec<QualPushdown> {
+        let obj = options
+            .get("object")
+            .map(|s| s.as_str())
+            .unwrap_or_default();
+        let fields = pushdown_fields(obj).unwrap_or_default();
+        quals
+            .iter()
+            .map(|qual| {
+                // a single id lookup is sent as an object GET request, but it
+                // is still checked locally, because the join conditions of a
+                // parameterized scan may be added to the quals when the scan
+                // begins. The parameter values are unknown during planning,
+                // so their types cannot be checked either.
+                let is_remote = is_pushdown_qual(qual, &fields)
+                    && match &qual.value {
+                        Value::Cell(Cell::Bool(_)) => true,
+                        // the event type filter accepts wildcards, e.g. 'invoice.*'
+                        Value::Cell(Cell::String(s)) => {
+                            !(obj == "events" && qual.field == "type" && s.contains('*'))
+                        }
+                        _ => false,
+                    };
+                if is_remote {
+                    QualPushdown::Remote
+                } else {
+                    QualPushdown::Local
+                }
+            })
+            .collect()
+    }
+
+    fn get_rel_size(
+        &mut self,
+        quals: &[Qual],
+        _columns: &[String],
+        _sorts: &[Sort],
+        _limit: &Option<Limit>,
+        options: &HashMap<String, String>,
+    ) -> FdwResult<ScanEstimate> {
+        // the request is not sent until the scan begins, build its url here
+        // so it can be shown by EXPLAIN
+        let obj = require_option("object", options)?;
+        self.scan_url = Some(self.build_url(&obj, quals, PAGE_SIZE)?);
+        Ok(ScanEstimate::default())
     }
 
     fn begin_scan(
@@ -616,188 +782,208 @@ impl ForeignDataWrapper for StripeFdw {
         _sorts: &[Sort],
         limit: &Option<Limit>,
         options: &HashMap<String, String>,
-    ) {
-        let obj = if let Some(name) = require_option("object", options) {
-            name
-        } else {
-            return;
-        };
+    ) -> FdwResult<()> {
+        let obj = require_option("object", options)?;
 
         if let Some(client) = &self.client {
-            let page_size = 100; // maximum page size limit for Stripe API
-            let page_cnt = if let Some(limit) = limit {
-                if limit.count == 0 {
-                    return;
-                }
-                (limit.offset + limit.count) / page_size + 1
-            } else {
-                // if no limit specified, fetch all records
-                i64::MAX
-            };
-            let mut page = 0;
-            let mut result = Vec::new();
-            let mut cursor: Option<String> = None;
-
-            while page < page_cnt {
-                // build url
-                let url = self.build_url(&obj, quals, page_size, &cursor);
-                if url.is_none() {
-                    return;
-                }
-                let url = url.unwrap();
-
-                // make api call
-                match self.rt.block_on(client.get(url).send()) {
-                    Ok(resp) => match resp.error_for_status() {
-                        Ok(resp) => {
-                            let body = self.rt.block_on(resp.text()).unwrap();
-                            let (rows, starting_after, has_more) =
-                                self.resp_to_rows(&obj, &body, columns);
-                            if rows.is_empty() {
-                                break;
-                            }
-                            result.extend(rows);
-                            match has_more {
-                                Some(has_more) => {
-                                    if !has_more {
-                                        break;
-                                    }
-                                }
-                                None => break,
-                            }
-                            cursor = starting_after;
-                        }
-                        Err(err) => report_request_error!(err),
-                    },
-                    Err(err) => report_request_error!(err),
-                }
+            let url = self.build_url(&obj, quals, PAGE_SIZE)?;
+            self.scan_url = Some(url.clone());
+            let bodies = fetch_pages(
+                &self.rt,
+                client,
+                &url,
+                page_count(limit),
+                &AtomicBool::new(false),
+                &mut self.stats,
+            )?;
+            self.scan_result = Some(self.bodies_to_rows(&obj, &bodies, columns)?);
+        }
+        Ok(())
+    }
 
-                page += 1;
-            }
+    fn begin_async_scan(
+        &mut self,
+        quals: &[Qual],
+        columns: &[String],
+        _sorts: &[Sort],
+        limit: &Option<Limit>,
+        options: &HashMap<String, String>,
+        notifier: AsyncNotifier,
+    ) -> FdwResult<bool> {
+        let api_key = match &self.api_key {
+            Some(api_key) => api_key.clone(),
+            None => return Ok(false),
+        };
+        let obj = require_option("object", options)?;
+        let url = self.build_url(&obj, quals, PAGE_SIZE)?;
+        self.scan_url = Some(url.clone());
+        let page_cnt = page_count(limit);
+
+        // the background thread sends requests using its own runtime and
+        // client, as they cannot be shared across threads
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let thread_cancelled = cancelled.clone();
+        let handle = thread::spawn(move || {
+            let rt = create_async_runtime();
+            let mut stats = RemoteStats::default();
+            let result = create_client(&api_key).and_then(|client| {
+                fetch_pages(&rt, &client, &url, page_cnt, &thread_cancelled, &mut stats)
+            });
+            notifier.notify();
+            result.map(|bodies| (bodies, stats))
+        });
+        self.async_scan = Some(AsyncScan {
+            obj,
+            columns: columns.to_vec(),
+            handle,
+            cancelled,
+        });
+        Ok(true)
+    }
 
-            self.scan_result = Some(result);
+    fn iter_scan(&mut self, row: &mut Row) -> FdwResult<Option<()>> {
+        // collect the result of asynchronous scan, the thread should have
+        // finished when it is notified
+        if let Some(scan) = self.async_scan.take() {
+            let (bodies, stats) = scan
+                .handle
+                .join()
+                .map_err(|_| "asynchronous scan thread panicked")??;
+            self.stats.requests += stats.requests;
+            self.stats.bytes += stats.bytes;
+            self.stats.latency += stats.latency;
+            self.scan_result = Some(self.bodies_to_rows(&scan.obj, &bodies, &scan.columns)?);
         }
-    }
 
-    fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
         if let Some(ref mut result) = self.scan_result {
             if !result.is_empty() {
-                return result
+                return Ok(result
                     .drain(0..1)
                     .last()
-                    .map(|src_row| row.replace_with(src_row));
+                    .map(|src_row| row.replace_with(src_row)));
             }
         }
-        None
+        Ok(None)
     }
 
-    fn end_scan(&mut self) {
+    fn end_scan(&mut self) -> FdwResult<()> {
+        // an unfinished asynchronous scan thread is cancelled and detached
+        if let Some(scan) = self.async_scan.take() {
+            scan.cancel();
+        }
         self.scan_result.take();
+        Ok(())
     }
 
-    fn begin_modify(&mut self, options: &HashMap<String, String>) {
-        self.obj = require_option("object", options).unwrap_or_default();
-        self.rowid_col = require_option("rowid_column", options).unwrap_or_default();
+    fn explain(&self, verbose: bool) -> Vec<(String, String)> {
+        match &self.scan_url {
+            Some(url) if verbose => vec![("Remote URL".to_string(), url.to_string())],
+            _ => Vec::new(),
+        }
     }
 
-    fn insert(&mut self, src: &Row) {
-        if let Some(ref mut client) = self.client {
-            let url = self.base_url.join(&self.obj).unwrap();
-            let body = row_to_body(src);
-            if body.is_null() {
-                return;
-            }
+    fn remote_stats(&self) -> Option<RemoteStats> {
+        Some(self.stats.clone())
+    }
+
+    fn begin_modify(&mut self, options: &HashMap<String, String>) -> FdwResult<()> {
+        self.obj = require_option("object", options)?;
+        self.rowid_col = require_option("rowid_column", options)?;
+        Ok(())
+    }
+
+    fn insert(&mut self, src: &Row) -> FdwResult<Option<Row>> {
+        if let Some(ref client) = self.client {
+            let url = self
+                .base_url
+                .join(&self.obj)
+                .map_err(|err| format!("invalid url: {}", err))?;
+            let body = row_to_body(src)?;
 
             // call Stripe API
-            match self.rt.block_on(client.post(url).form(&body).send()) {
-                Ok(resp) => match resp.error_for_status() {
-                    Ok(resp) => {
-                        let body = self.rt.block_on(resp.text()).unwrap();
-                        let json: JsonValue = serde_json::from_str(&body).unwrap();
-                        if let Some(id) = json.get("id").and_then(|v| v.as_str()) {
-                            report_info(&format!("inserted {} {}", self.obj, id));
-                        }
-                    }
-                    Err(err) => report_request_error!(err),
-                },
-                Err(err) => report_request_error!(err),
-            }
+            let resp_body = send_request(&self.rt, client.post(url).form(&body))?;
+            return self.modify_resp_to_row("inserted", &resp_body);
         }
+        Ok(None)
     }
 
-    fn update(&mut self, rowid: &Cell, new_row: &Row) {
-        if let Some(ref mut client) = self.client {
-            match rowid {
-                Cell::String(rowid) => {
-                    let url = self
-                        .base_url
-                        .join(&format!("{}/", self.obj))
-                        .unwrap()
-                        .join(rowid)
-                        .unwrap();
-                    let body = row_to_body(new_row);
-                    if body.is_null() {
-                        return;
-                    }
+    fn update(&mut self, rowid: &Cell, new_row: &Row) -> FdwResult<Option<Row>> {
+        if let Some(ref client) = self.client {
+            let url = self.object_url(rowid)?;
+            let body = row_to_body(new_row)?;
 
-                    // call Stripe API
-                    match self.rt.block_on(client.post(url).form(&body).send()) {
-                        Ok(resp) => match resp.error_for_status() {
-                            Ok(resp) => {
-                                let body = self.rt.block_on(resp.text()).unwrap();
-                                let json: JsonValue = serde_json::from_str(&body).unwrap();
-                                if let Some(id) = json.get("id").and_then(|v| v.as_str()) {
-                                    report_info(&format!("updated {} {}", self.obj, id));
-                                }
-                            }
-                            Err(err) => report_request_error!(err),
-                        },
-                        Err(err) => report_request_error!(err),
-                    }
-                }
-                _ => unreachable!(),
-            }
+            // call Stripe API
+            let resp_body = send_request(&self.rt, client.post(url).form(&body))?;
+            return self.modify_resp_to_row("updated", &resp_body);
         }
+        Ok(None)
     }
 
-    fn delete(&mut self, rowid: &Cell) {
-        if let Some(ref mut client) = self.client {
-            match rowid {
-                Cell::String(rowid) => {
-                    let url = self
-                        .base_url
-                        .join(&format!("{}/", self.obj))
-                        .unwrap()
-                        .join(rowid)
-                        .unwrap();
-
-                    // call Stripe API
-                    match self.rt.block_on(client.delete(url).send()) {
-                        Ok(resp) => match resp.error_for_status() {
-                            Ok(resp) => {
-                                let body = self.rt.block_on(resp.text()).unwrap();
-                                let json: JsonValue = serde_json::from_str(&body).unwrap();
-                                if let Some(id) = json.get("id").and_then(|v| v.as_str()) {
-                                    report_info(&format!("deleted {} {}", self.obj, id));
-                                }
-                            }
-                            Err(err) => report_request_error!(err),
-                        },
-                        Err(err) => report_request_error!(err),
-                    }
-                }
-                _ => unreachable!(),
-            }
+    fn delete(&mut self, rowid: &Cell) -> FdwResult<Option<Row>> {
+        if let Some(ref client) = self.client {
+            let url = self.object_url(rowid)?;
+
+            // call Stripe API
+            let resp_body = send_request(&self.rt, client.delete(url))?;
+            return self.modify_resp_to_row("deleted", &resp_body);
         }
+        Ok(None)
     }
 
-    fn end_modify(&mut self) {}
+    fn import_foreign_schema(&mut self, stmt: &ImportForeignSchemaStmt) -> FdwResult<Vec<String>> {
+        let stmts = SUPPORTED_OBJECTS
+            .iter()
+            .filter(|obj| stmt.is_importable(obj))
+            .map(|obj| {
+                let mut cols: Vec<String> = obj_columns(obj)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|(col_name, col_type)| {
+                        let pg_type = match *col_type {
+                            "bool" => "boolean",
+                            "i64" => "bigint",
+                            "timestamp" => "timestamp",
+                            _ => "text",
+                        };
+                        format!("{} {}", col_name, pg_type)
+                    })
+                    .collect();
+                cols.push("attrs jsonb".to_string());
+
+                let mut opts = vec![format!("object '{}'", obj)];
+                if MODIFIABLE_OBJECTS.contains(obj) {
+                    opts.push("rowid_column 'id'".to_string());
+                }
+
+                format!(
+                    r#"create foreign table if not exists "{}"."{}" ({}) server "{}" options ({})"#,
+                    stmt.local_schema,
+                    obj,
+                    cols.join(", "),
+                    stmt.server_name,
+                    opts.join(", ")
+                )
+            })
+            .collect();
+        Ok(stmts)
+    }
 
-    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) {
+    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) -> FdwResult<()> {
         if let Some(oid) = catalog {
-            if oid == FOREIGN_TABLE_RELATION_ID {
-                check_options_contain(&options, "object");
+            match oid {
+                FOREIGN_SERVER_RELATION_ID => {
+                    check_options_bool(&options, "async_capable")?;
+                }
+                FOREIGN_TABLE_RELATION_ID => {
+                    check_options_contain(&options, "object")?;
+                    check_options_bool(&options, "async_capable")?;
+                }
+                USER_MAPPING_RELATION_ID => {
+                    check_options_allowed(&options, &["api_key", "api_key_id"])?;
+                }
+                _ => {}
             }
         }
+        Ok(())
     }
 }
@@ -532,6 +532,51 @@ mod tests {
                 vec![("evt_1Lb4lfDciZwYG8GPHARl3JTf", "plan.created")]
             );
 
+            // the event type wildcard is rechecked locally
+            assert_eq!(
+                c.select(
+                    "SELECT count(*) FROM stripe_events WHERE type = 'plan.*'",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                0
+            );
+
+            // the object id lookup is rechecked locally, also in a
+            // parameterized scan where a join condition is added
+            assert_eq!(
+                c.select(
+                    "SELECT count(*) FROM stripe_customers WHERE id = 'cus_unknown'",
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                0
+            );
+            c.update("SET enable_hashjoin = off", None, None);
+            c.update("SET enable_mergejoin = off", None, None);
+            assert_eq!(
+                c.select(
+                    r#"SELECT count(*)
+                       FROM (VALUES ('cus_other')) v(id)
+                       JOIN stripe_customers c
+                         ON c.id <> v.id AND c.id = 'cus_unknown'"#,
+                    None,
+                    None
+                )
+                .first()
+                .get_one::<i64>()
+                .unwrap(),
+                0
+            );
+            c.update("RESET enable_hashjoin", None, None);
+            c.update("RESET enable_mergejoin", None, None);
+
             let results = c
                 .select("SELECT * FROM stripe_files", None, None)
                 .filter_map(|r| {
@@ -758,6 +803,92 @@ mod tests {
                 )]
             );
 
+            // test import foreign schema
+            c.update("CREATE SCHEMA stripe_imported", None, None);
+            c.update(
+                r#"IMPORT FOREIGN SCHEMA stripe LIMIT TO (customers)
+                     FROM SERVER my_stripe_server INTO stripe_imported"#,
+                None,
+                None,
+            );
+            let results = c
+                .select("SELECT id FROM stripe_imported.customers", None, None)
+                .filter_map(|r| r.by_name("id").ok().and_then(|v| v.value::<&str>()))
+                .collect::<Vec<_>>();
+            assert_eq!(results, vec!["cus_MJiBgSUgeWFN0z"]);
+
+            // the scans under Append are executed asynchronously
+            c.update(
+                "ALTER SERVER my_stripe_server OPTIONS (ADD async_capable 'true')",
+                None,
+                None,
+            );
+            let results = c
+                .select(
+                    r#"SELECT id FROM stripe_customers
+                       UNION ALL
+                       SELECT id FROM stripe_imported.customers"#,
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("id").ok().and_then(|v| v.value::<&str>()))
+                .collect::<Vec<_>>();
+            assert_eq!(results, vec!["cus_MJiBgSUgeWFN0z", "cus_MJiBgSUgeWFN0z"]);
+            c.update(
+                "ALTER SERVER my_stripe_server OPTIONS (DROP async_capable)",
+                None,
+                None,
+            );
+
+            // the api key can be defined in user mapping
+            c.update(
+                r#"CREATE SERVER my_stripe_server_um
+                         FOREIGN DATA WRAPPER stripe_wrapper
+                         OPTIONS (
+                           api_url 'http://localhost:12111/v1'
+                         )"#,
+                None,
+                None,
+            );
+            c.update(
+                r#"CREATE USER MAPPING FOR CURRENT_USER
+                         SERVER my_stripe_server_um
+                         OPTIONS (
+                           api_key 'sk_test_51LUmojFkiV6mfx3cpEzG9VaxhA86SA4DIj3b62RKHnRC0nhPp2JBbAmQ1izsX9RKD8rlzvw2xpY54AwZtXmWciif00Qi8J0w3O'
+                         )"#,
+                None,
+                None,
+            );
+            c.update(
+                r#"CREATE FOREIGN TABLE stripe_customers_um (id text)
+                     SERVER my_stripe_server_um
+                     OPTIONS (object 'customers')"#,
+                None,
+                None,
+            );
+            let results = c
+                .select("SELECT id FROM stripe_customers_um", None, None)
+                .filter_map(|r| r.by_name("id").ok().and_then(|v| v.value::<&str>()))
+                .collect::<Vec<_>>();
+            assert_eq!(results, vec!["cus_MJiBgSUgeWFN0z"]);
+
+            // the created object is returned by Stripe mock, although it is
+            // not saved
+            let results = c
+                .update(
+                    r#"
+                    INSERT INTO stripe_customers(email, name)
+                    VALUES ('test@test.com', 'test name')
+                    RETURNING id
+                    "#,
+                    None,
+                    None,
+                )
+                .filter_map(|r| r.by_name("id").ok().and_then(|v| v.value::<&str>()))
+                .collect::<Vec<_>>();
+            assert_eq!(results.len(), 1);
+            assert!(results[0].starts_with("cus_"));
+
             // Stripe mock container is currently stateless, so we cannot test
             // data modify for now but will keep the code below for future use.
             //
@@ -1,9 +1,17 @@
 use pgx::pg_module_magic;
+use pgx::prelude::*;
 
 pg_module_magic!();
 
 mod fdw;
 
+/// Drop the cached wrapper instances and connections of a foreign server in
+/// current session, returns true if any of them is found
+#[pg_extern]
+fn wrappers_disconnect(server: &str) -> bool {
+    supabase_wrappers::utils::disconnect_server(server)
+}
+
 #[cfg(test)]
 pub mod pg_test {
     pub fn setup(_options: Vec<&str>) {